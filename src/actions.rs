@@ -1,10 +1,40 @@
+use chrono::Local;
 use once_cell::sync::Lazy;
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsStr,
+    fs::{self, canonicalize},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::Instant,
+};
 
 use crate::{
-    directory_tree::{run_command_in_foreground, FileTreeNode},
-    helper_types::{AppSettings, MarkType, TrackedModifiable},
-    modes::{delete_mode::delete_file_tree_node, Mode, OverlayMode, SimpleMode, TextInput},
+    archive::{detect_archive_format, extract_archive, get_extraction_dir_name, ArchiveJob},
+    compile_time_settings::{
+        DEFAULT_BACKUP_NAME_FORMAT, DEFAULT_CONFIG, DEFAULT_OS_OPEN_COMMAND, MAX_YANKED_FILE_SIZE,
+        MULTIPLEXERS, UNNAMED_REGISTER,
+    },
+    directory_tree::{
+        find_git_root, format_size, get_common_prefix, get_file_cursor_index, get_path_completions,
+        resolve_path, run_command_in_foreground, DiskUsage, FileHashing, FileTreeNode,
+    },
+    filesystem::Filesystem,
+    git::{discover_repository, stage_paths, unstage_paths},
+    helper_types::{
+        AppSettings, ArchiveFormat, LineNumbers, MarkType, PathCompletion, Register,
+        TrackedModifiable, ViewMode,
+    },
+    modes::{
+        substitute_mode::{
+            find_conflict, find_duplicate, find_existing, get_edited_names, rename_all,
+        },
+        Mode, OverlayMode, SimpleMode, TextInput,
+    },
+    util::{get_backup_name, PrivateTempDir},
     AppState, CustomTerminal,
 };
 
@@ -19,6 +49,8 @@ pub struct ActionData<'a> {
     pub app_state: &'a mut TrackedModifiable<AppState>,
     pub modifier: Option<usize>,
     pub dir_items: &'a Vec<FileTreeNode>,
+    // the changes to the files go through this instead of `std::fs`
+    pub filesystem: &'a mut dyn Filesystem,
 }
 
 impl<'a> ActionData<'a> {
@@ -28,6 +60,7 @@ impl<'a> ActionData<'a> {
         app_state: &'a mut TrackedModifiable<AppState>,
         modifier: Option<usize>,
         dir_items: &'a Vec<FileTreeNode>,
+        filesystem: &'a mut dyn Filesystem,
     ) -> Self {
         ActionData {
             config,
@@ -35,6 +68,7 @@ impl<'a> ActionData<'a> {
             app_state,
             modifier,
             dir_items,
+            filesystem,
         }
     }
 }
@@ -65,6 +99,27 @@ impl ActionMapper {
         dynamic_map.insert(name, closure);
         ActionMapper::StaticActionMapWithCallback(&TEXT_MODE_ACTION_MAP, dynamic_map)
     }
+    /// Adds another action, which can also replace a global one, e.g. a prompt that cancels in its own way
+    pub fn with_action(mut self, name: String, closure: ActionClosure) -> Self {
+        if let ActionMapper::StaticActionMapWithCallback(_, dynamic_map) = &mut self {
+            dynamic_map.insert(name, closure);
+        }
+        self
+    }
+}
+
+/// Wraps an action that changes files so that it is refused in read-only mode
+fn mutating_action<F>(action: F) -> ActionClosure
+where
+    F: Fn(ActionData) -> ActionResult + Sync + Send + 'static,
+{
+    Box::new(move |v| {
+        if v.app_state.is_read_only {
+            ActionResult::Invalid(String::from("Read-only mode"))
+        } else {
+            action(v)
+        }
+    })
 }
 
 pub(crate) static GLOBAL_ACTION_MAP: Lazy<ActionNameMap> = Lazy::new(|| {
@@ -76,6 +131,19 @@ pub(crate) static GLOBAL_ACTION_MAP: Lazy<ActionNameMap> = Lazy::new(|| {
             ActionResult::Valid
         }),
     );
+    m.insert(
+        String::from("cd_and_quit"),
+        Box::new(|v| {
+            if v.app_state.last_dir_path.is_none() {
+                return ActionResult::Invalid(String::from(
+                    "Start fphile with --last-dir-path to change the directory of the shell",
+                ));
+            }
+            v.app_state.get_mut().is_cd_requested = true;
+            v.app_state.get_mut().mode = Mode::SimpleMode(SimpleMode::Quitting);
+            ActionResult::Valid
+        }),
+    );
     m.insert(
         String::from("normal_mode"),
         Box::new(|v| {
@@ -142,204 +210,2493 @@ pub(crate) static NORMAL_MODE_ACTION_MAP: Lazy<ActionNameMap> = Lazy::new(|| {
         String::from("left"),
         Box::new(|v| {
             let current_path = v.app_state.current_dir.get_path_buf();
-            let next_path = current_path.parent().unwrap_or(&current_path);
+            let next_path = current_path.parent().unwrap_or(current_path);
             let new_dir = next_path.to_path_buf();
-            v.app_state.get_mut().current_dir = FileTreeNode::new(new_dir);
+            v.app_state.get_mut().change_dir(FileTreeNode::new(new_dir));
             ActionResult::Valid
         }),
     );
     m.insert(
-        String::from("right"),
+        String::from("repeat_last_action"),
         Box::new(|v| {
-            let selected_file_tree_node = &v.app_state.selected_file;
-            if let Some(selected_file_tree_node) = selected_file_tree_node {
-                if selected_file_tree_node.is_dir() {
-                    // open the directory
-                    v.app_state.get_mut().current_dir = selected_file_tree_node.clone();
-                    ActionResult::Valid
-                } else {
-                    if let Some(file_editor_options) = &v.config.default_file_editor_command{
-                        let file_name = selected_file_tree_node.get_simple_name();
-                        let options = file_editor_options.iter().map(|option|{
-                            option.replace("<FILE>", &file_name)
-                        });
-
-                        run_command_in_foreground(options,
-                                                  v.terminal,
-                                                  v.app_state.current_dir.get_path_buf(),
-                                                  &v.app_state.interrupt_signal_receiver,
-                                                  v.config.command_status_refresh_secs,
-                                                  false);
-
-                        ActionResult::Valid
-                    } else {
-                        ActionResult::Invalid(String::from("Can not open the file because the config file does not contain a command to open files"))
-                    }
-                }
-            } else {
-                ActionResult::Invalid(String::from("No file selected"))
-            }
+            let (action_name, last_modifier) = match &v.app_state.last_action {
+                Some(last_action) => last_action.clone(),
+                None => return ActionResult::Invalid(String::from("There is nothing to repeat")),
+            };
+            let action = match NORMAL_MODE_ACTION_MAP.get(&action_name) {
+                Some(action) => action,
+                None => unreachable!(), // only the actions from this map are remembered
+            };
+            // like in vim, a new count replaces the old one
+            let modifier = v.modifier.or(last_modifier);
+            action(ActionData { modifier, ..v })
         }),
     );
     m.insert(
-        String::from("go_to_or_go_to_bottom"),
+        String::from("next_sibling_dir"),
+        Box::new(|v| go_to_sibling_dir(v, true)),
+    );
+    m.insert(
+        String::from("prev_sibling_dir"),
+        Box::new(|v| go_to_sibling_dir(v, false)),
+    );
+    // "right" is kept so that older configs still work
+    m.insert(String::from("right"), Box::new(open_selected));
+    m.insert(String::from("open"), Box::new(open_selected));
+    m.insert(
+        String::from("enter_directory"),
+        Box::new(enter_selected_directory),
+    );
+    m.insert(String::from("open_file"), Box::new(open_selected_file));
+    m.insert(
+        String::from("edit_config"),
         Box::new(|v| {
-            // if there is a specified line, go to it
-            if let Some(modifier) = v.modifier {
-                v.app_state.get_mut().set_file_cursor_highlight_index(
-                    v.dir_items,
-                    |_, num_items| {
-                        (modifier - 1).clamp(0, num_items - 1) // to convert it into an index
-                    },
-                );
-                ActionResult::Valid
-            } else {
+            let config_path = match &v.config.loaded_from {
+                Some(config_path) => config_path.to_string_lossy().into_owned(),
+                None => return ActionResult::Invalid(String::from("Could not find which config file was loaded")),
+            };
+            let file_editor_options = match &v.config.default_file_editor_command {
+                Some(file_editor_options) => file_editor_options,
+                None => return ActionResult::Invalid(String::from(
+                    "Can not open the config file because the config file does not contain a command to open files",
+                )),
+            };
+            let options = file_editor_options.iter().map(|option| {
+                option
+                    .replace("<FILE>", &config_path)
+                    .replace("<LINE>", "1")
+            });
+
+            let result = run_command_in_foreground(
+                options,
+                v.terminal,
+                v.app_state.current_dir.get_path_buf(),
+                &v.app_state.interrupt_signal_receiver,
+                v.config.command_status_refresh_secs,
+                false,
+            );
+
+            v.app_state.get_mut().reset_state();
+            v.app_state.get_mut().invalidate_listing();
+            if let Err(err) = result {
                 v.app_state
                     .get_mut()
-                    .set_file_cursor_highlight_index(v.dir_items, |_, num_items| num_items - 1);
-                ActionResult::Valid
+                    .error_popup(String::from("Could not edit the config file"), err);
+                return ActionResult::Valid;
             }
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::ReloadConfigConfirm,
+            };
+            ActionResult::Valid
         }),
     );
+    m.insert(String::from("help_config"), Box::new(help_config));
     m.insert(
-        String::from("go_to_top"),
+        String::from("history_back"),
         Box::new(|v| {
-            v.app_state
-                .get_mut()
-                .set_file_cursor_highlight_index(v.dir_items, |_, _| 0);
-            ActionResult::Valid
+            let steps = v.modifier.unwrap_or(1) as isize;
+            if v.app_state.get_mut().move_in_history(-steps) {
+                ActionResult::Valid
+            } else {
+                ActionResult::Invalid(String::from("Can not go back any further"))
+            }
         }),
     );
     m.insert(
-        String::from("rename"),
+        String::from("history_forward"),
         Box::new(|v| {
-            // reset the  mode
-            v.app_state.get_mut().reset_state();
-
-            if let Some(old_file) = &v.app_state.selected_file {
-                v.app_state.get_mut().mode = Mode::OverlayMode {
-                    background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
-                    overlay_mode: OverlayMode::Rename {
-                        old_file: old_file.to_owned(),
-                    },
-                };
+            let steps = v.modifier.unwrap_or(1) as isize;
+            if v.app_state.get_mut().move_in_history(steps) {
                 ActionResult::Valid
             } else {
-                ActionResult::Invalid(String::from("No file selected"))
+                ActionResult::Invalid(String::from("Can not go forward any further"))
             }
         }),
     );
     m.insert(
-        String::from("delete_instantly"),
+        String::from("quick_nav"),
         Box::new(|v| {
-            // reset the  mode
-            v.app_state.get_mut().reset_state();
-
-            if let Some(file) = &v.app_state.selected_file {
-                v.app_state.get_mut().mode = Mode::OverlayMode {
-                    background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
-                    overlay_mode: OverlayMode::DeleteInstantlyConfirm {
-                        file: file.to_owned(),
-                    },
-                };
-                ActionResult::Valid
-            } else {
-                ActionResult::Invalid(String::from("No file selected"))
+            // the same action is bound to every sequence, so the typed keys tell which directory it is
+            let key_sequence = v
+                .app_state
+                .input_reader
+                .get_human_friendly_verb_key_sequence();
+            let typed_path = match v.config.quick_nav.get(&key_sequence) {
+                Some(typed_path) => typed_path,
+                None => {
+                    return ActionResult::Invalid(format!(
+                        "'{}' is not in the [quick_nav] section of the config file",
+                        key_sequence
+                    ))
+                }
+            };
+            let path = resolve_path(v.app_state.current_dir.get_path_buf(), typed_path);
+            if !path.is_dir() {
+                return ActionResult::Invalid(format!(
+                    "'{}' is not a directory",
+                    path.to_string_lossy()
+                ));
             }
+            v.app_state.get_mut().change_dir(FileTreeNode::new(path));
+            ActionResult::Valid
         }),
     );
     m.insert(
-        String::from("remove_marks"),
+        String::from("toggle_last_dir"),
         Box::new(|v| {
-            v.app_state.get_mut().marked_files = vec![];
+            let (previous_dir, previous_selected_file) = match &v.app_state.previous_dir {
+                Some(previous_dir) => previous_dir.clone(),
+                None => {
+                    return ActionResult::Invalid(String::from(
+                        "There is no previous directory to go back to",
+                    ))
+                }
+            };
+            let app_state = v.app_state.get_mut();
+            app_state.change_dir(FileTreeNode::new(previous_dir));
+            app_state.selected_file = previous_selected_file;
             ActionResult::Valid
         }),
     );
     m.insert(
-        String::from("toggle_delete_mark"),
+        String::from("copy_listing"),
+        Box::new(|v| copy_listing(v, false)),
+    );
+    m.insert(
+        String::from("copy_listing_with_sizes"),
+        Box::new(|v| copy_listing(v, true)),
+    );
+    m.insert(
+        String::from("yank_contents"),
         Box::new(|v| {
-            if let Some(selected_file) = &v.app_state.selected_file {
-                let index = v
-                    .app_state
-                    .marked_files
-                    .iter()
-                    .position(|f| f == selected_file);
-
-                if let Some(index) = index {
-                    v.app_state.get_mut().marked_files.remove(index);
-                } else {
-                    let selected_file = selected_file.to_owned();
-                    v.app_state.get_mut().marked_files.push(selected_file);
+            let file = match &v.app_state.selected_file {
+                Some(selected_file) if !selected_file.is_dir() => selected_file.clone(),
+                Some(_) => {
+                    return ActionResult::Invalid(String::from("The selected item is not a file"))
                 }
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            let contents = match read_text_for_clipboard(&file) {
+                Ok(contents) => contents,
+                Err(err) => return ActionResult::Invalid(err),
+            };
+            let lines_num = contents.lines().count();
 
-                ActionResult::Valid
-            } else {
-                ActionResult::Invalid(String::from("No file selected"))
+            match v.app_state.get_mut().set_clipboard_text(contents) {
+                Ok(_) => {
+                    v.app_state.get_mut().toast(format!(
+                        "Copied {} line(s) of '{}' to the clipboard",
+                        lines_num,
+                        file.get_simple_name()
+                    ));
+                    ActionResult::Valid
+                }
+                Err(err) => {
+                    ActionResult::Invalid(format!("Could not copy to the clipboard: {}", err))
+                }
             }
         }),
     );
     m.insert(
-        String::from("apply_mark_action"),
+        String::from("toggle_read_only"),
+        Box::new(|v| {
+            let is_read_only = !v.app_state.is_read_only;
+            v.app_state.get_mut().is_read_only = is_read_only;
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("toggle_case_insensitive_names"),
+        Box::new(|v| {
+            let case_insensitive_names = !v.app_state.case_insensitive_names;
+            v.app_state.get_mut().case_insensitive_names = case_insensitive_names;
+            v.app_state.get_mut().toast(format!(
+                "Case-insensitive names: {}",
+                if case_insensitive_names { "on" } else { "off" }
+            ));
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("refresh"),
+        Box::new(|v| {
+            v.app_state.get_mut().invalidate_listing();
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("cycle_sort"),
+        Box::new(|v| {
+            // the cursor stays on the same file because the selected file is tracked by its path
+            let sort_key = v.app_state.get_sort_key().next();
+            v.app_state.get_mut().sort_key = sort_key;
+            // what is chosen by hand wins over the directory's config until the directory is left
+            v.app_state.get_mut().dir_config.sort_key = None;
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("reverse_sort"),
+        Box::new(|v| {
+            let is_sort_reversed = !v.app_state.get_is_sort_reversed();
+            v.app_state.get_mut().is_sort_reversed = is_sort_reversed;
+            v.app_state.get_mut().dir_config.reverse_sort = None;
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("git_stage"),
+        mutating_action(|v| update_git_index(v, true)),
+    );
+    m.insert(
+        String::from("git_unstage"),
+        mutating_action(|v| update_git_index(v, false)),
+    );
+    m.insert(
+        String::from("load_preview"),
+        Box::new(|v| {
+            let selected_path = match &v.app_state.selected_file {
+                Some(selected_file) => selected_file.get_path_buf().to_owned(),
+                None => return ActionResult::Invalid(String::from("Nothing is selected")),
+            };
+            v.app_state.get_mut().loaded_preview = Some(selected_path);
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("toggle_preview"),
+        Box::new(|v| {
+            let is_preview_shown = !v.app_state.is_preview_shown;
+            v.app_state.get_mut().is_preview_shown = is_preview_shown;
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("toggle_line_numbers"),
         Box::new(|v| {
-            for file in v.app_state.marked_files.iter() {
-                let result = match v.app_state.mark_type {
-                    MarkType::Delete => delete_file_tree_node(file),
+            // turning them back on brings back the kind from the config
+            v.app_state.get_mut().line_numbers =
+                match (v.app_state.line_numbers, v.config.line_numbers) {
+                    (LineNumbers::Off, LineNumbers::Off) => LineNumbers::Absolute,
+                    (LineNumbers::Off, line_numbers) => line_numbers,
+                    _ => LineNumbers::Off,
                 };
-                if let Err(err) = result {
-                    return ActionResult::Invalid(format!("Error executing the action: {}", err));
-                }
-            }
             ActionResult::Valid
         }),
     );
     m.insert(
-        String::from("create_file"),
+        String::from("preview_search_mode"),
         Box::new(|v| {
+            if v.app_state.selected_file.is_none() {
+                return ActionResult::Invalid(String::from("No file selected"));
+            }
+            // reset the mode
             v.app_state.get_mut().reset_state();
 
-            v.app_state.get_mut().mode = Mode::OverlayMode {
-                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
-                overlay_mode: OverlayMode::CreateFile,
+            v.app_state.get_mut().mode = Mode::TextInputMode {
+                text_input_type: TextInput::PreviewSearch,
             };
             ActionResult::Valid
         }),
     );
     m.insert(
-        String::from("create_directory"),
+        String::from("preview_next_match"),
+        Box::new(|v| move_preview_match(v, true)),
+    );
+    m.insert(
+        String::from("preview_previous_match"),
+        Box::new(|v| move_preview_match(v, false)),
+    );
+    m.insert(
+        String::from("sort_column_left"),
+        Box::new(|v| sort_by_adjacent_column(v, false)),
+    );
+    m.insert(
+        String::from("sort_column_right"),
+        Box::new(|v| sort_by_adjacent_column(v, true)),
+    );
+    m.insert(
+        String::from("toggle_path_display"),
         Box::new(|v| {
-            v.app_state.get_mut().reset_state();
-
-            v.app_state.get_mut().mode = Mode::OverlayMode {
-                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
-                overlay_mode: OverlayMode::CreateDirectory,
+            let path_display = v
+                .app_state
+                .path_display
+                .get_next(v.config.path_display_root.is_some());
+            v.app_state.get_mut().path_display = path_display;
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("toggle_view_mode"),
+        Box::new(|v| {
+            v.app_state.get_mut().view_mode = match v.app_state.view_mode {
+                ViewMode::Compact => ViewMode::Detailed,
+                ViewMode::Detailed => ViewMode::Compact,
             };
             ActionResult::Valid
         }),
     );
-    m
-});
+    m.insert(
+        String::from("expand"),
+        Box::new(|v| {
+            let selected_file = match &v.app_state.selected_file {
+                Some(selected_file) if selected_file.is_dir() => selected_file.clone(),
+                Some(_) => {
+                    return ActionResult::Invalid(String::from(
+                        "The selected item is not a directory",
+                    ))
+                }
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            if v.app_state
+                .expanded_dirs
+                .contains_key(selected_file.get_path_buf())
+            {
+                return ActionResult::Valid;
+            }
 
-pub(crate) static TEXT_MODE_ACTION_MAP: Lazy<ActionNameMap> = Lazy::new(|| {
-    let mut m: ActionNameMap = BTreeMap::new();
+            // it is listed in the background, so that huge or slow directories do not block the UI.
+            // Until then it is shown as empty, with a spinner next to it
+            let selected_path = selected_file.get_path_buf().to_owned();
+            let app_state = v.app_state.get_mut();
+            app_state
+                .loading_expanded_dirs
+                .insert(selected_path.clone(), Instant::now());
+            app_state.expanded_dirs.insert(selected_path, vec![]);
+            ActionResult::Valid
+        }),
+    );
     m.insert(
-        String::from("noop"),
+        String::from("collapse"),
         Box::new(|v| {
-            // push the new typed characters
+            let selected_path = match &v.app_state.selected_file {
+                Some(selected_file) => selected_file.get_path_buf().to_owned(),
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            // collapse the selected directory, or the one that the selected item is in
+            let dir_to_collapse = if v.app_state.expanded_dirs.contains_key(&selected_path) {
+                selected_path
+            } else {
+                match selected_path.parent() {
+                    Some(parent) if v.app_state.expanded_dirs.contains_key(parent) => {
+                        let parent = parent.to_owned();
+                        // the selected item disappears, so move to the directory it was in
+                        v.app_state.get_mut().selected_file =
+                            Some(FileTreeNode::new(parent.clone()));
+                        parent
+                    }
+                    _ => return ActionResult::Invalid(String::from("Nothing to collapse")),
+                }
+            };
+            // the directories inside it are collapsed too, so that they do not reappear when it is expanded again
             v.app_state
                 .get_mut()
-                .copy_input_manager_verbs_to_entered_text();
-            v.app_state.get_mut().input_reader.clear();
+                .expanded_dirs
+                .retain(|path, _| !path.starts_with(&dir_to_collapse));
             ActionResult::Valid
         }),
     );
     m.insert(
-        String::from("delete_last_char"),
+        String::from("collapse_all"),
         Box::new(|v| {
-            v.app_state.get_mut().entered_text.pop();
-
+            if v.app_state.expanded_dirs.is_empty() {
+                return ActionResult::Invalid(String::from("Nothing to collapse"));
+            }
+            let app_state = v.app_state.get_mut();
+            // if the cursor is inside an expanded directory, move it to the item at the top level that contains it
+            if let Some(selected_file) = &app_state.selected_file {
+                if let Some(top_level_path) = selected_file
+                    .get_path_buf()
+                    .strip_prefix(app_state.current_dir.get_path_buf())
+                    .ok()
+                    .and_then(|relative_path| relative_path.components().next())
+                    .map(|component| app_state.current_dir.get_path_buf().join(component))
+                {
+                    app_state.selected_file = Some(FileTreeNode::new(top_level_path));
+                }
+            }
+            app_state.expanded_dirs = BTreeMap::new();
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("toggle_ignored"),
+        Box::new(|v| {
+            let is_ignore_active = !v.app_state.is_ignore_active;
+            v.app_state.get_mut().is_ignore_active = is_ignore_active;
+            v.app_state.get_mut().toast(format!(
+                "Hiding ignored entries: {}",
+                if is_ignore_active { "on" } else { "off" }
+            ));
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("toggle_follow_symlinks"),
+        Box::new(|v| {
+            let follow_symlinks = !v.app_state.follow_symlinks;
+            v.app_state.get_mut().follow_symlinks = follow_symlinks;
+            v.app_state.get_mut().toast(format!(
+                "Following symlinks: {}",
+                if follow_symlinks { "on" } else { "off" }
+            ));
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("page_down"),
+        Box::new(|v| {
+            // one page is as many rows as were on the screen the last time
+            let page_size = v.app_state.visible_rows.max(1) * v.modifier.unwrap_or(1);
+            v.app_state.get_mut().set_file_cursor_highlight_index(
+                v.dir_items,
+                |index, items_num| {
+                    // stop at the bottom instead of wrapping around
+                    (index + page_size).min(items_num - 1)
+                },
+            );
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("page_up"),
+        Box::new(|v| {
+            let page_size = v.app_state.visible_rows.max(1) * v.modifier.unwrap_or(1);
+            v.app_state
+                .get_mut()
+                .set_file_cursor_highlight_index(v.dir_items, |index, _| {
+                    index.saturating_sub(page_size)
+                });
             ActionResult::Valid
         }),
     );
+    m.insert(
+        String::from("go_to_visible_row"),
+        Box::new(|v| {
+            // the rows are counted from the top of the screen, not from the start of the list
+            let row = match v.modifier {
+                Some(row) if row >= 1 && row <= v.app_state.visible_rows => row,
+                Some(_) => return ActionResult::Invalid(String::from("That row is not visible")),
+                None => {
+                    return ActionResult::Invalid(String::from(
+                        "Type the number of the row before this action",
+                    ))
+                }
+            };
+            let index = v.app_state.scroll_offset + row - 1;
+            if index >= v.dir_items.len() {
+                return ActionResult::Invalid(String::from("That row is empty"));
+            }
 
-    m
-});
+            v.app_state
+                .get_mut()
+                .set_file_cursor_highlight_index(v.dir_items, |_, _| index);
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("go_to_or_go_to_bottom"),
+        Box::new(|v| {
+            // if there is a specified line, go to it
+            if let Some(modifier) = v.modifier {
+                v.app_state.get_mut().set_file_cursor_highlight_index(
+                    v.dir_items,
+                    |_, num_items| {
+                        (modifier - 1).clamp(0, num_items - 1) // to convert it into an index
+                    },
+                );
+                ActionResult::Valid
+            } else {
+                v.app_state
+                    .get_mut()
+                    .set_file_cursor_highlight_index(v.dir_items, |_, num_items| num_items - 1);
+                ActionResult::Valid
+            }
+        }),
+    );
+    m.insert(
+        String::from("go_to_top"),
+        Box::new(|v| {
+            v.app_state
+                .get_mut()
+                .set_file_cursor_highlight_index(v.dir_items, |_, _| 0);
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("rename"),
+        mutating_action(|v| {
+            // reset the  mode
+            v.app_state.get_mut().reset_state();
+
+            if let Some(old_file) = &v.app_state.selected_file {
+                let old_file = old_file.to_owned();
+                // only the stem is typed, and the extension is added back when renaming
+                let keep_extension =
+                    v.config.rename_keeps_extension && old_file.get_extension().is_some();
+                // start from the old name, so that small changes do not need retyping all of it
+                let old_name = if keep_extension {
+                    old_file
+                        .get_path_buf()
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                } else {
+                    old_file.get_simple_name().trim_end_matches('/').to_owned()
+                };
+                v.app_state.get_mut().set_entered_text(old_name);
+
+                v.app_state.get_mut().mode = Mode::OverlayMode {
+                    background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                    overlay_mode: OverlayMode::Rename {
+                        old_file,
+                        keep_extension,
+                    },
+                };
+                ActionResult::Valid
+            } else {
+                ActionResult::Invalid(String::from("No file selected"))
+            }
+        }),
+    );
+    m.insert(
+        String::from("substitute_rename"),
+        mutating_action(|v| {
+            let paths: Vec<_> = if v.app_state.marked_files.is_empty() {
+                match &v.app_state.selected_file {
+                    Some(selected_file) => vec![selected_file.get_path_buf().to_owned()],
+                    None => return ActionResult::Invalid(String::from("No file selected")),
+                }
+            } else {
+                v.app_state.marked_files.keys().cloned().collect()
+            };
+
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::SubstituteRename {
+                    paths,
+                    is_regex: false,
+                },
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(String::from("bulk_edit"), mutating_action(bulk_edit));
+    m.insert(
+        String::from("delete_instantly"),
+        mutating_action(|v| {
+            // reset the  mode
+            v.app_state.get_mut().reset_state();
+
+            let file = match &v.app_state.selected_file {
+                Some(file) => file.to_owned(),
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            if !v.config.confirmations.delete {
+                return delete_instantly(v, &file);
+            }
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::DeleteInstantlyConfirm { file },
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("trash_instantly"),
+        mutating_action(|v| {
+            // trash the selected file and the ones after it if there is a modifier
+            let start = match get_file_cursor_index(&v.app_state.selected_file, v.dir_items) {
+                Some(start) => start,
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            let end = (start + v.modifier.unwrap_or(1)).min(v.dir_items.len());
+            let files = v.dir_items[start..end].to_vec();
+
+            if !v.config.confirmations.trash {
+                return trash_files(v, &files);
+            }
+            v.app_state.get_mut().reset_state();
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::TrashConfirm { files },
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("remove_marks"),
+        Box::new(|v| {
+            // the first press only cancels the visual selection, keeping the marks made before it
+            if v.app_state.visual_anchor.is_some() {
+                v.app_state.get_mut().visual_anchor = None;
+            } else {
+                v.app_state.get_mut().marked_files = BTreeMap::new();
+            }
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("show_dry_run_log"),
+        Box::new(|v| {
+            if !v.filesystem.is_dry_run() {
+                return ActionResult::Invalid(String::from(
+                    "Not a dry run, start with --dry-run to only log the changes",
+                ));
+            }
+            let log = v.filesystem.get_log();
+            let desc = if log.is_empty() {
+                String::from("Nothing was skipped yet")
+            } else {
+                log.join("\n")
+            };
+            v.app_state
+                .get_mut()
+                .info_popup(String::from("Changes skipped by the dry run"), desc);
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("show_registers"),
+        Box::new(|v| {
+            // unlike the other overlays, this keeps the marks, since the picked register is used on them
+            let app_state = v.app_state.get_mut();
+            app_state.entered_text = String::new();
+            app_state.cursor_pos = 0;
+            app_state.mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal,
+                overlay_mode: OverlayMode::Registers,
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("yank_to_register"),
+        Box::new(|v| put_into_register(v, false)),
+    );
+    m.insert(
+        String::from("cut_to_register"),
+        Box::new(|v| put_into_register(v, true)),
+    );
+    m.insert(
+        String::from("paste_from_register"),
+        mutating_action(paste_from_register),
+    );
+    m.insert(
+        String::from("visual_mode"),
+        Box::new(|v| {
+            if v.app_state.visual_anchor.is_some() {
+                v.app_state.get_mut().visual_anchor = None;
+                return ActionResult::Valid;
+            }
+            match get_file_cursor_index(&v.app_state.selected_file, v.dir_items) {
+                Some(cursor_index) => {
+                    v.app_state.get_mut().visual_anchor = Some(cursor_index);
+                    ActionResult::Valid
+                }
+                None => ActionResult::Invalid(String::from("No file selected")),
+            }
+        }),
+    );
+    m.insert(
+        String::from("toggle_delete_mark"),
+        Box::new(|v| toggle_mark(v, MarkType::Delete)),
+    );
+    m.insert(
+        String::from("toggle_copy_mark"),
+        Box::new(|v| toggle_mark(v, MarkType::Copy)),
+    );
+    m.insert(
+        String::from("toggle_cut_mark"),
+        Box::new(|v| toggle_mark(v, MarkType::Cut)),
+    );
+    m.insert(
+        String::from("invert_marks"),
+        Box::new(|v| {
+            let mark_type = get_current_mark_type(v.app_state);
+            // only the visible items count, so marks on items that are not shown are dropped
+            let inverted_marks = v
+                .dir_items
+                .iter()
+                .filter(|item| !v.app_state.marked_files.contains_key(item.get_path_buf()))
+                .map(|item| (item.get_path_buf().to_owned(), mark_type))
+                .collect();
+            v.app_state.get_mut().marked_files = inverted_marks;
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("mark_same_extension"),
+        Box::new(|v| {
+            let selected_file = match &v.app_state.selected_file {
+                Some(selected_file) if !selected_file.is_dir() => selected_file,
+                Some(_) => {
+                    return ActionResult::Invalid(String::from("The selected item is not a file"))
+                }
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            // files without an extension match each other
+            let extension = selected_file
+                .get_path_buf()
+                .extension()
+                .map(OsStr::to_owned);
+
+            let mark_type = get_current_mark_type(v.app_state);
+            let new_marks: Vec<_> = v
+                .dir_items
+                .iter()
+                .filter(|item| {
+                    !item.is_dir()
+                        && item.get_path_buf().extension() == extension.as_deref()
+                        && !v.app_state.marked_files.contains_key(item.get_path_buf())
+                })
+                .map(|item| (item.get_path_buf().to_owned(), mark_type))
+                .collect();
+            let marked_num = new_marks.len();
+            v.app_state.get_mut().marked_files.extend(new_marks);
+
+            v.app_state.get_mut().toast(match extension {
+                Some(extension) => format!(
+                    "Marked {} more file(s) with the extension .{}",
+                    marked_num,
+                    extension.to_string_lossy()
+                ),
+                None => format!("Marked {} more file(s) without an extension", marked_num),
+            });
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("apply_mark_action"),
+        mutating_action(|v| {
+            let confirmations = v.config.confirmations;
+            let needs_confirmation =
+                v.app_state
+                    .marked_files
+                    .values()
+                    .any(|mark_type| match mark_type {
+                        MarkType::Delete => confirmations.delete,
+                        MarkType::Cut => confirmations.move_files,
+                        MarkType::Copy => false,
+                    });
+            if !needs_confirmation {
+                return apply_marks(v);
+            }
+            // the marks are kept, since they are what is being confirmed
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal,
+                overlay_mode: OverlayMode::ApplyMarksConfirm,
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("create_file"),
+        mutating_action(|v| {
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::CreateFile {
+                    from_clipboard: false,
+                },
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("touch"),
+        mutating_action(|v| {
+            let path = match &v.app_state.selected_file {
+                Some(selected_file) => selected_file.get_path_buf().to_owned(),
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            if let Err(err) = v.filesystem.touch(&path) {
+                return ActionResult::Invalid(format!("Could not touch the file: {}", err));
+            }
+            let app_state = v.app_state.get_mut();
+            // the new modification time can move the file when sorting by it
+            app_state.selected_file = Some(FileTreeNode::new(path));
+            app_state.invalidate_listing();
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("backup"),
+        mutating_action(|v| {
+            let selected_file = match &v.app_state.selected_file {
+                Some(selected_file) => selected_file,
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            if selected_file.is_dir() {
+                return ActionResult::Invalid(String::from("Only files can be backed up"));
+            }
+            let path = selected_file.get_path_buf().to_owned();
+            let file_name = selected_file.get_simple_name().to_owned();
+            let name_format = v
+                .config
+                .backup_name_format
+                .as_deref()
+                .unwrap_or(DEFAULT_BACKUP_NAME_FORMAT);
+            let backup_name = match get_backup_name(&file_name, name_format, Local::now()) {
+                Ok(backup_name) => backup_name,
+                Err(err) => {
+                    return ActionResult::Invalid(format!("Invalid backup_name_format: {}", err))
+                }
+            };
+            if backup_name.is_empty() || backup_name.contains(std::path::is_separator) {
+                return ActionResult::Invalid(format!(
+                    "'{}' is not a valid name for the backup",
+                    backup_name
+                ));
+            }
+            let backup_path = path.with_file_name(&backup_name);
+            // copying never overwrites anything, but this says which file is in the way
+            if backup_path.symlink_metadata().is_ok() {
+                return ActionResult::Invalid(format!(
+                    "Could not back up '{}': '{}' already exists",
+                    file_name, backup_name
+                ));
+            }
+            if let Err(err) = v.filesystem.copy(&path, &backup_path) {
+                return ActionResult::Invalid(format!(
+                    "Could not back up '{}': {}",
+                    file_name, err
+                ));
+            }
+            let app_state = v.app_state.get_mut();
+            app_state.invalidate_listing();
+            app_state.toast(format!("Backed up '{}' as '{}'", file_name, backup_name));
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("create_file_from_clipboard"),
+        mutating_action(|v| {
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::CreateFile {
+                    from_clipboard: true,
+                },
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(String::from("open_in_os"), Box::new(open_in_os));
+    m.insert(
+        String::from("open_multiplexer_pane"),
+        Box::new(open_multiplexer_pane),
+    );
+    m.insert(String::from("open_terminal"), Box::new(open_terminal));
+    m.insert(
+        String::from("show_hash"),
+        Box::new(|v| {
+            let selected_file = match &v.app_state.selected_file {
+                Some(selected_file) => selected_file.clone(),
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            if selected_file.is_dir() {
+                return ActionResult::Invalid(String::from("Can not hash a directory"));
+            }
+            if v.app_state.file_hashing.is_some() {
+                return ActionResult::Invalid(String::from("Already hashing a file"));
+            }
+
+            v.app_state.get_mut().file_hashing =
+                Some(FileHashing::new(selected_file, v.config.hash_algorithm));
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("info"),
+        Box::new(|v| {
+            let selected_file = match &v.app_state.selected_file {
+                Some(selected_file) => selected_file.clone(),
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            let info = match selected_file.get_info() {
+                Ok(info) => info,
+                Err(err) => {
+                    return ActionResult::Invalid(format!("Could not read the file: {}", err))
+                }
+            };
+            v.app_state.get_mut().reset_state();
+            v.app_state.get_mut().table_popup(
+                format!("Info about '{}'", selected_file.get_simple_name()),
+                &info,
+            );
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("disk_usage"),
+        Box::new(|v| {
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().disk_usage = Some(DiskUsage::new(&v.app_state.current_dir));
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::DiskUsage,
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("command_menu"),
+        Box::new(|v| {
+            if v.config.commands.is_empty() {
+                return ActionResult::Invalid(String::from(
+                    "There are no commands in the [commands] section of the config file",
+                ));
+            }
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::CommandMenu,
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("copy_to_bookmark"),
+        mutating_action(|v| {
+            if v.config.bookmarks.is_empty() {
+                return ActionResult::Invalid(String::from(
+                    "There are no bookmarks in the [bookmarks] section of the config file",
+                ));
+            }
+            let paths: Vec<_> = if v.app_state.marked_files.is_empty() {
+                match &v.app_state.selected_file {
+                    Some(selected_file) => vec![selected_file.get_path_buf().to_owned()],
+                    None => return ActionResult::Invalid(String::from("No file selected")),
+                }
+            } else {
+                v.app_state.marked_files.keys().cloned().collect()
+            };
+            // e.g. "2 z b" copies into the second bookmark straight away
+            if let Some(number) = v.modifier {
+                return copy_to_bookmark(v, &paths, &number.to_string());
+            }
+
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::CopyToBookmark { paths },
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("jump_to_dir"),
+        Box::new(|v| {
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::JumpToDir,
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("go_to_path"),
+        Box::new(|v| {
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::GoToPath,
+            };
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("archive"),
+        mutating_action(|v| {
+            let paths: Vec<_> = if v.app_state.marked_files.is_empty() {
+                match &v.app_state.selected_file {
+                    Some(selected_file) => vec![selected_file.get_path_buf().to_owned()],
+                    None => return ActionResult::Invalid(String::from("No file selected")),
+                }
+            } else {
+                v.app_state.marked_files.keys().cloned().collect()
+            };
+            // a single item gives its name to the archive, otherwise the current directory does
+            let default_name_source = match paths.as_slice() {
+                [path] => path.as_path(),
+                _ => v.app_state.current_dir.get_path_buf().as_path(),
+            };
+            let default_name = default_name_source
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("archive"));
+
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::Archive { paths },
+            };
+            v.app_state.get_mut().set_entered_text(format!(
+                "{}{}",
+                default_name,
+                v.config.archive_format.get_extension()
+            ));
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("extract"),
+        mutating_action(|v| {
+            let file = match &v.app_state.selected_file {
+                Some(selected_file) if !selected_file.is_dir() => selected_file.clone(),
+                Some(_) => {
+                    return ActionResult::Invalid(String::from("The selected item is not a file"))
+                }
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            let format = match detect_archive_format(file.get_path_buf()) {
+                Ok(Some(format)) => format,
+                Ok(None) => {
+                    return ActionResult::Invalid(String::from(
+                        "The file is not a zip, tar or tar.gz archive",
+                    ))
+                }
+                Err(err) => {
+                    return ActionResult::Invalid(format!("Could not read the file: {}", err))
+                }
+            };
+            // e.g. "photos.tar.gz" goes into "photos/" next to it
+            let destination = file
+                .get_path_buf()
+                .parent()
+                .unwrap_or(v.app_state.current_dir.get_path_buf())
+                .join(get_extraction_dir_name(file.get_simple_name()));
+
+            v.app_state.get_mut().reset_state();
+
+            if destination.exists() && v.config.confirmations.overwrite {
+                v.app_state.get_mut().mode = Mode::OverlayMode {
+                    background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                    overlay_mode: OverlayMode::ExtractOverwriteConfirm {
+                        file,
+                        format,
+                        destination,
+                    },
+                };
+                return ActionResult::Valid;
+            }
+            start_extracting(v, &file, format, destination)
+        }),
+    );
+    m.insert(
+        String::from("copy_relative_path"),
+        Box::new(|v| {
+            let file = match &v.app_state.selected_file {
+                Some(selected_file) => selected_file.clone(),
+                None => return ActionResult::Invalid(String::from("No file selected")),
+            };
+            // paths in a project are usually wanted relative to its root
+            let base = find_git_root(v.app_state.current_dir.get_path_buf())
+                .unwrap_or(v.app_state.current_dir.get_path_buf())
+                .to_string_lossy()
+                .into_owned();
+
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::CopyRelativePath { file },
+            };
+            v.app_state.get_mut().set_entered_text(base);
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("copy_git_root"),
+        Box::new(|v| {
+            let repo = match discover_repository(v.app_state.current_dir.get_path_buf()) {
+                Some(repo) => repo,
+                None => return ActionResult::Invalid(String::from("Not in a git repository")),
+            };
+            // the working directory is given with a trailing slash, which going through the components drops
+            let root = match repo.workdir() {
+                Some(workdir) => workdir
+                    .components()
+                    .as_path()
+                    .to_string_lossy()
+                    .into_owned(),
+                None => return ActionResult::Invalid(String::from("Not in a git repository")),
+            };
+            match v.app_state.get_mut().set_clipboard_text(root.clone()) {
+                Ok(_) => {
+                    v.app_state
+                        .get_mut()
+                        .toast(format!("Copied '{}' to the clipboard", root));
+                    ActionResult::Valid
+                }
+                Err(err) => {
+                    ActionResult::Invalid(format!("Could not copy to the clipboard: {}", err))
+                }
+            }
+        }),
+    );
+    m.insert(
+        String::from("create_directory"),
+        mutating_action(|v| {
+            v.app_state.get_mut().reset_state();
+
+            v.app_state.get_mut().mode = Mode::OverlayMode {
+                background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                overlay_mode: OverlayMode::CreateDirectory,
+            };
+            ActionResult::Valid
+        }),
+    );
+    m
+});
+
+/// Copies the names of the items as they are currently shown (i.e. filtered and sorted), one per line
+fn copy_listing(v: ActionData, include_sizes: bool) -> ActionResult {
+    let listing: Vec<_> = v
+        .dir_items
+        .iter()
+        .map(|item| {
+            if include_sizes {
+                let size = item
+                    .get_size()
+                    .map(format_size)
+                    .unwrap_or_else(|_| String::from("?"));
+                format!("{}\t{}", size, item.get_simple_name())
+            } else {
+                item.get_simple_name().to_owned()
+            }
+        })
+        .collect();
+
+    match v.app_state.get_mut().set_clipboard_text(listing.join("\n")) {
+        Ok(_) => {
+            v.app_state
+                .get_mut()
+                .toast(format!("Copied {} item(s) to the clipboard", listing.len()));
+            ActionResult::Valid
+        }
+        Err(err) => ActionResult::Invalid(format!("Could not copy to the clipboard: {}", err)),
+    }
+}
+
+/// Copies the paths into the bookmarked directory, keeping their names, and reports the result in a popup.
+/// The files that could not be copied are skipped
+pub(crate) fn copy_to_bookmark(
+    v: ActionData,
+    paths: &[PathBuf],
+    number_or_name: &str,
+) -> ActionResult {
+    let (bookmark_name, bookmark_path) = match v.config.get_bookmark(number_or_name) {
+        Some(bookmark) => bookmark,
+        None => return ActionResult::Invalid(format!("There is no bookmark '{}'", number_or_name)),
+    };
+    let destination_dir = resolve_path(v.app_state.current_dir.get_path_buf(), bookmark_path);
+    if !destination_dir.is_dir() {
+        return ActionResult::Invalid(format!(
+            "The bookmark '{}' is not a directory",
+            bookmark_name
+        ));
+    }
+
+    let errors: Vec<_> = paths
+        .iter()
+        .filter_map(|path| {
+            let destination = destination_dir.join(path.file_name()?);
+            v.filesystem.copy(path, &destination).err().map(|err| {
+                format!(
+                    "{}: {}",
+                    FileTreeNode::new(path.clone()).get_simple_name(),
+                    err
+                )
+            })
+        })
+        .collect();
+
+    let app_state = v.app_state.get_mut();
+    app_state.reset_state();
+    // the bookmark might be the current directory or one of the expanded ones
+    app_state.invalidate_listing();
+    let title = format!(
+        "Copied {} of {} item(s) to '{}'",
+        paths.len() - errors.len(),
+        paths.len(),
+        bookmark_name
+    );
+    if errors.is_empty() {
+        app_state.info_popup(title, destination_dir.to_string_lossy().into_owned());
+    } else {
+        app_state.error_popup(title, errors.join("\n"));
+    }
+    ActionResult::Valid
+}
+
+/// Moves to the next or previous directory in the parent directory, in the order of their names, wrapping around at the ends
+fn go_to_sibling_dir(v: ActionData, is_forward: bool) -> ActionResult {
+    let current_path = v.app_state.current_dir.get_path_buf().to_owned();
+    let parent = match current_path.parent() {
+        Some(parent) => parent,
+        None => return ActionResult::Invalid(String::from("The root directory has no siblings")),
+    };
+    let entries = match FileTreeNode::new(parent.to_owned()).list_files() {
+        Ok(entries) => entries,
+        Err(err) => {
+            return ActionResult::Invalid(format!("Could not list the parent directory: {}", err))
+        }
+    };
+    let show_hidden = v
+        .app_state
+        .dir_config
+        .show_hidden
+        .or(v.config.show_hidden)
+        .unwrap_or(true);
+    let mut sibling_paths: Vec<_> = entries
+        .iter()
+        .map(FileTreeNode::get_path_buf)
+        .filter(|path| {
+            let is_hidden = path
+                .file_name()
+                .is_some_and(|file_name| file_name.to_string_lossy().starts_with('.'));
+            // the current directory is kept even if it is hidden, so that there is somewhere to start from
+            path.is_dir() && (show_hidden || !is_hidden || *path == &current_path)
+        })
+        .collect();
+    sibling_paths.sort();
+
+    let current_index = match sibling_paths.iter().position(|path| *path == &current_path) {
+        Some(current_index) => current_index,
+        None => {
+            return ActionResult::Invalid(String::from(
+                "The current directory is not in its parent directory anymore",
+            ))
+        }
+    };
+    let steps = v.modifier.unwrap_or(1) % sibling_paths.len();
+    let new_index = if is_forward {
+        (current_index + steps) % sibling_paths.len()
+    } else {
+        (current_index + sibling_paths.len() - steps) % sibling_paths.len()
+    };
+    let new_dir = FileTreeNode::new(sibling_paths[new_index].to_owned());
+    v.app_state.get_mut().change_dir(new_dir);
+    ActionResult::Valid
+}
+
+/// Moves to the next or previous match of the preview search, wrapping around at the ends
+fn move_preview_match(v: ActionData, is_forward: bool) -> ActionResult {
+    let selected_path = v
+        .app_state
+        .selected_file
+        .as_ref()
+        .map(FileTreeNode::get_path_buf);
+    let matches_num = match &v.app_state.preview_search {
+        Some(preview_search) if Some(&preview_search.path) == selected_path => {
+            preview_search.matches.len()
+        }
+        _ => {
+            return ActionResult::Invalid(String::from(
+                "Search in the preview of the selected file first",
+            ))
+        }
+    };
+    let steps = v.modifier.unwrap_or(1) % matches_num;
+    if let Some(preview_search) = &mut v.app_state.get_mut().preview_search {
+        preview_search.current_match = if is_forward {
+            (preview_search.current_match + steps) % matches_num
+        } else {
+            (preview_search.current_match + matches_num - steps) % matches_num
+        };
+    }
+    ActionResult::Valid
+}
+
+/// Puts the marked files (or the selected one) into the picked register, or the unnamed one
+fn put_into_register(v: ActionData, is_cut: bool) -> ActionResult {
+    let files: Vec<_> = if v.app_state.marked_files.is_empty() {
+        match &v.app_state.selected_file {
+            Some(selected_file) => vec![selected_file.clone()],
+            None => return ActionResult::Invalid(String::from("No file selected")),
+        }
+    } else {
+        v.app_state
+            .marked_files
+            .keys()
+            .map(|path| FileTreeNode::new(path.clone()))
+            .collect()
+    };
+    let app_state = v.app_state.get_mut();
+    let register = app_state
+        .pending_register
+        .take()
+        .unwrap_or(UNNAMED_REGISTER);
+    app_state.toast(format!(
+        "{} {} item(s) into the register '{}'",
+        if is_cut { "Cut" } else { "Yanked" },
+        files.len(),
+        register
+    ));
+    app_state
+        .registers
+        .insert(register, Register { files, is_cut });
+    app_state.marked_files = BTreeMap::new();
+    ActionResult::Valid
+}
+
+/// Copies the files in the picked register (or the unnamed one) into the current directory, or moves them if they were cut
+fn paste_from_register(v: ActionData) -> ActionResult {
+    let register = v
+        .app_state
+        .get_mut()
+        .pending_register
+        .take()
+        .unwrap_or(UNNAMED_REGISTER);
+    let (files, is_cut) = match v.app_state.registers.get(&register) {
+        Some(contents) => (contents.files.clone(), contents.is_cut),
+        None => return ActionResult::Invalid(format!("The register '{}' is empty", register)),
+    };
+
+    let destination_dir = v.app_state.current_dir.get_path_buf().to_owned();
+    let case_insensitive_names = v.app_state.case_insensitive_names;
+    let destinations: Vec<_> = files
+        .iter()
+        .filter_map(|file| Some(destination_dir.join(file.get_path_buf().file_name()?)))
+        .collect();
+    // e.g. "File" and "file" from different directories would end up as the same file
+    if let Some((first, second)) = find_duplicate(&destinations, case_insensitive_names) {
+        return ActionResult::Invalid(format!(
+            "Nothing was pasted: '{}' and '{}' would both be pasted as '{}'",
+            files[first].get_path_buf().display(),
+            files[second].get_path_buf().display(),
+            destinations[second].display()
+        ));
+    }
+    let errors: Vec<_> = files
+        .iter()
+        .filter_map(|file| {
+            let destination = destination_dir.join(file.get_path_buf().file_name()?);
+            if let Some(existing) = find_existing(&destination, case_insensitive_names) {
+                return Some(format!(
+                    "{}: '{}' already exists",
+                    file.get_simple_name(),
+                    existing.display()
+                ));
+            }
+            let result = if is_cut {
+                v.filesystem.move_path(file.get_path_buf(), &destination)
+            } else {
+                v.filesystem.copy(file.get_path_buf(), &destination)
+            };
+            result
+                .err()
+                .map(|err| format!("{}: {}", file.get_simple_name(), err))
+        })
+        .collect();
+
+    let app_state = v.app_state.get_mut();
+    app_state.invalidate_listing();
+    // the cut files are not where they were anymore
+    if is_cut {
+        app_state.registers.remove(&register);
+    }
+    if errors.is_empty() {
+        app_state.toast(format!(
+            "Pasted {} item(s) from the register '{}'",
+            files.len(),
+            register
+        ));
+    } else {
+        app_state.error_popup(
+            format!(
+                "Pasted {} of {} item(s) from the register '{}'",
+                files.len() - errors.len(),
+                files.len(),
+                register
+            ),
+            errors.join("\n"),
+        );
+    }
+    ActionResult::Valid
+}
+
+/// Deletes the file permanently
+pub(crate) fn delete_instantly(v: ActionData, file: &FileTreeNode) -> ActionResult {
+    let result = v.filesystem.delete(file.get_path_buf());
+
+    // reset the mode
+    v.app_state.get_mut().reset_state();
+    v.app_state.get_mut().invalidate_listing();
+
+    match result {
+        Ok(_) => ActionResult::Valid,
+        Err(err) => ActionResult::Invalid(format!("Error while deleting: {}", err)),
+    }
+}
+
+/// Moves the files, which are next to each other in the listing, to the trash
+pub(crate) fn trash_files(v: ActionData, files: &[FileTreeNode]) -> ActionResult {
+    for (num_trashed, file) in files.iter().enumerate() {
+        if let Err(err) = v.filesystem.trash(file.get_path_buf()) {
+            v.app_state.get_mut().invalidate_listing();
+            return ActionResult::Invalid(format!(
+                "Moved {} item(s) to the trash, but could not trash '{}': {}",
+                num_trashed,
+                file.get_simple_name(),
+                err
+            ));
+        }
+    }
+
+    // keep the cursor in the same place instead of jumping to the top
+    if let Some(start) = get_file_cursor_index(&files.first().cloned(), v.dir_items) {
+        let end = start + files.len();
+        v.app_state.get_mut().selected_file = v
+            .dir_items
+            .get(end)
+            .or_else(|| start.checked_sub(1).and_then(|i| v.dir_items.get(i)))
+            .cloned();
+    }
+    v.app_state
+        .get_mut()
+        .toast(format!("Moved {} item(s) to the trash", files.len()));
+    v.app_state.get_mut().invalidate_listing();
+    ActionResult::Valid
+}
+
+/// Deletes the files marked for deleting, and copies or moves the other marked files into the current directory
+pub(crate) fn apply_marks(v: ActionData) -> ActionResult {
+    let app_state = v.app_state.get_mut();
+    app_state.invalidate_listing();
+    // copied and cut files go to the directory that is open now, not the one they were marked in
+    let destination_dir = app_state.current_dir.get_path_buf().to_owned();
+    for (path, mark_type) in app_state.marked_files.clone() {
+        let destination = match path.file_name() {
+            Some(file_name) => destination_dir.join(file_name),
+            None => destination_dir.clone(),
+        };
+        let result = match mark_type {
+            MarkType::Delete => v.filesystem.delete(&path),
+            MarkType::Copy => v.filesystem.copy(&path, &destination),
+            MarkType::Cut => v.filesystem.move_path(&path, &destination),
+        };
+        if let Err(err) = result {
+            // the files that were not processed yet stay marked, so that the action can be retried
+            return ActionResult::Invalid(format!("Error executing the action: {}", err));
+        }
+        app_state.marked_files.remove(&path);
+    }
+    ActionResult::Valid
+}
+
+/// The contents of a small text file, or why they can not be copied
+fn read_text_for_clipboard(file: &FileTreeNode) -> Result<String, String> {
+    let size = file
+        .get_size()
+        .map_err(|err| format!("Could not read the file: {}", err))?;
+    if size > MAX_YANKED_FILE_SIZE {
+        return Err(format!(
+            "The file is too large to copy ({}, the limit is {})",
+            format_size(size),
+            format_size(MAX_YANKED_FILE_SIZE)
+        ));
+    }
+    let contents =
+        fs::read(file.get_path_buf()).map_err(|err| format!("Could not read the file: {}", err))?;
+    // text files do not contain NUL bytes, but almost all binary formats do
+    if contents.contains(&0) {
+        return Err(String::from("The file is binary"));
+    }
+    String::from_utf8(contents).map_err(|_| String::from("The file is not valid UTF-8 text"))
+}
+
+/// Stages or unstages the marked files, or the selected file if nothing is marked
+fn update_git_index(v: ActionData, is_staging: bool) -> ActionResult {
+    let repo = match discover_repository(v.app_state.current_dir.get_path_buf()) {
+        Some(repo) => repo,
+        None => return ActionResult::Invalid(String::from("Not in a git repository")),
+    };
+    let paths: Vec<_> = if v.app_state.marked_files.is_empty() {
+        match &v.app_state.selected_file {
+            Some(selected_file) => vec![selected_file.get_path_buf().to_owned()],
+            None => return ActionResult::Invalid(String::from("No file selected")),
+        }
+    } else {
+        v.app_state.marked_files.keys().cloned().collect()
+    };
+
+    // the index is changed by git itself rather than through `filesystem`, so a dry run has to skip it
+    let verb = if is_staging { "stage" } else { "unstage" };
+    if !v
+        .filesystem
+        .start_job(&format!("{} {} item(s) in git", verb, paths.len()))
+    {
+        v.app_state.get_mut().toast(format!(
+            "Dry run: skipped {} {} item(s)",
+            if is_staging { "staging" } else { "unstaging" },
+            paths.len()
+        ));
+        return ActionResult::Valid;
+    }
+
+    let result = if is_staging {
+        stage_paths(&repo, &paths)
+    } else {
+        unstage_paths(&repo, &paths)
+    };
+    if let Err(err) = result {
+        return ActionResult::Invalid(format!("Could not {} the files: {}", verb, err.message()));
+    }
+
+    let names: Vec<_> = paths
+        .iter()
+        .map(|path| FileTreeNode::new(path.clone()).get_simple_name().to_owned())
+        .collect();
+    let app_state = v.app_state.get_mut();
+    // the git statuses are updated together with the listing
+    app_state.invalidate_listing();
+    app_state.info_popup(
+        format!(
+            "{} {} item(s)",
+            if is_staging { "Staged" } else { "Unstaged" },
+            names.len()
+        ),
+        names.join("\n"),
+    );
+    ActionResult::Valid
+}
+
+/// Sorts by the column of the detailed view next to the one that the items are sorted by now
+fn sort_by_adjacent_column(v: ActionData, to_the_right: bool) -> ActionResult {
+    if v.app_state.view_mode != ViewMode::Detailed {
+        return ActionResult::Invalid(String::from(
+            "The columns are only shown in the detailed view",
+        ));
+    }
+    let sort_key = v.app_state.get_sort_key().get_adjacent_column(to_the_right);
+    // like with `cycle_sort`, the cursor stays on the same file and the choice outlives the directory
+    v.app_state.get_mut().sort_key = sort_key;
+    v.app_state.get_mut().dir_config.sort_key = None;
+    ActionResult::Valid
+}
+
+/// Marks the selected file with the given type, or removes the mark if it already has it.
+/// During a visual selection, marks every item in it instead
+fn toggle_mark(v: ActionData, mark_type: MarkType) -> ActionResult {
+    if let Some(visual_range) = get_visual_range(v.app_state, v.dir_items) {
+        let app_state = v.app_state.get_mut();
+        app_state.marked_files.extend(
+            v.dir_items[visual_range]
+                .iter()
+                .map(|item| (item.get_path_buf().to_owned(), mark_type)),
+        );
+        app_state.visual_anchor = None;
+        return ActionResult::Valid;
+    }
+
+    let selected_path = match &v.app_state.selected_file {
+        Some(selected_file) => selected_file.get_path_buf().to_owned(),
+        None => return ActionResult::Invalid(String::from("No file selected")),
+    };
+
+    let marked_files = &mut v.app_state.get_mut().marked_files;
+    if marked_files.get(&selected_path) == Some(&mark_type) {
+        marked_files.remove(&selected_path);
+    } else {
+        marked_files.insert(selected_path, mark_type);
+    }
+    ActionResult::Valid
+}
+
+/// The items between the visual anchor and the cursor, if there is a visual selection
+pub(crate) fn get_visual_range(
+    app_state: &AppState,
+    dir_items: &[FileTreeNode],
+) -> Option<RangeInclusive<usize>> {
+    let cursor_index = get_file_cursor_index(&app_state.selected_file, dir_items)?;
+    // the listing might have become shorter since the anchor was set
+    let anchor = app_state
+        .visual_anchor?
+        .min(dir_items.len().checked_sub(1)?);
+    Some(anchor.min(cursor_index)..=anchor.max(cursor_index))
+}
+
+/// The mark type used by the actions that mark several files at once: the same as the existing marks, or delete if there are none
+pub(crate) fn get_current_mark_type(app_state: &AppState) -> MarkType {
+    app_state
+        .marked_files
+        .values()
+        .next()
+        .copied()
+        .unwrap_or(MarkType::Delete)
+}
+
+/// Enters the selected item if it is a directory or opens it in the editor if it is a file
+fn open_selected(v: ActionData) -> ActionResult {
+    match &v.app_state.selected_file {
+        Some(selected_file) if selected_file.is_dir() => enter_selected_directory(v),
+        Some(_) => open_selected_file(v),
+        None => ActionResult::Invalid(String::from("No file selected")),
+    }
+}
+
+fn enter_selected_directory(v: ActionData) -> ActionResult {
+    if let Some(selected_file_tree_node) = &v.app_state.selected_file {
+        if !selected_file_tree_node.is_dir() {
+            return ActionResult::Invalid(String::from("The selected item is not a directory"));
+        }
+        // if we do not follow symlinks, keep the symlink in the path so that `left` returns to where we came from
+        let new_dir = if v.app_state.follow_symlinks && selected_file_tree_node.is_symlink() {
+            match canonicalize(selected_file_tree_node.get_path_buf()) {
+                Ok(path) => FileTreeNode::new(path),
+                Err(err) => {
+                    return ActionResult::Invalid(format!("Could not resolve the symlink: {}", err))
+                }
+            }
+        } else {
+            selected_file_tree_node.clone()
+        };
+        // open the directory
+        v.app_state.get_mut().change_dir(new_dir);
+        ActionResult::Valid
+    } else {
+        ActionResult::Invalid(String::from("No file selected"))
+    }
+}
+
+/// Opens the example config that fphile was built with in the editor, so that every option can be looked up.
+/// It is a read-only copy, since changing it would not change anything
+fn help_config(v: ActionData) -> ActionResult {
+    let file_editor_options = match &v.config.default_file_editor_command {
+        Some(file_editor_options) => file_editor_options,
+        None => {
+            return ActionResult::Invalid(String::from(
+                "The config file does not contain a command to open files",
+            ))
+        }
+    };
+
+    // removed with the copy in it once the editor exits
+    let written = PrivateTempDir::new("fphile-example-config").and_then(|temp_dir| {
+        let example_path =
+            temp_dir.create_file("example_config.toml", DEFAULT_CONFIG.as_bytes())?;
+        let mut permissions = fs::metadata(&example_path)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&example_path, permissions)?;
+        Ok((temp_dir, example_path))
+    });
+    let (_temp_dir, example_path) = match written {
+        Ok(written) => written,
+        Err(err) => {
+            return ActionResult::Invalid(format!("Could not write the example config: {}", err))
+        }
+    };
+    let example_path_string = example_path.to_string_lossy().into_owned();
+    let options = file_editor_options.iter().map(|option| {
+        option
+            .replace("<FILE>", &example_path_string)
+            .replace("<LINE>", "1")
+    });
+    let result = run_command_in_foreground(
+        options,
+        v.terminal,
+        v.app_state.current_dir.get_path_buf(),
+        &v.app_state.interrupt_signal_receiver,
+        v.config.command_status_refresh_secs,
+        false,
+    );
+
+    v.app_state.get_mut().reset_state();
+    v.app_state.get_mut().invalidate_listing();
+    if let Err(err) = result {
+        v.app_state
+            .get_mut()
+            .error_popup(String::from("Could not open the example config"), err);
+    }
+    ActionResult::Valid
+}
+
+/// Lets the names of the marked files (or of everything in the current directory) be edited as lines in the editor,
+/// and renames the files whose lines were changed
+fn bulk_edit(v: ActionData) -> ActionResult {
+    let paths: Vec<_> = if v.app_state.marked_files.is_empty() {
+        v.dir_items
+            .iter()
+            .map(|item| item.get_path_buf().to_owned())
+            .collect()
+    } else {
+        v.app_state.marked_files.keys().cloned().collect()
+    };
+    if paths.is_empty() {
+        return ActionResult::Invalid(String::from("There is nothing to rename"));
+    }
+    let names: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            path.file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        })
+        .collect();
+    if let Some(name) = names.iter().find(|name| name.contains('\n')) {
+        return ActionResult::Invalid(format!(
+            "'{}' has a line break in its name, so the names can not be edited as lines",
+            name.escape_default()
+        ));
+    }
+    let file_editor_options = match &v.config.default_file_editor_command {
+        Some(file_editor_options) => file_editor_options,
+        None => {
+            return ActionResult::Invalid(String::from(
+                "The config file does not contain a command to open files",
+            ))
+        }
+    };
+
+    // the list is a scratch file rather than a change to the files, so it does not go through `filesystem`.
+    // It is removed with its directory at the end
+    let written = PrivateTempDir::new("fphile-bulk-edit").and_then(|temp_dir| {
+        let list_path = temp_dir.create_file("names.txt", (names.join("\n") + "\n").as_bytes())?;
+        Ok((temp_dir, list_path))
+    });
+    let (_temp_dir, list_path) = match written {
+        Ok(written) => written,
+        Err(err) => {
+            return ActionResult::Invalid(format!("Could not write the list of names: {}", err))
+        }
+    };
+    let list_path_string = list_path.to_string_lossy().into_owned();
+    let options = file_editor_options.iter().map(|option| {
+        option
+            .replace("<FILE>", &list_path_string)
+            .replace("<LINE>", "1")
+    });
+    let result = run_command_in_foreground(
+        options,
+        v.terminal,
+        v.app_state.current_dir.get_path_buf(),
+        &v.app_state.interrupt_signal_receiver,
+        v.config.command_status_refresh_secs,
+        false,
+    );
+    let edited_text = fs::read_to_string(&list_path);
+
+    v.app_state.get_mut().reset_state();
+    if let Err(err) = result {
+        v.app_state
+            .get_mut()
+            .error_popup(String::from("Could not edit the names"), err);
+        return ActionResult::Valid;
+    }
+    let edited_text = match edited_text {
+        Ok(edited_text) => edited_text,
+        Err(err) => {
+            return ActionResult::Invalid(format!("Could not read the edited names: {}", err))
+        }
+    };
+    let renames = match get_edited_names(&paths, &edited_text) {
+        Ok(renames) => renames,
+        Err(err) => return ActionResult::Invalid(format!("Nothing was renamed: {}", err)),
+    };
+    let case_insensitive_names = v.app_state.case_insensitive_names;
+    if let Some(conflict) = find_conflict(&renames, case_insensitive_names) {
+        return ActionResult::Invalid(format!("Nothing was renamed: {}", conflict));
+    }
+
+    v.app_state.get_mut().invalidate_listing();
+    match rename_all(&renames, v.filesystem, case_insensitive_names) {
+        Ok(num_renamed) => {
+            v.app_state
+                .get_mut()
+                .toast(format!("Renamed {} item(s)", num_renamed));
+            ActionResult::Valid
+        }
+        Err(err) => ActionResult::Invalid(err),
+    }
+}
+
+fn open_selected_file(v: ActionData) -> ActionResult {
+    if let Some(selected_file_tree_node) = &v.app_state.selected_file {
+        if selected_file_tree_node.is_dir() {
+            return ActionResult::Invalid(String::from("The selected item is not a file"));
+        }
+
+        // open it where the search in the preview got to
+        let line = v
+            .app_state
+            .get_preview_search_line(selected_file_tree_node.get_path_buf());
+
+        // editors can freeze while loading huge files, so make sure that it is not a mistake
+        let threshold_mb = v.config.large_file_threshold_mb.unwrap_or(100);
+        if let Ok(size) = selected_file_tree_node.get_size() {
+            if size > threshold_mb.saturating_mul(1024 * 1024) {
+                let file = selected_file_tree_node.clone();
+                v.app_state.get_mut().reset_state();
+
+                v.app_state.get_mut().mode = Mode::OverlayMode {
+                    background_mode: SimpleMode::Normal, //NOTE: we reset this a couple lines above, so it has to be normal mode. It is also within the normal mode key bindings block.
+                    overlay_mode: OverlayMode::OpenLargeFileConfirm { file, size, line },
+                };
+                return ActionResult::Valid;
+            }
+        }
+
+        let file = selected_file_tree_node.clone();
+        open_file(v, &file, line)
+    } else {
+        ActionResult::Invalid(String::from("No file selected"))
+    }
+}
+
+/// Extracts the archive in the background. The progress is shown in the status bar
+pub(crate) fn start_extracting(
+    v: ActionData,
+    file: &FileTreeNode,
+    format: ArchiveFormat,
+    destination: PathBuf,
+) -> ActionResult {
+    if v.app_state.archive_job.is_some() {
+        return ActionResult::Invalid(String::from("Another archive is still being worked on"));
+    }
+    let description = format!("extracting '{}'", file.get_simple_name());
+    if !v.filesystem.start_job(&format!(
+        "extract '{}' into '{}'",
+        file.get_path_buf().display(),
+        destination.display()
+    )) {
+        v.app_state
+            .get_mut()
+            .toast(format!("Dry run: skipped {}", description));
+        return ActionResult::Valid;
+    }
+    let archive_path = file.get_path_buf().to_owned();
+    v.app_state.get_mut().archive_job =
+        Some(ArchiveJob::new(description, move |report_progress| {
+            extract_archive(&archive_path, &destination, format, report_progress)
+        }));
+    ActionResult::Valid
+}
+
+/// Opens the file with the command for its type, or the editor if there is none.
+/// `<LINE>` in the command is replaced with `line`, or with 1 if it is not known
+pub(crate) fn open_file(v: ActionData, file: &FileTreeNode, line: Option<usize>) -> ActionResult {
+    let open_command = file
+        .get_path_buf()
+        .extension()
+        .and_then(|extension| {
+            v.config
+                .open_commands
+                .get(&extension.to_string_lossy().to_lowercase())
+        })
+        .or(v.config.default_file_editor_command.as_ref());
+    if let Some(file_editor_options) = open_command {
+        let file_name = file.get_simple_name();
+        let line = line.unwrap_or(1).to_string();
+        let options = file_editor_options
+            .iter()
+            .map(|option| option.replace("<FILE>", file_name).replace("<LINE>", &line));
+
+        // the file is not in the current directory if it is inside an expanded directory
+        let working_dir = file
+            .get_path_buf()
+            .parent()
+            .unwrap_or(v.app_state.current_dir.get_path_buf());
+        let result = run_command_in_foreground(
+            options,
+            v.terminal,
+            working_dir,
+            &v.app_state.interrupt_signal_receiver,
+            v.config.command_status_refresh_secs,
+            false,
+        );
+        // the editor might have changed the files
+        v.app_state.get_mut().invalidate_listing();
+        if let Err(err) = result {
+            v.app_state
+                .get_mut()
+                .error_popup(format!("Could not open {}", file.get_simple_name()), err);
+        }
+
+        ActionResult::Valid
+    } else {
+        ActionResult::Invalid(String::from(
+            "Can not open the file because the config file does not contain a command to open files",
+        ))
+    }
+}
+
+/// Opens the selected item, or the current directory if nothing is selected, with a GUI program
+fn open_in_os(v: ActionData) -> ActionResult {
+    let path = match &v.app_state.selected_file {
+        Some(selected_file) => selected_file.get_path_buf(),
+        None => v.app_state.current_dir.get_path_buf(),
+    }
+    .to_string_lossy()
+    .to_string();
+
+    let options: Vec<String> = match &v.config.os_open_command {
+        Some(os_open_command) => os_open_command.clone(),
+        None => DEFAULT_OS_OPEN_COMMAND
+            .iter()
+            .map(|option| option.to_string())
+            .collect(),
+    }
+    .iter()
+    .map(|option| option.replace("<FILE>", &path))
+    .collect();
+    if options.is_empty() {
+        return ActionResult::Invalid(String::from("The command to open files with is empty"));
+    }
+    // it is a separate GUI program, so do not give it the terminal and do not wait for it
+    spawn_detached(options, v.app_state.current_dir.get_path_buf())
+}
+
+/// Opens the selected directory, or the current one if a file is selected, in a new pane of tmux or zellij
+fn open_multiplexer_pane(v: ActionData) -> ActionResult {
+    let default_command = match MULTIPLEXERS
+        .iter()
+        .find(|(env_var, _)| env::var_os(env_var).is_some())
+    {
+        Some((_, default_command)) => default_command,
+        None => {
+            return ActionResult::Invalid(String::from(
+                "Not running inside a terminal multiplexer like tmux or zellij",
+            ))
+        }
+    };
+    let dir = match &v.app_state.selected_file {
+        Some(selected_file) if selected_file.is_dir() => selected_file.get_path_buf(),
+        _ => v.app_state.current_dir.get_path_buf(),
+    };
+    let dir_path = dir.to_string_lossy();
+
+    let options: Vec<String> = match &v.config.multiplexer_command {
+        Some(multiplexer_command) => multiplexer_command.clone(),
+        None => default_command
+            .iter()
+            .map(|option| option.to_string())
+            .collect(),
+    }
+    .iter()
+    .map(|option| option.replace("<DIR>", &dir_path))
+    .collect();
+    if options.is_empty() {
+        return ActionResult::Invalid(String::from("The multiplexer command is empty"));
+    }
+    // the multiplexer draws the new pane itself, so this only has to start the command
+    spawn_detached(options, dir)
+}
+
+/// Opens a new terminal window in the current directory, next to this one
+fn open_terminal(v: ActionData) -> ActionResult {
+    let dir = v.app_state.current_dir.get_path_buf();
+    let dir_path = dir.to_string_lossy();
+    let options: Vec<String> = match &v.config.terminal_command {
+        Some(terminal_command) => terminal_command
+            .iter()
+            .map(|option| option.replace("<DIR>", &dir_path))
+            .collect(),
+        // most terminals start in the directory that they were started from
+        None => match env::var("TERMINAL") {
+            Ok(terminal) if !terminal.is_empty() => vec![terminal],
+            _ => {
+                return ActionResult::Invalid(String::from(
+                    "$TERMINAL is not set. Set it or terminal_command in the config file",
+                ))
+            }
+        },
+    };
+    if options.is_empty() {
+        return ActionResult::Invalid(String::from("The terminal command is empty"));
+    }
+    spawn_detached(options, dir)
+}
+
+/// Highlights the best or the worst search result, which ENTER then selects
+fn move_search_cursor(v: ActionData, to_last: bool) -> ActionResult {
+    if !matches!(
+        v.app_state.mode,
+        Mode::TextInputMode {
+            text_input_type: TextInput::Search
+        }
+    ) {
+        return ActionResult::Invalid(String::from("Only the search results can be gone through"));
+    }
+    // the results are sorted from the best match to the worst
+    let index = if to_last {
+        v.dir_items.len().saturating_sub(1)
+    } else {
+        0
+    };
+    let query = v.app_state.entered_text.clone();
+    v.app_state.get_mut().search_cursor = Some((query, index));
+    ActionResult::Valid
+}
+
+/// Starts the command without giving it the terminal and without waiting for it
+fn spawn_detached(mut options: Vec<String>, working_dir: &Path) -> ActionResult {
+    let program = options.remove(0);
+    let spawn_result = Command::new(&program)
+        .args(options)
+        .current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match spawn_result {
+        Ok(mut child) => {
+            // reap it when it exits, so that it does not stay around as a zombie process
+            thread::spawn(move || child.wait());
+            ActionResult::Valid
+        }
+        Err(err) => ActionResult::Invalid(format!("Could not run '{}': {}", program, err)),
+    }
+}
+
+pub(crate) static TEXT_MODE_ACTION_MAP: Lazy<ActionNameMap> = Lazy::new(|| {
+    let mut m: ActionNameMap = BTreeMap::new();
+    m.insert(
+        String::from("noop"),
+        Box::new(|v| {
+            // push the new typed characters
+            v.app_state
+                .get_mut()
+                .copy_input_manager_verbs_to_entered_text();
+            v.app_state.get_mut().input_reader.clear();
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("complete"),
+        Box::new(|v| {
+            match &v.app_state.mode {
+                Mode::OverlayMode {
+                    overlay_mode:
+                        OverlayMode::GoToPath
+                        | OverlayMode::CopyRelativePath { .. }
+                        | OverlayMode::CreateFile { .. }
+                        | OverlayMode::CreateDirectory
+                        | OverlayMode::Rename { .. },
+                    ..
+                } => {}
+                _ => return ActionResult::Invalid(String::from("Nothing to complete here")),
+            };
+
+            // pressing tab again without editing the text goes to the next match
+            let app_state = v.app_state.get_mut();
+            if let Some(completion) = &mut app_state.path_completion {
+                if completion.completed_text == app_state.entered_text {
+                    let index = completion
+                        .index
+                        .map_or(0, |index| (index + 1) % completion.candidates.len());
+                    completion.index = Some(index);
+                    completion.completed_text = completion.candidates[index].clone();
+                    let completed_text = completion.completed_text.clone();
+                    app_state.set_entered_text(completed_text);
+                    return ActionResult::Valid;
+                }
+            }
+
+            let candidates = get_path_completions(
+                app_state.current_dir.get_path_buf(),
+                &app_state.entered_text,
+            );
+            match candidates.len() {
+                0 => {
+                    app_state.path_completion = None;
+                    ActionResult::Invalid(String::from("No matches found"))
+                }
+                1 => {
+                    app_state.set_entered_text(candidates[0].clone());
+                    app_state.path_completion = None;
+                    ActionResult::Valid
+                }
+                _ => {
+                    let common_prefix = get_common_prefix(&candidates).to_string();
+                    // if the prefix does not add anything, go straight to the first match
+                    let index = if common_prefix == app_state.entered_text {
+                        Some(0)
+                    } else {
+                        None
+                    };
+                    let completed_text = match index {
+                        Some(index) => candidates[index].clone(),
+                        None => common_prefix,
+                    };
+
+                    app_state.set_entered_text(completed_text.clone());
+                    app_state.path_completion = Some(PathCompletion {
+                        candidates,
+                        index,
+                        completed_text,
+                    });
+                    ActionResult::Valid
+                }
+            }
+        }),
+    );
+    m.insert(
+        String::from("toggle_extension"),
+        Box::new(|v| {
+            let (old_file, keep_extension) = match &mut v.app_state.get_mut().mode {
+                Mode::OverlayMode {
+                    overlay_mode:
+                        OverlayMode::Rename {
+                            old_file,
+                            keep_extension,
+                        },
+                    ..
+                } => {
+                    *keep_extension = !*keep_extension;
+                    (old_file.to_owned(), *keep_extension)
+                }
+                _ => {
+                    return ActionResult::Invalid(String::from(
+                        "The extension can only be toggled when renaming",
+                    ))
+                }
+            };
+            let extension = match old_file.get_extension() {
+                Some(extension) => format!(".{}", extension),
+                None => return ActionResult::Invalid(String::from("The file has no extension")),
+            };
+
+            // move the extension between the typed text and the part that is added automatically
+            let entered_text = v.app_state.entered_text.clone();
+            let new_text = if keep_extension {
+                entered_text
+                    .strip_suffix(&extension)
+                    .map(ToOwned::to_owned)
+                    .unwrap_or(entered_text)
+            } else {
+                entered_text + &extension
+            };
+            v.app_state.get_mut().set_entered_text(new_text);
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("toggle_regex"),
+        Box::new(|v| match &mut v.app_state.get_mut().mode {
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::SubstituteRename { is_regex, .. },
+                ..
+            } => {
+                *is_regex = !*is_regex;
+                ActionResult::Valid
+            }
+            _ => ActionResult::Invalid(String::from(
+                "Regex can only be toggled when renaming with a pattern",
+            )),
+        }),
+    );
+    m.insert(
+        String::from("search_first_match"),
+        Box::new(|v| move_search_cursor(v, false)),
+    );
+    m.insert(
+        String::from("search_last_match"),
+        Box::new(|v| move_search_cursor(v, true)),
+    );
+    m.insert(
+        String::from("delete_last_char"),
+        Box::new(|v| {
+            // the character before the cursor
+            if let Some(previous_char_pos) = v.app_state.get_previous_char_pos() {
+                let app_state = v.app_state.get_mut();
+                app_state.entered_text.remove(previous_char_pos);
+                app_state.cursor_pos = previous_char_pos;
+            }
+
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("paste_from_clipboard"),
+        Box::new(|v| match v.app_state.get_mut().get_clipboard_text() {
+            Ok(text) => {
+                // the inputs are single lines
+                let text: String = text.chars().filter(|c| *c != '\n' && *c != '\r').collect();
+                v.app_state.get_mut().insert_text_at_cursor(&text);
+                ActionResult::Valid
+            }
+            Err(err) => {
+                ActionResult::Invalid(format!("Could not paste from the clipboard: {}", err))
+            }
+        }),
+    );
+    m.insert(
+        String::from("delete_word"),
+        Box::new(|v| {
+            let app_state = v.app_state.get_mut();
+            let text_before_cursor = &app_state.entered_text[..app_state.cursor_pos];
+            // like in shells, skip the whitespace and then delete until the next whitespace or slash
+            let word_start = text_before_cursor
+                .trim_end()
+                .trim_end_matches('/')
+                .rfind(|c: char| c.is_whitespace() || c == '/')
+                .map_or(0, |i| i + 1);
+
+            app_state
+                .entered_text
+                .replace_range(word_start..app_state.cursor_pos, "");
+            app_state.cursor_pos = word_start;
+
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("cursor_left"),
+        Box::new(|v| {
+            if let Some(previous_char_pos) = v.app_state.get_previous_char_pos() {
+                v.app_state.get_mut().cursor_pos = previous_char_pos;
+            }
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("cursor_right"),
+        Box::new(|v| {
+            if let Some(next_char_pos) = v.app_state.get_next_char_pos() {
+                v.app_state.get_mut().cursor_pos = next_char_pos;
+            }
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("cursor_to_start"),
+        Box::new(|v| {
+            v.app_state.get_mut().cursor_pos = 0;
+            ActionResult::Valid
+        }),
+    );
+    m.insert(
+        String::from("cursor_to_end"),
+        Box::new(|v| {
+            v.app_state.get_mut().cursor_pos = v.app_state.entered_text.len();
+            ActionResult::Valid
+        }),
+    );
+
+    m
+});
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::{
+        compile_time_settings::UNNAMED_REGISTER,
+        directory_tree::{get_file_cursor_index, FileTreeNode},
+        filesystem::Filesystem,
+        helper_types::Register,
+        modes::sort_dir_items,
+        test_util::TestApp,
+        util::PrivateTempDir,
+    };
+
+    use super::ActionResult;
+
+    const ROOT: &str = "/fphile-test-root";
+
+    #[test]
+    fn create_file_writes_an_empty_file() {
+        let root = Path::new(ROOT);
+        let mut app = TestApp::new(root);
+
+        assert!(matches!(
+            app.run_action("create_file", &[]),
+            ActionResult::Valid
+        ));
+        assert!(matches!(app.submit("notes"), ActionResult::Valid));
+
+        assert_eq!(
+            app.filesystem.get_contents(&root.join("notes")),
+            Some(&b""[..])
+        );
+    }
+
+    #[test]
+    fn create_file_uses_the_template() {
+        let root = Path::new(ROOT);
+        let mut app = TestApp::new(root);
+
+        app.run_action("create_file", &[]);
+        app.submit("dir/script.sh");
+
+        assert!(app.filesystem.is_dir(&root.join("dir")));
+        assert_eq!(
+            app.filesystem.get_contents(&root.join("dir/script.sh")),
+            Some(&b"#!/bin/sh\n"[..])
+        );
+    }
+
+    #[test]
+    fn create_directory_creates_the_parents() {
+        let root = Path::new(ROOT);
+        let mut app = TestApp::new(root);
+
+        app.run_action("create_directory", &[]);
+        assert!(matches!(app.submit("a/b"), ActionResult::Valid));
+
+        assert!(app.filesystem.is_dir(&root.join("a")));
+        assert!(app.filesystem.is_dir(&root.join("a/b")));
+    }
+
+    #[test]
+    fn rename_keeps_the_contents() {
+        let root = Path::new(ROOT);
+        let mut app = TestApp::new(root);
+        app.config.rename_keeps_extension = true;
+        app.filesystem.add_file(&root.join("old.txt"), b"contents");
+        app.select(&root.join("old.txt"));
+
+        app.run_action("rename", &[]);
+        // only the stem is typed
+        assert_eq!(app.app_state.entered_text, "old");
+        assert!(matches!(app.submit("new"), ActionResult::Valid));
+
+        assert!(!app.filesystem.exists(&root.join("old.txt")));
+        assert_eq!(
+            app.filesystem.get_contents(&root.join("new.txt")),
+            Some(&b"contents"[..])
+        );
+    }
+
+    #[test]
+    fn delete_instantly_asks_first() {
+        let root = Path::new(ROOT);
+        let mut app = TestApp::new(root);
+        app.config.confirmations.delete = true;
+        app.filesystem.create_dir_all(&root.join("dir")).unwrap();
+        app.filesystem.add_file(&root.join("dir/file"), b"");
+        app.select(&root.join("dir"));
+
+        app.run_action("delete_instantly", &[]);
+        assert!(app.filesystem.exists(&root.join("dir/file")));
+        assert!(matches!(app.run_action("select", &[]), ActionResult::Valid));
+
+        assert!(!app.filesystem.exists(&root.join("dir")));
+        assert!(!app.filesystem.exists(&root.join("dir/file")));
+        assert!(app.filesystem.exists(root));
+    }
+
+    #[test]
+    fn read_only_mode_refuses_changes() {
+        let root = Path::new(ROOT);
+        let mut app = TestApp::new(root);
+        app.app_state.get_mut().is_read_only = true;
+        app.filesystem.add_file(&root.join("file"), b"");
+        app.select(&root.join("file"));
+
+        assert!(matches!(
+            app.run_action("delete_instantly", &[]),
+            ActionResult::Invalid(_)
+        ));
+        assert!(matches!(
+            app.run_action("create_file", &[]),
+            ActionResult::Invalid(_)
+        ));
+        assert!(app.filesystem.exists(&root.join("file")));
+    }
+
+    #[test]
+    fn paste_refuses_names_that_only_differ_in_case() {
+        let root = Path::new(ROOT);
+        let mut app = TestApp::new(root);
+        app.app_state.get_mut().case_insensitive_names = true;
+        app.filesystem.create_dir_all(&root.join("a")).unwrap();
+        app.filesystem.create_dir_all(&root.join("b")).unwrap();
+        app.filesystem.add_file(&root.join("a/File"), b"");
+        app.filesystem.add_file(&root.join("b/file"), b"");
+        let files = vec![
+            FileTreeNode::new(root.join("a/File")),
+            FileTreeNode::new(root.join("b/file")),
+        ];
+        app.app_state.get_mut().registers.insert(
+            UNNAMED_REGISTER,
+            Register {
+                files,
+                is_cut: false,
+            },
+        );
+
+        assert!(matches!(
+            app.run_action("paste_from_register", &[]),
+            ActionResult::Invalid(_)
+        ));
+        assert!(!app.filesystem.exists(&root.join("File")));
+        assert!(!app.filesystem.exists(&root.join("file")));
+    }
+
+    #[test]
+    fn selected_file_survives_sorting_and_reversing() {
+        let root = Path::new(ROOT);
+        let mut app = TestApp::new(root);
+        let paths: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| root.join(name))
+            .collect();
+        let mut dir_items: Vec<_> = paths
+            .iter()
+            .map(|path| FileTreeNode::new(path.clone()))
+            .collect();
+        app.select(&root.join("b"));
+
+        let mut cursor_indices = Vec::new();
+        for action in ["reverse_sort", "cycle_sort", "reverse_sort", "cycle_sort"] {
+            assert!(matches!(
+                app.run_action(action, &paths),
+                ActionResult::Valid
+            ));
+            sort_dir_items(
+                &mut dir_items,
+                &app.app_state.get_sort_key(),
+                app.app_state.get_is_sort_reversed(),
+                app.config.group_directories,
+            );
+
+            let selected_file = &app.app_state.selected_file;
+            assert_eq!(
+                selected_file.as_ref().map(|file| file.get_path_buf()),
+                Some(&root.join("b"))
+            );
+            let cursor_index = get_file_cursor_index(selected_file, &dir_items)
+                .expect("The selected file should still be listed");
+            assert_eq!(dir_items[cursor_index].get_path_buf(), &root.join("b"));
+            cursor_indices.push(cursor_index);
+        }
+        // the cursor follows the file rather than staying at the same row
+        assert_eq!(cursor_indices[0], 2);
+    }
+
+    #[test]
+    fn expanding_leaves_the_listing_to_the_background() {
+        let temp_dir = PrivateTempDir::new("fphile-test-expand").unwrap();
+        let dir = temp_dir.get_path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        temp_dir.create_file("dir/file", b"").unwrap();
+        let mut app = TestApp::new(temp_dir.get_path());
+        app.select(&dir);
+
+        assert!(matches!(app.run_action("expand", &[]), ActionResult::Valid));
+
+        // it is shown as empty until the listing in the main loop fills it in
+        assert!(app
+            .app_state
+            .expanded_dirs
+            .get(&dir)
+            .is_some_and(|children| children.is_empty()));
+        assert!(app.app_state.loading_expanded_dirs.contains_key(&dir));
+    }
+}
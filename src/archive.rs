@@ -0,0 +1,415 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Instant, SystemTime},
+};
+
+use chrono::{Datelike, Local, Timelike};
+use crossbeam_channel::{unbounded, Receiver, TryRecvError};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use zip::{
+    read::ZipFile, write::SimpleFileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter,
+};
+
+use crate::{directory_tree::get_spinner_frame, helper_types::ArchiveFormat};
+
+const ZIP_MAGIC: &[u8] = b"PK";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+// tar files have no magic bytes at the start, only in the header of the first entry
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+enum ArchiveJobUpdate {
+    // how many entries are done out of how many
+    Progress(usize, usize),
+    Finished(io::Result<()>),
+}
+
+/// Creates or extracts an archive on a background thread, reporting how far it got
+pub(crate) struct ArchiveJob {
+    description: String,
+    receiver: Receiver<ArchiveJobUpdate>,
+    progress: Option<(usize, usize)>,
+    started_at: Instant,
+}
+
+impl ArchiveJob {
+    /// `work` is run on a background thread and is given a function to report its progress with
+    pub(crate) fn new<F>(description: String, work: F) -> Self
+    where
+        F: FnOnce(&dyn Fn(usize, usize)) -> io::Result<()> + Send + 'static,
+    {
+        let (sender, receiver) = unbounded();
+        thread::spawn(move || {
+            let progress_sender = sender.clone();
+            let report_progress = move |done_num, total_num| {
+                let _ = progress_sender.send(ArchiveJobUpdate::Progress(done_num, total_num));
+            };
+            let _ = sender.send(ArchiveJobUpdate::Finished(work(&report_progress)));
+        });
+        ArchiveJob {
+            description,
+            receiver,
+            progress: None,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub(crate) fn get_description(&self) -> &str {
+        &self.description
+    }
+
+    /// Takes in the progress reported since the last call. Returns the result once the job has finished
+    pub(crate) fn poll(&mut self) -> Option<io::Result<()>> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(ArchiveJobUpdate::Progress(done_num, total_num)) => {
+                    self.progress = Some((done_num, total_num))
+                }
+                Ok(ArchiveJobUpdate::Finished(result)) => return Some(result),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    return Some(Err(io::Error::other("The archiving stopped unexpectedly")))
+                }
+            }
+        }
+    }
+
+    /// e.g. "⠋ archiving 'photos.zip' 40%"
+    pub(crate) fn get_status_text(&self) -> String {
+        let progress = match self.progress {
+            Some((done_num, total_num)) => format!(" {}%", done_num * 100 / total_num.max(1)),
+            None => String::new(),
+        };
+        format!(
+            "{} {}{}",
+            get_spinner_frame(self.started_at),
+            self.description,
+            progress
+        )
+    }
+}
+
+/// Lists the entries to put into the archive with their names inside it. Directories come before their contents,
+/// and each of `paths` is put at the top level of the archive
+fn collect_entries(paths: &[PathBuf]) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    fn collect(path: &Path, name: PathBuf, ret: &mut Vec<(PathBuf, PathBuf)>) -> io::Result<()> {
+        // symlinks are stored as links, so a link to a directory is not entered
+        let is_dir = fs::symlink_metadata(path)?.is_dir();
+        ret.push((path.to_owned(), name.clone()));
+        if is_dir {
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                collect(&entry.path(), name.join(entry.file_name()), ret)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut ret = vec![];
+    for path in paths {
+        let name = path
+            .file_name()
+            .map(PathBuf::from)
+            .ok_or_else(|| io::Error::other(format!("Can not archive '{}'", path.display())))?;
+        collect(path, name, &mut ret)?;
+    }
+    Ok(ret)
+}
+
+/// The name of an entry inside a zip file, which always uses forward slashes
+fn get_zip_entry_name(name: &Path) -> String {
+    name.iter()
+        .map(|component| component.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Puts the files and directories into a new archive at `destination`. A half-written archive is removed on errors
+pub(crate) fn create_archive(
+    paths: &[PathBuf],
+    destination: &Path,
+    format: ArchiveFormat,
+    report_progress: &dyn Fn(usize, usize),
+) -> io::Result<()> {
+    let entries = collect_entries(paths)?;
+    // never overwrite an existing file
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(destination)?;
+
+    let result = match format {
+        ArchiveFormat::Zip => write_zip(file, &entries, report_progress),
+        ArchiveFormat::Tar => write_tar(file, &entries, report_progress).map(|_| ()),
+        ArchiveFormat::TarGz => write_tar(
+            GzEncoder::new(file, Compression::default()),
+            &entries,
+            report_progress,
+        )
+        .and_then(|encoder| encoder.finish())
+        .map(|_| ()),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(destination);
+    }
+    result
+}
+
+fn write_zip(
+    file: File,
+    entries: &[(PathBuf, PathBuf)],
+    report_progress: &dyn Fn(usize, usize),
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    for (i, (path, name)) in entries.iter().enumerate() {
+        let name = get_zip_entry_name(name);
+        let metadata = fs::symlink_metadata(path)?;
+        let file_type = metadata.file_type();
+        // without this, every entry is dated 1980
+        let options = match metadata.modified().ok().and_then(get_zip_date_time) {
+            Some(date_time) => options.last_modified_time(date_time),
+            None => options,
+        };
+        if file_type.is_dir() {
+            zip.add_directory(name, options)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(path)?;
+            zip.add_symlink(name, target.to_string_lossy(), options)?;
+        } else {
+            zip.start_file(name, options)?;
+            io::copy(&mut File::open(path)?, &mut zip)?;
+        }
+        report_progress(i + 1, entries.len());
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Zip files store the local time. None for times that zip can not represent, e.g. before 1980
+fn get_zip_date_time(time: SystemTime) -> Option<DateTime> {
+    let time = chrono::DateTime::<Local>::from(time);
+    DateTime::from_date_and_time(
+        u16::try_from(time.year()).ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+/// Returns the writer, so that e.g. a compressor can be finished
+fn write_tar<W: Write>(
+    writer: W,
+    entries: &[(PathBuf, PathBuf)],
+    report_progress: &dyn Fn(usize, usize),
+) -> io::Result<W> {
+    let mut tar = tar::Builder::new(writer);
+    tar.follow_symlinks(false);
+    for (i, (path, name)) in entries.iter().enumerate() {
+        if fs::symlink_metadata(path)?.is_dir() {
+            // only the directory itself, since its contents are separate entries
+            tar.append_dir(name, path)?;
+        } else {
+            tar.append_path_with_name(path, name)?;
+        }
+        report_progress(i + 1, entries.len());
+    }
+    tar.into_inner()
+}
+
+/// Reads the first bytes of the file to find out which kind of archive it is, if any.
+/// A gzip file is only taken to be a tar.gz archive if its extension says so, since it could hold anything else
+pub(crate) fn detect_archive_format(path: &Path) -> io::Result<Option<ArchiveFormat>> {
+    let mut header = Vec::with_capacity(TAR_MAGIC_OFFSET + TAR_MAGIC.len());
+    File::open(path)?
+        .take((TAR_MAGIC_OFFSET + TAR_MAGIC.len()) as u64)
+        .read_to_end(&mut header)?;
+
+    let is_tar_gz_name = path
+        .file_name()
+        .and_then(|file_name| ArchiveFormat::from_file_name(&file_name.to_string_lossy()))
+        == Some(ArchiveFormat::TarGz);
+    Ok(if header.starts_with(ZIP_MAGIC) {
+        Some(ArchiveFormat::Zip)
+    } else if header.starts_with(GZIP_MAGIC) && is_tar_gz_name {
+        Some(ArchiveFormat::TarGz)
+    } else if header.get(TAR_MAGIC_OFFSET..) == Some(TAR_MAGIC) {
+        Some(ArchiveFormat::Tar)
+    } else {
+        None
+    })
+}
+
+/// The name of the directory to extract an archive into, e.g. "photos" for "photos.tar.gz"
+pub(crate) fn get_extraction_dir_name(file_name: &str) -> String {
+    let lowercase_name = file_name.to_lowercase();
+    let stem = match [".tar.gz", ".tgz", ".zip", ".tar"]
+        .iter()
+        .find(|extension| lowercase_name.ends_with(*extension))
+    {
+        Some(extension) => &file_name[..file_name.len() - extension.len()],
+        // e.g. a .jar file, which is a zip archive too
+        None => Path::new(file_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(file_name),
+    };
+    // the directory can not have the same name as the archive, and it needs a name at all
+    if stem.is_empty() || stem == file_name {
+        format!("{}_extracted", file_name)
+    } else {
+        stem.to_owned()
+    }
+}
+
+/// Passes on the reads and reports how much of the file has been read, which is how far tar extraction has got
+struct ProgressReader<'a, R: Read> {
+    inner: R,
+    read_num: usize,
+    total_num: usize,
+    report_progress: &'a dyn Fn(usize, usize),
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        // reporting every read would flood the main thread, so only report whole percents
+        let old_percent = self.read_num * 100 / self.total_num.max(1);
+        self.read_num += n;
+        if self.read_num * 100 / self.total_num.max(1) != old_percent {
+            (self.report_progress)(self.read_num, self.total_num);
+        }
+        Ok(n)
+    }
+}
+
+/// Unpacks the archive into `destination`, which is created if needed. Existing files are overwritten,
+/// and entries that would end up outside of `destination` (e.g. "../file" or through a symlink) are skipped
+pub(crate) fn extract_archive(
+    archive_path: &Path,
+    destination: &Path,
+    format: ArchiveFormat,
+    report_progress: &dyn Fn(usize, usize),
+) -> io::Result<()> {
+    fs::create_dir_all(destination)?;
+    let file = File::open(archive_path)?;
+    match format {
+        ArchiveFormat::Zip => extract_zip(file, destination, report_progress),
+        ArchiveFormat::Tar | ArchiveFormat::TarGz => {
+            let reader = ProgressReader {
+                total_num: file.metadata()?.len() as usize,
+                inner: file,
+                read_num: 0,
+                report_progress,
+            };
+            if format == ArchiveFormat::Tar {
+                tar::Archive::new(reader).unpack(destination)
+            } else {
+                tar::Archive::new(GzDecoder::new(reader)).unpack(destination)
+            }
+        }
+    }
+}
+
+fn extract_zip(
+    file: File,
+    destination: &Path,
+    report_progress: &dyn Fn(usize, usize),
+) -> io::Result<()> {
+    // the paths are compared with the resolved ones, so this has to be resolved too
+    let destination = &destination.canonicalize()?;
+    let mut zip = ZipArchive::new(file)?;
+    let entries_num = zip.len();
+    for i in 0..entries_num {
+        let mut entry = zip.by_index(i)?;
+        let name = match entry.enclosed_name() {
+            Some(name) => name,
+            None => continue,
+        };
+        let path = destination.join(&name);
+        // a symlink from the archive or one that was there already could lead anywhere
+        let is_parent_inside = path
+            .parent()
+            .is_some_and(|parent| is_inside(parent, destination));
+        if !is_parent_inside {
+            continue;
+        }
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // writing to a symlink would write to its target instead
+            if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+                fs::remove_file(&path)?;
+            }
+            extract_zip_file(&mut entry, &name, &path)?;
+        }
+        report_progress(i + 1, entries_num);
+    }
+    Ok(())
+}
+
+/// Whether `path` is in `destination` (which is canonical) after following the symlinks on the way.
+/// Only the part that exists can be followed, and the directories created below it stay in it
+fn is_inside(path: &Path, destination: &Path) -> bool {
+    path.ancestors()
+        .find_map(|ancestor| ancestor.canonicalize().ok())
+        .is_some_and(|ancestor| ancestor.starts_with(destination))
+}
+
+/// Whether the target of a symlink at `name` (relative to the destination) stays in the destination
+#[cfg(unix)]
+fn is_link_target_inside(name: &Path, target: &Path) -> bool {
+    use std::path::Component;
+
+    let mut depth = name.components().count().saturating_sub(1);
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            // e.g. "/etc" or "../.."
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+#[cfg(unix)]
+fn extract_zip_file(entry: &mut ZipFile<'_>, name: &Path, path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    if entry.is_symlink() {
+        let mut target = String::new();
+        entry.read_to_string(&mut target)?;
+        // the later entries could otherwise be written through it to anywhere
+        if !is_link_target_inside(name, Path::new(&target)) {
+            return Ok(());
+        }
+        // symlinks can not be overwritten in place
+        if fs::symlink_metadata(path).is_ok() {
+            fs::remove_file(path)?;
+        }
+        return symlink(target, path);
+    }
+    io::copy(entry, &mut File::create(path)?)?;
+    if let Some(mode) = entry.unix_mode() {
+        // an archive should not be able to make setuid or setgid programs
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn extract_zip_file(entry: &mut ZipFile<'_>, _name: &Path, path: &Path) -> io::Result<()> {
+    io::copy(entry, &mut File::create(path)?)?;
+    Ok(())
+}
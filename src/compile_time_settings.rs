@@ -1 +1,102 @@
 pub const PREVIEW_TEXT_FETCH_LENGTH: usize = 1000;
+/// `yank_contents` refuses files larger than this, since the clipboard is meant for snippets
+pub const MAX_YANKED_FILE_SIZE: u64 = 1024 * 1024;
+/// The least visited directories are forgotten when there are more than this many
+pub const MAX_VISITED_DIRS: usize = 1000;
+/// The oldest directories are dropped from the back and forward history when it gets longer than this
+pub const MAX_DIR_HISTORY: usize = 100;
+/// How many of the best matches the directory jumper shows
+pub const JUMP_SHOWN_DIRS: usize = 10;
+/// Searching in the preview reads this much of the file, so that matches further down can be scrolled to
+pub const PREVIEW_SEARCH_FETCH_LENGTH: usize = 1024 * 1024;
+/// How many lines are shown above the current match when the preview is scrolled to it
+pub const PREVIEW_SEARCH_CONTEXT_LINES: usize = 3;
+/// `show_children_num` stops counting the entries of a directory after this many, and shows e.g. "1000+"
+pub const MAX_COUNTED_CHILDREN: usize = 1000;
+/// How many directories the last known contents are kept for, to show them straight away when coming back
+pub const MAX_CACHED_LISTINGS: usize = 100;
+/// Like `MAX_CACHED_LISTINGS`, but for the git statuses
+pub const MAX_CACHED_GIT_STATUSES: usize = 100;
+/// Like `MAX_CACHED_LISTINGS`, but for the numbers of entries that `show_children_num` shows. It is higher,
+/// since every directory on the screen has one
+pub const MAX_CACHED_CHILDREN_NUMS: usize = 1000;
+/// The oldest toasts are dismissed early when there would be more than this many on the screen
+pub const MAX_TOASTS: usize = 5;
+/// The register that yanking, cutting and pasting use unless another one was picked first
+pub const UNNAMED_REGISTER: char = '"';
+/// Below this width, the preview pane is hidden and the list takes up the whole width
+pub const MIN_WIDTH_FOR_PREVIEW: u16 = 50;
+
+/// The disk usage overlay stops counting after visiting this many files, so that huge trees do not take forever
+pub const MAX_DISK_USAGE_ENTRIES: usize = 200_000;
+/// How many of the largest entries the disk usage overlay shows
+pub const DISK_USAGE_SHOWN_ENTRIES: usize = 20;
+
+/// Written to the user's config directory when there is no config file anywhere, e.g. on the first run
+pub const DEFAULT_CONFIG: &str = include_str!("../example_config.toml");
+
+/// Whether renames that only change the case of a name go through a temporary name, unless `case_insensitive_names` is set
+pub const CASE_INSENSITIVE_NAMES_BY_DEFAULT: bool =
+    cfg!(any(target_os = "macos", target_os = "windows"));
+
+/// A file with this name overrides some of the settings for the directory that it is in
+pub const DIR_CONFIG_FILE_NAME: &str = ".fphile.toml";
+
+/// Opens files and directories with the platform's default GUI program, unless `os_open_command` is set
+#[cfg(target_os = "macos")]
+pub const DEFAULT_OS_OPEN_COMMAND: &[&str] = &["open", "<FILE>"];
+#[cfg(target_os = "windows")]
+pub const DEFAULT_OS_OPEN_COMMAND: &[&str] = &["explorer", "<FILE>"];
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+pub const DEFAULT_OS_OPEN_COMMAND: &[&str] = &["xdg-open", "<FILE>"];
+
+/// The terminal multiplexers that `open_multiplexer_pane` works in, found by the environment variables that they set,
+/// with the commands that open a new pane in `<DIR>` unless `multiplexer_command` is set
+pub const MULTIPLEXERS: &[(&str, &[&str])] = &[
+    ("TMUX", &["tmux", "split-window", "-c", "<DIR>"]),
+    (
+        "ZELLIJ",
+        &["zellij", "action", "new-pane", "--cwd", "<DIR>"],
+    ),
+];
+
+/// The icons used when `show_icons` is on. They can be overridden with the `[icons]` config section.
+/// Keys are either file extensions or one of the special `<DIR>`, `<SYMLINK>`, `<EXECUTABLE>` and `<FILE>` keys
+pub const DEFAULT_ICONS: &[(&str, &str)] = &[
+    ("<DIR>", "\u{f115}"),
+    ("<SYMLINK>", "\u{f481}"),
+    ("<EXECUTABLE>", "\u{f489}"),
+    ("<FILE>", "\u{f15b}"),
+    ("c", "\u{e61e}"),
+    ("cpp", "\u{e61d}"),
+    ("css", "\u{e749}"),
+    ("gif", "\u{f1c5}"),
+    ("go", "\u{e626}"),
+    ("gz", "\u{f1c6}"),
+    ("h", "\u{f0fd}"),
+    ("html", "\u{e736}"),
+    ("java", "\u{e738}"),
+    ("jpeg", "\u{f1c5}"),
+    ("jpg", "\u{f1c5}"),
+    ("js", "\u{e74e}"),
+    ("json", "\u{e60b}"),
+    ("lock", "\u{f023}"),
+    ("md", "\u{e609}"),
+    ("mp3", "\u{f1c7}"),
+    ("mp4", "\u{f1c8}"),
+    ("pdf", "\u{f1c1}"),
+    ("png", "\u{f1c5}"),
+    ("py", "\u{e606}"),
+    ("rs", "\u{e7a8}"),
+    ("sh", "\u{f489}"),
+    ("tar", "\u{f1c6}"),
+    ("toml", "\u{e615}"),
+    ("ts", "\u{e628}"),
+    ("txt", "\u{f15c}"),
+    ("yaml", "\u{e615}"),
+    ("yml", "\u{e615}"),
+    ("zip", "\u{f1c6}"),
+];
+
+/// The name of the copies made by `backup`, unless `backup_name_format` is set, e.g. "notes.2022-04-01.txt"
+pub const DEFAULT_BACKUP_NAME_FORMAT: &str = "<STEM>.%Y-%m-%d<EXT>";
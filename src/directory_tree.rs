@@ -1,30 +1,53 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
 use std::ffi::OsString;
 use std::io::{self, stdin, BufRead, Stdout};
 use std::path::{Component, Path, PathBuf};
+use std::process::Child;
 
-use std::fs::{canonicalize, read_dir};
-use std::time::Duration;
+use std::fs::{canonicalize, metadata, read_dir, read_link, symlink_metadata, File, Metadata};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crossbeam_channel::{select, tick, Receiver};
+use crossbeam_channel::{
+    bounded, select, tick, unbounded, Receiver, RecvTimeoutError, Sender, TryRecvError,
+};
 use fuzzy_matcher::skim::SkimMatcherV2;
+use sha2::Digest;
 use tui::backend::CrosstermBackend;
 use tui::style::Style;
 use tui::text::{Span, Spans};
 use tui::widgets::ListItem;
 use tui::Terminal;
+use unicode_width::UnicodeWidthStr;
 
-use crate::helper_types::{MarkType, StyleSet};
+use crate::compile_time_settings::{
+    DEFAULT_ICONS, MAX_CACHED_CHILDREN_NUMS, MAX_COUNTED_CHILDREN, MAX_DISK_USAGE_ENTRIES,
+};
+use crate::helper_types::{
+    AppSettings, AppState, HashAlgorithm, LruCache, PathDisplay, SearchMatch, SortKey, StyleSet,
+    ViewMode,
+};
+use crate::util::{format_full_time, format_mtime, format_permissions, truncate_name};
 use crate::{enter_captured_mode, exit_captured_mode};
 
 #[derive(Clone)]
 pub struct FileTreeNode {
     pub(self) path_buf: PathBuf,
     pub(self) simple_name: String,
+    // cached so that we do not need to stat every file on every frame
+    pub(self) is_executable: bool,
 }
 
+// NOTE: this is purely lexical and does not touch the filesystem, so symlinks are not resolved.
+// That means that "some_symlink/.." goes back to the directory containing the symlink, not to the parent of its target.
+// If symlinks need to be resolved, use `canonicalize` instead (which is what the `right` action does when following symlinks)
 // taken from here (I am assuming MIT license applies?):
 // https://github.com/rust-lang/cargo/blob/master/crates/cargo-util/src/paths.rs
-fn normalize_path(path: &Path) -> PathBuf {
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
     let mut components = path.components().peekable();
     let mut ret = if let Some(c @ Component::Prefix(..)) = components.peek().cloned() {
         components.next();
@@ -68,50 +91,223 @@ impl FileTreeNode {
 
         // get the file name
         let simple_name = simple_os_string_name.to_string_lossy().into_owned();
+        let is_executable = is_path_executable(&path);
         FileTreeNode {
             path_buf: path.to_path_buf(),
             simple_name,
+            is_executable,
         }
     }
 
     pub fn get_tui_representation(
         &self,
-        cursor_styles: &StyleSet,
-        default_styles: &StyleSet,
-        is_cursor: bool,
-        marks: &Vec<FileTreeNode>,
-        mark_type: &MarkType,
-    ) -> ListItem {
-        let mark = if marks.contains(self) {
-            Some(mark_type)
+        config: &AppSettings,
+        // the cursor styles on the row with the cursor, and the default ones elsewhere
+        styles_set: &StyleSet,
+        app_state: &AppState,
+        available_width: usize,
+        line_number: Option<String>,
+    ) -> ListItem<'_> {
+        let spans = self.get_tui_spans(config, app_state, available_width, line_number);
+        // choose the style based on whether it is a directory or a file
+        ListItem::new(spans).style(if self.is_dir() {
+            styles_set.dir
+        } else if self.is_executable() {
+            styles_set.executable
         } else {
-            None
-        };
+            styles_set.file
+        })
+    }
 
-        let mut spans_vec = match mark {
+    /// The text of the row, which fits into `available_width` columns unless even the name has no room left
+    fn get_tui_spans(
+        &self,
+        config: &AppSettings,
+        app_state: &AppState,
+        available_width: usize,
+        line_number: Option<String>,
+    ) -> Spans<'_> {
+        let mut spans_vec = match line_number {
+            Some(line_number) => vec![Span::styled(
+                line_number,
+                Style::default().fg(tui::style::Color::DarkGray),
+            )],
             None => vec![],
-            Some(&MarkType::Delete) => vec![
-                Span::styled("D", Style::default().fg(tui::style::Color::Red)),
-                Span::from("|"),
-            ],
         };
+        if let Some(mark_type) = app_state.marked_files.get(&self.path_buf) {
+            spans_vec.push(Span::styled(
+                mark_type.get_letter(),
+                Style::default().fg(mark_type.get_color()),
+            ));
+            spans_vec.push(Span::from("|"));
+        }
+
+        if let Some(git_statuses) = &app_state.git_statuses {
+            spans_vec.push(match git_statuses.get(&self.path_buf) {
+                Some(git_status) => Span::styled(
+                    format!("{} ", git_status.get_letter()),
+                    Style::default().fg(git_status.get_color()),
+                ),
+                // keep the names lined up
+                None => Span::raw("  "),
+            });
+        }
+
+        if let ViewMode::Detailed = app_state.view_mode {
+            spans_vec.push(Span::raw(self.get_details(config)));
+        }
 
-        spans_vec.push(Span::raw(self.get_simple_name().clone()));
+        // the contents of the expanded directories are indented by how deep they are
+        let depth = self
+            .path_buf
+            .strip_prefix(app_state.current_dir.get_path_buf())
+            .map(|relative_path| relative_path.components().count().saturating_sub(1))
+            .unwrap_or(0);
+        if depth > 0 {
+            spans_vec.push(Span::raw("  ".repeat(depth)));
+        }
+
+        if config.show_icons {
+            spans_vec.push(Span::raw(format!("{} ", self.get_icon(config))));
+        }
 
-        // choose the style based on whether it is a directory or a file and whether it is selected
-        let styles_set = if is_cursor {
-            &cursor_styles
+        // like `ls -F`
+        let executable_marker = if self.is_executable() { "*" } else { "" };
+        let children_num = if config.show_children_num && self.is_dir() {
+            format!(
+                " ({})",
+                format_children_num(app_state.children_nums.get(&self.path_buf))
+            )
         } else {
-            &default_styles
+            String::new()
+        };
+        // the directory was expanded, but its contents are not there yet
+        let children_num = match app_state.loading_expanded_dirs.get(&self.path_buf) {
+            Some(expanded_at) if app_state.expanded_dirs.contains_key(&self.path_buf) => {
+                format!("{} {}", children_num, get_spinner_frame(*expanded_at))
+            }
+            _ => children_num,
         };
-        let out = ListItem::new(Spans::from(spans_vec)).style(if self.is_dir() {
-            styles_set.dir.clone()
+        // the name gets whatever is left after the line number, marks, details, indentation, icon and number of entries
+        let used_width: usize = spans_vec.iter().map(Span::width).sum::<usize>()
+            + executable_marker.width()
+            + children_num.width();
+        let max_name_width = available_width
+            .saturating_sub(used_width)
+            .min(config.max_name_width.unwrap_or(usize::MAX));
+        let kept_suffix = match self.get_extension() {
+            Some(extension) => format!(".{}", extension),
+            // the slash shows that it is a directory
+            None if self.simple_name.ends_with('/') => String::from("/"),
+            None => String::new(),
+        };
+        spans_vec.push(Span::raw(truncate_name(
+            self.get_simple_name(),
+            &kept_suffix,
+            max_name_width,
+            config.name_truncation,
+        )));
+        spans_vec.push(Span::raw(executable_marker));
+        if !children_num.is_empty() {
+            spans_vec.push(Span::styled(
+                children_num,
+                Style::default().fg(tui::style::Color::DarkGray),
+            ));
+        }
+
+        Spans::from(spans_vec)
+    }
+    /// The permissions, size and modification time, padded to line up as columns
+    fn get_details(&self, config: &AppSettings) -> String {
+        let metadata = match metadata(&self.path_buf) {
+            Ok(metadata) => metadata,
+            // e.g. a broken symlink
+            Err(_) => return format!("{:<10} {:>6} {:<16} ", "?", "?", "?"),
+        };
+        // the size of a directory is not the size of its contents, so it would be misleading
+        let size = if metadata.is_dir() {
+            String::from("-")
         } else {
-            styles_set.file.clone()
-        });
+            format_size(metadata.len())
+        };
+        let modified = match metadata.modified() {
+            Ok(mtime) => format_mtime(mtime, config.time_format),
+            Err(_) => String::from("?"),
+        };
+        format!(
+            "{:<10} {:>6} {:<16} ",
+            format_permissions(&metadata),
+            size,
+            modified
+        )
+    }
 
-        out
+    /// The column names for `get_details`, with an arrow next to the one that the items are sorted by
+    pub(crate) fn get_details_header(
+        config: &AppSettings,
+        sort_key: SortKey,
+        is_sort_reversed: bool,
+    ) -> String {
+        let arrow = if sort_key.is_ascending() != is_sort_reversed {
+            "▲"
+        } else {
+            "▼"
+        };
+        let get_column_name = |name: &str, column_sort_key: SortKey| {
+            if column_sort_key == sort_key {
+                format!("{}{}", name, arrow)
+            } else {
+                name.to_owned()
+            }
+        };
+        let name_column = if sort_key == SortKey::Extension {
+            format!("name (extension){}", arrow)
+        } else {
+            get_column_name("name", SortKey::Name)
+        };
+        format!(
+            "{:<10} {:>6} {:<16} {}{}",
+            "perms",
+            get_column_name("size", SortKey::Size),
+            get_column_name("modified", SortKey::Modified),
+            // line the name up with the names below the icons
+            if config.show_icons { "  " } else { "" },
+            name_column
+        )
     }
+
+    /// Finds the icon for this node, preferring the config's `[icons]` section over the built-in defaults
+    pub(crate) fn get_icon<'a>(&self, config: &'a AppSettings) -> &'a str {
+        let find_icon = |key: &str| -> Option<&'a str> {
+            config.icons.get(key).map(String::as_str).or_else(|| {
+                DEFAULT_ICONS
+                    .iter()
+                    .find(|(icon_key, _)| *icon_key == key)
+                    .map(|(_, icon)| *icon)
+            })
+        };
+
+        let special_key = if self.is_symlink() {
+            Some("<SYMLINK>")
+        } else if self.is_dir() {
+            Some("<DIR>")
+        } else if self.is_executable() {
+            Some("<EXECUTABLE>")
+        } else {
+            None
+        };
+
+        special_key
+            .and_then(find_icon)
+            .or_else(|| {
+                self.path_buf
+                    .extension()
+                    .and_then(|extension| find_icon(&extension.to_string_lossy().to_lowercase()))
+            })
+            .or_else(|| find_icon("<FILE>"))
+            .unwrap_or("")
+    }
+
     pub(crate) fn get_path_buf(&self) -> &PathBuf {
         &self.path_buf
     }
@@ -124,24 +320,114 @@ impl FileTreeNode {
         self.path_buf.is_dir()
     }
 
-    pub(crate) fn compute_score(&self, query: &str) -> i64 {
-        let match_data =
-            SkimMatcherV2::default()
-                .smart_case()
-                .fuzzy(&self.simple_name, query, true);
-        match match_data {
-            None => 0,
-            Some(match_data) => match_data.0,
+    pub(crate) fn is_symlink(&self) -> bool {
+        self.path_buf.is_symlink()
+    }
+
+    pub(crate) fn is_executable(&self) -> bool {
+        self.is_executable
+    }
+
+    /// The extension of a file, without the dot. Directories do not have one
+    pub(crate) fn get_extension(&self) -> Option<String> {
+        if self.is_dir() {
+            return None;
+        }
+        self.path_buf
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+    }
+
+    pub(crate) fn get_size(&self) -> io::Result<u64> {
+        Ok(metadata(&self.path_buf)?.len())
+    }
+
+    pub(crate) fn get_modified(&self) -> io::Result<SystemTime> {
+        metadata(&self.path_buf)?.modified()
+    }
+
+    /// How well the name, or the path relative to `base_dir`, fuzzy matches the query, higher is better,
+    /// or None if it does not match. An empty query matches everything equally well, with a score of 0
+    pub(crate) fn compute_score(
+        &self,
+        query: &str,
+        search_match: SearchMatch,
+        base_dir: &Path,
+    ) -> Option<i64> {
+        if query.is_empty() {
+            return Some(0);
+        }
+        let matched_text = match search_match {
+            SearchMatch::Name => Cow::Borrowed(self.simple_name.as_str()),
+            // entries outside of `base_dir` fall back to their name
+            SearchMatch::RelativePath => match self.path_buf.strip_prefix(base_dir) {
+                Ok(relative_path) if !relative_path.as_os_str().is_empty() => {
+                    relative_path.to_string_lossy()
+                }
+                _ => Cow::Borrowed(self.simple_name.as_str()),
+            },
+        };
+        SkimMatcherV2::default()
+            .smart_case()
+            .fuzzy(&matched_text, query, true)
+            .map(|match_data| match_data.0)
+    }
+
+    /// The details that `stat` shows, as names and values. The ones that the platform does not have are left out.
+    /// Symlinks are described themselves rather than what they point to
+    pub(crate) fn get_info(&self) -> io::Result<Vec<(&'static str, String)>> {
+        let metadata = symlink_metadata(&self.path_buf)?;
+        let file_type = if metadata.file_type().is_symlink() {
+            "symlink"
+        } else if metadata.is_dir() {
+            "directory"
+        } else {
+            "file"
+        };
+        let mut info = vec![
+            ("Path", self.path_buf.to_string_lossy().into_owned()),
+            ("Type", String::from(file_type)),
+        ];
+        if let Ok(target) = read_link(&self.path_buf) {
+            info.push(("Target", target.to_string_lossy().into_owned()));
         }
+        info.push((
+            "Size",
+            format!("{} ({} bytes)", format_size(metadata.len()), metadata.len()),
+        ));
+        info.push(("Permissions", format_permissions(&metadata)));
+        info.extend(get_platform_info(&metadata));
+        let times = [
+            ("Created", metadata.created()),
+            ("Modified", metadata.modified()),
+            ("Accessed", metadata.accessed()),
+        ];
+        for (name, time) in times {
+            if let Ok(time) = time {
+                info.push((name, format_full_time(time)));
+            }
+        }
+        Ok(info)
     }
 
+    /// Lists the entries of this directory, leaving out the ones that can not be read
     pub(crate) fn list_files(&self) -> io::Result<Vec<FileTreeNode>> {
+        self.list_files_counting_skipped()
+            .map(|(entries, _skipped_num)| entries)
+    }
+
+    /// Like `list_files`, but also returns how many entries were left out because they could not be read.
+    /// Only failing to read the directory itself is an error, so that one bad entry does not hide all the others
+    pub(crate) fn list_files_counting_skipped(&self) -> ListingResult {
         let mut ret = Vec::new();
+        let mut skipped_num = 0;
         for entry in read_dir(self.path_buf.clone())? {
-            let resolved_entry = entry?;
-            ret.push(FileTreeNode::new(resolved_entry.path()));
+            match entry {
+                Ok(resolved_entry) => ret.push(FileTreeNode::new(resolved_entry.path())),
+                Err(_) => skipped_num += 1,
+            }
         }
-        Ok(ret)
+        Ok((ret, skipped_num))
     }
 }
 
@@ -151,9 +437,582 @@ impl PartialEq for FileTreeNode {
     }
 }
 
+#[cfg(unix)]
+fn is_path_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_path_executable(_path: &Path) -> bool {
+    false
+}
+
+/// The owner, the group, the inode and the number of hard links
+#[cfg(unix)]
+fn get_platform_info(metadata: &Metadata) -> Vec<(&'static str, String)> {
+    use nix::unistd::{Gid, Group, Uid, User};
+    use std::os::unix::fs::MetadataExt;
+
+    // the ids are shown by themselves when there is no such user or group, e.g. for files from another machine
+    let owner = match User::from_uid(Uid::from_raw(metadata.uid())) {
+        Ok(Some(user)) => format!("{} ({})", user.name, metadata.uid()),
+        _ => metadata.uid().to_string(),
+    };
+    let group = match Group::from_gid(Gid::from_raw(metadata.gid())) {
+        Ok(Some(group)) => format!("{} ({})", group.name, metadata.gid()),
+        _ => metadata.gid().to_string(),
+    };
+    vec![
+        ("Owner", owner),
+        ("Group", group),
+        ("Inode", metadata.ino().to_string()),
+        ("Links", metadata.nlink().to_string()),
+    ]
+}
+
+#[cfg(not(unix))]
+fn get_platform_info(_metadata: &Metadata) -> Vec<(&'static str, String)> {
+    Vec::new()
+}
+
+/// Turns a path typed by the user into an absolute path.
+/// Relative paths are relative to `base` and a leading "~" refers to the home directory
+pub(crate) fn resolve_path(base: &Path, typed_path: &str) -> PathBuf {
+    let home_dir = env::var_os("HOME").map(PathBuf::from);
+    let path = match (typed_path.strip_prefix('~'), home_dir) {
+        (Some(rest), Some(home_dir)) if rest.is_empty() || rest.starts_with('/') => {
+            home_dir.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(typed_path),
+    };
+    // join keeps absolute paths as they are
+    normalize_path(&base.join(path))
+}
+
+/// Formats a path for showing it in the UI. Paths outside the home directory or outside `root` are shown in full
+pub(crate) fn display_path(path: &Path, path_display: PathDisplay, root: Option<&Path>) -> String {
+    let home_dir = env::var_os("HOME").map(PathBuf::from);
+    let shortened = match path_display {
+        PathDisplay::Absolute => None,
+        PathDisplay::Home => home_dir.as_deref().and_then(|home_dir| {
+            let relative_path = path.strip_prefix(home_dir).ok()?;
+            Some(Path::new("~").join(relative_path))
+        }),
+        PathDisplay::Root => root.and_then(|root| {
+            let relative_path = path.strip_prefix(root).ok()?;
+            // the name of the root says which root it is relative to, e.g. "projects/fphile"
+            let root_name = root.file_name().unwrap_or(root.as_os_str());
+            Some(Path::new(root_name).join(relative_path))
+        }),
+    };
+    shortened
+        .as_deref()
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The closest directory containing `dir` (or `dir` itself) that is the root of a git repository
+pub(crate) fn find_git_root(dir: &Path) -> Option<&Path> {
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+}
+
+/// Lists the ways to complete the last component of a typed path with the entries of its parent directory.
+/// The candidates are sorted and include the already typed parent, e.g. "src/main.rs" for "src/ma"
+pub(crate) fn get_path_completions(base: &Path, typed_path: &str) -> Vec<String> {
+    // the part that is already complete, e.g. "src/" in "src/ma"
+    let (typed_parent, partial_name) = match typed_path.rfind('/') {
+        Some(index) => typed_path.split_at(index + 1),
+        None => ("", typed_path),
+    };
+
+    let parent = FileTreeNode::new(resolve_path(base, typed_parent));
+    let mut completions: Vec<_> = match parent.list_files() {
+        Ok(items) => items
+            .into_iter()
+            .map(|item| item.simple_name)
+            .filter(|name| name.starts_with(partial_name))
+            .map(|name| format!("{}{}", typed_parent, name))
+            .collect(),
+        Err(_) => vec![],
+    };
+    completions.sort();
+    completions
+}
+
+/// Returns the longest prefix that all the strings share
+pub(crate) fn get_common_prefix(strings: &[String]) -> &str {
+    let first = match strings.first() {
+        Some(first) => first,
+        None => return "",
+    };
+    let common_prefix_len = strings.iter().fold(first.len(), |len, string| {
+        first[..len]
+            .char_indices()
+            .zip(string.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            // one of them is a prefix of the other
+            .unwrap_or_else(|| len.min(string.len()))
+    });
+    &first[..common_prefix_len]
+}
+
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// The entries of a directory and how many of them could not be read
+type ListingResult = io::Result<(Vec<FileTreeNode>, usize)>;
+
+/// Lists a directory on a background thread, so that huge or slow (e.g. network) directories do not block the UI
+pub(crate) struct DirListing {
+    dir: FileTreeNode,
+    // the result of the last finished listing. None until the first listing finishes
+    items: Option<Vec<FileTreeNode>>,
+    // how many entries the last finished listing could not read
+    skipped_num: usize,
+    // Some while a listing is running. Dropping it cancels the listing because its result is discarded
+    receiver: Option<Receiver<ListingResult>>,
+    started_at: Instant,
+}
+
+impl DirListing {
+    /// `cached_items` are shown until the listing finishes
+    pub(crate) fn new(dir: FileTreeNode, cached_items: Option<Vec<FileTreeNode>>) -> Self {
+        let mut dir_listing = DirListing {
+            dir,
+            items: cached_items,
+            skipped_num: 0,
+            receiver: None,
+            started_at: Instant::now(),
+        };
+        dir_listing.start();
+        dir_listing
+    }
+
+    /// Starts listing the directory again, replacing the listing that is currently running
+    pub(crate) fn start(&mut self) {
+        let (sender, receiver) = bounded(1);
+        let dir = self.dir.clone();
+        thread::spawn(move || {
+            // this fails if the listing was cancelled, in which case nobody needs the result
+            let _ = sender.send(dir.list_files_counting_skipped());
+        });
+        self.receiver = Some(receiver);
+        self.started_at = Instant::now();
+    }
+
+    pub(crate) fn get_dir(&self) -> &FileTreeNode {
+        &self.dir
+    }
+
+    /// How long ago the last listing was started
+    pub(crate) fn get_age(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub(crate) fn is_pending(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    /// Whether there is nothing to show yet because the first listing has not finished
+    pub(crate) fn is_loading(&self) -> bool {
+        self.items.is_none()
+    }
+
+    /// Waits for up to `timeout` for the running listing to finish and stores its items if it has.
+    /// Returns None if nothing has finished since the last call
+    pub(crate) fn poll(&mut self, timeout: Duration) -> Option<io::Result<()>> {
+        let result = match self.receiver.as_ref()?.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::other(
+                "The directory listing stopped unexpectedly",
+            )),
+        };
+        self.receiver = None;
+
+        Some(match result {
+            Ok((items, skipped_num)) => {
+                self.items = Some(items);
+                self.skipped_num = skipped_num;
+                Ok(())
+            }
+            Err(err) => {
+                self.items = Some(vec![]);
+                self.skipped_num = 0;
+                Err(err)
+            }
+        })
+    }
+
+    pub(crate) fn get_items(&self) -> Vec<FileTreeNode> {
+        self.items.clone().unwrap_or_default()
+    }
+
+    pub(crate) fn get_skipped_num(&self) -> usize {
+        self.skipped_num
+    }
+
+    pub(crate) fn get_spinner_frame(&self) -> &'static str {
+        get_spinner_frame(self.started_at)
+    }
+}
+
+/// Lists the expanded directories again on a background thread, so that the changes in them show up
+pub(crate) struct ExpandedDirsListing {
+    paths: Vec<PathBuf>,
+    receiver: Receiver<BTreeMap<PathBuf, Vec<FileTreeNode>>>,
+}
+
+impl ExpandedDirsListing {
+    pub(crate) fn new(paths: Vec<PathBuf>) -> Self {
+        let (sender, receiver) = bounded(1);
+        let thread_paths = paths.clone();
+        thread::spawn(move || {
+            // the directories that can not be listed any more, e.g. because they were deleted, are left out
+            let expanded_dirs = thread_paths
+                .into_iter()
+                .filter_map(|path| {
+                    let children = FileTreeNode::new(path.clone()).list_files().ok()?;
+                    Some((path, children))
+                })
+                .collect();
+            // this fails if the listing was replaced, in which case nobody needs the result
+            let _ = sender.send(expanded_dirs);
+        });
+        ExpandedDirsListing { paths, receiver }
+    }
+
+    /// The directories that are being listed
+    pub(crate) fn get_paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// The new contents of `expanded_dirs` if the listing has finished. The directories that were expanded
+    /// after it started are kept as they are
+    pub(crate) fn poll(
+        &self,
+        expanded_dirs: &BTreeMap<PathBuf, Vec<FileTreeNode>>,
+    ) -> Option<BTreeMap<PathBuf, Vec<FileTreeNode>>> {
+        let mut listed_dirs = self.receiver.try_recv().ok()?;
+        Some(
+            expanded_dirs
+                .iter()
+                .filter_map(|(path, children)| match listed_dirs.remove(path) {
+                    Some(listed_children) => Some((path.clone(), listed_children)),
+                    None if self.paths.contains(path) => None,
+                    None => Some((path.clone(), children.clone())),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Counts the entries of directories on a background thread, so that slow directories do not hold up drawing.
+/// A directory that is counted again is only read if it was modified since it was last counted
+pub(crate) struct ChildrenCounter {
+    // the directories to count, and whether to count them even if they were not modified
+    sender: Sender<(PathBuf, bool)>,
+    receiver: Receiver<(PathBuf, Option<usize>)>,
+    // the directories that were sent to be counted for the first time
+    pending: BTreeSet<PathBuf>,
+}
+
+impl ChildrenCounter {
+    pub(crate) fn new() -> Self {
+        let (sender, path_receiver) = unbounded::<(PathBuf, bool)>();
+        let (result_sender, receiver) = unbounded();
+        thread::spawn(move || {
+            // when the counted directories were last modified, so that the ones that did not change are skipped.
+            // It forgets the same way as `children_nums`, so it does not grow forever either
+            let mut modification_times: LruCache<PathBuf, SystemTime> =
+                LruCache::new(MAX_CACHED_CHILDREN_NUMS);
+            // this stops when the counter is dropped
+            for (path, is_forced) in path_receiver {
+                let modified = metadata(&path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
+                let was_modified = modification_times.get(&path) != modified.as_ref();
+                match modified {
+                    Some(modified) => modification_times.insert(path.clone(), modified),
+                    None => {
+                        modification_times.remove(&path);
+                    }
+                }
+                if !is_forced && !was_modified {
+                    continue;
+                }
+                let children_num = read_dir(&path)
+                    .map(|entries| entries.take(MAX_COUNTED_CHILDREN + 1).count())
+                    .ok();
+                if result_sender.send((path, children_num)).is_err() {
+                    break;
+                }
+            }
+        });
+        ChildrenCounter {
+            sender,
+            receiver,
+            pending: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn is_pending(&self, path: &Path) -> bool {
+        self.pending.contains(path)
+    }
+
+    /// Counts the entries of a directory that was not counted yet
+    pub(crate) fn count(&mut self, path: PathBuf) {
+        self.pending.insert(path.clone());
+        // its count might have been forgotten while the modification time was not, so it is counted either way
+        let _ = self.sender.send((path, true));
+    }
+
+    /// Counts the entries of the directories again, only sending back the ones that were modified
+    pub(crate) fn recount(&self, paths: impl IntoIterator<Item = PathBuf>) {
+        for path in paths {
+            let _ = self.sender.send((path, false));
+        }
+    }
+
+    /// The counts that have finished since the last call
+    pub(crate) fn poll(&self) -> Vec<(PathBuf, Option<usize>)> {
+        self.receiver.try_iter().collect()
+    }
+
+    pub(crate) fn finish(&mut self, path: &Path) {
+        self.pending.remove(path);
+    }
+}
+
+/// The number of entries in a directory as it is shown, e.g. "12", or "1000+" when counting stopped early
+pub(crate) fn format_children_num(children_num: Option<&Option<usize>>) -> String {
+    match children_num {
+        Some(Some(children_num)) if *children_num > MAX_COUNTED_CHILDREN => {
+            format!("{}+", MAX_COUNTED_CHILDREN)
+        }
+        Some(Some(children_num)) => children_num.to_string(),
+        // not counted yet or unreadable
+        _ => String::from("?"),
+    }
+}
+
+pub(crate) fn get_spinner_frame(started_at: Instant) -> &'static str {
+    let frame_index = started_at.elapsed().as_millis() / 100;
+    SPINNER_FRAMES[frame_index as usize % SPINNER_FRAMES.len()]
+}
+
+/// Hashes a file on a background thread, since big files can take a while
+pub(crate) struct FileHashing {
+    file: FileTreeNode,
+    algorithm: HashAlgorithm,
+    receiver: Receiver<io::Result<String>>,
+    started_at: Instant,
+}
+
+impl FileHashing {
+    pub(crate) fn new(file: FileTreeNode, algorithm: HashAlgorithm) -> Self {
+        let (sender, receiver) = bounded(1);
+        let path = file.get_path_buf().to_owned();
+        thread::spawn(move || {
+            let _ = sender.send(compute_file_hash(&path, algorithm));
+        });
+        FileHashing {
+            file,
+            algorithm,
+            receiver,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub(crate) fn get_file(&self) -> &FileTreeNode {
+        &self.file
+    }
+
+    pub(crate) fn get_algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the hash in hex, or None if it is not ready yet
+    pub(crate) fn poll(&self) -> Option<io::Result<String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(io::Error::other("The hashing stopped unexpectedly")))
+            }
+        }
+    }
+
+    pub(crate) fn get_spinner_frame(&self) -> &'static str {
+        get_spinner_frame(self.started_at)
+    }
+}
+
+/// The total size of a file or a directory with everything inside it. Symlinks are not followed.
+/// Every visited entry uses up one of `entries_left`, and the counting stops when there are none left
+pub(crate) fn recursive_size(
+    path: &Path,
+    entries_left: &mut usize,
+    is_cancelled: &AtomicBool,
+) -> u64 {
+    let metadata = match symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        // e.g. no permissions or the file was deleted in the meantime
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+
+    let entries = match read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut size = 0;
+    for entry in entries.flatten() {
+        if *entries_left == 0 || is_cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        *entries_left -= 1;
+        size += recursive_size(&entry.path(), entries_left, is_cancelled);
+    }
+    size
+}
+
+enum DiskUsageUpdate {
+    Entry(FileTreeNode, u64),
+    LimitReached,
+}
+
+/// Computes the sizes of the entries of a directory on a background thread, making them available as they are computed
+pub(crate) struct DiskUsage {
+    entries: Vec<(FileTreeNode, u64)>,
+    receiver: Receiver<DiskUsageUpdate>,
+    is_finished: bool,
+    is_limit_reached: bool,
+    // tells the thread to stop once nobody needs the sizes any more
+    is_cancelled: Arc<AtomicBool>,
+    started_at: Instant,
+}
+
+impl DiskUsage {
+    pub(crate) fn new(dir: &FileTreeNode) -> Self {
+        let (sender, receiver) = unbounded();
+        let is_cancelled = Arc::new(AtomicBool::new(false));
+        let dir = dir.clone();
+        let is_cancelled_clone = is_cancelled.clone();
+        thread::spawn(move || {
+            let mut entries_left = MAX_DISK_USAGE_ENTRIES;
+            for item in dir.list_files().unwrap_or_default() {
+                let size =
+                    recursive_size(item.get_path_buf(), &mut entries_left, &is_cancelled_clone);
+                if sender.send(DiskUsageUpdate::Entry(item, size)).is_err() {
+                    return;
+                }
+            }
+            if entries_left == 0 {
+                let _ = sender.send(DiskUsageUpdate::LimitReached);
+            }
+        });
+
+        DiskUsage {
+            entries: vec![],
+            receiver,
+            is_finished: false,
+            is_limit_reached: false,
+            is_cancelled,
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Collects the sizes computed since the last call
+    pub(crate) fn poll(&mut self) {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(DiskUsageUpdate::Entry(item, size)) => self.entries.push((item, size)),
+                Ok(DiskUsageUpdate::LimitReached) => self.is_limit_reached = true,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.is_finished = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The entries whose sizes are known, from the largest
+    pub(crate) fn get_sorted_entries(&self) -> Vec<&(FileTreeNode, u64)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+        entries
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+
+    /// Whether the sizes are incomplete because there were too many files
+    pub(crate) fn is_limit_reached(&self) -> bool {
+        self.is_limit_reached
+    }
+
+    pub(crate) fn get_spinner_frame(&self) -> &'static str {
+        get_spinner_frame(self.started_at)
+    }
+}
+
+impl Drop for DiskUsage {
+    fn drop(&mut self) {
+        self.is_cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns the hash of the file's contents in hex
+pub(crate) fn compute_file_hash(path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+    fn hash_with<D: Digest + io::Write>(mut file: File) -> io::Result<String> {
+        let mut hasher = D::new();
+        // streams the file instead of reading all of it into memory
+        io::copy(&mut file, &mut hasher)?;
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    let file = File::open(path)?;
+    match algorithm {
+        HashAlgorithm::Md5 => hash_with::<md5::Md5>(file),
+        HashAlgorithm::Sha1 => hash_with::<sha1::Sha1>(file),
+        HashAlgorithm::Sha256 => hash_with::<sha2::Sha256>(file),
+    }
+}
+
+/// Formats a number of bytes like `ls -h`, e.g. "1.5K"
+pub(crate) fn format_size(size: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+
+    if size < 1024 {
+        return format!("{}B", size);
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit_index = 0;
+    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit_index += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit_index])
+}
+
 pub(crate) fn get_file_cursor_index(
     selected_file: &Option<FileTreeNode>,
-    items: &Vec<FileTreeNode>,
+    items: &[FileTreeNode],
 ) -> Option<usize> {
     selected_file.as_ref().and_then(|selected_file| {
         items
@@ -162,67 +1021,258 @@ pub(crate) fn get_file_cursor_index(
     })
 }
 
+/// Runs the command with the terminal given to it, and waits for it to exit or for ctrl-c.
+/// Failing to start it or it exiting with an error are reported rather than panicking, since it is usually a typo in the config
 pub(crate) fn run_command_in_foreground<I: Iterator<Item = String>>(
     mut options: I,
     terminal: &mut Terminal<CrosstermBackend<Stdout>>,
-    relative_path_current_dir: &PathBuf,
+    relative_path_current_dir: &Path,
     interrupt_signal_receiver: &Receiver<()>,
     command_status_refresh_secs: f64,
     pause_before_exiting: bool,
-) {
-    // open the file
-    // move to a different screen
-    exit_captured_mode(terminal).expect("Could not leave terminal capture");
+) -> Result<(), String> {
+    let program = options
+        .next()
+        .ok_or_else(|| String::from("The command is empty"))?;
     // NOTE: current_dir()'s behaviour is up to the implementation if the path is relative,
     // So we need to make it canonical
-    let panic_message = format!(
-        r#"Encountered an error while tried to convert "{}" to an absolute path"#,
-        relative_path_current_dir.as_os_str().to_string_lossy()
-    );
-    let absolute_path_current_dir = canonicalize(relative_path_current_dir).expect(&panic_message);
-    let mut command = std::process::Command::new(
-        options
-            .next()
-            .expect("Found empty arguments for the default file editor"),
-    );
+    let absolute_path_current_dir = canonicalize(relative_path_current_dir).map_err(|err| {
+        format!(
+            "Could not find the directory '{}': {}",
+            relative_path_current_dir.to_string_lossy(),
+            err
+        )
+    })?;
+    let mut command = std::process::Command::new(&program);
     command.current_dir(absolute_path_current_dir);
 
     options.for_each(|o| {
         command.arg(o);
     });
 
-    let mut handle = command.spawn().expect("Error: Failed to run the command");
+    // move to a different screen
+    if let Err(err) = exit_captured_mode(terminal) {
+        // it might have been left partly
+        let _ = enter_captured_mode(terminal);
+        return Err(format!(
+            "Could not give the terminal to '{}': {}",
+            program, err
+        ));
+    }
+    let result = match command.spawn() {
+        Ok(handle) => {
+            let result = wait_for_command(
+                handle,
+                &program,
+                interrupt_signal_receiver,
+                command_status_refresh_secs,
+            );
+            // the output of a command that failed is worth reading as well
+            if pause_before_exiting {
+                println!("The command has terminated. Press ENTER to continue.");
+                let stdin = stdin();
+                let mut buf = String::new();
+                // if there is no input to wait for, there is nothing to do but carry on
+                let _ = stdin.lock().read_line(&mut buf);
+            }
+            result
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Err(format!("Could not find the program '{}'", program))
+        }
+        Err(err) => Err(format!("Could not run '{}': {}", program, err)),
+    };
+
+    enter_captured_mode(terminal).expect("Could not re-enter the terminal capture");
+    result
+}
 
+/// Waits for the command to exit, or kills it on ctrl-c. Exiting with an error status is an error, but being killed is not
+fn wait_for_command(
+    mut handle: Child,
+    program: &str,
+    interrupt_signal_receiver: &Receiver<()>,
+    command_status_refresh_secs: f64,
+) -> Result<(), String> {
     let ticks = tick(Duration::from_millis(
         (command_status_refresh_secs * 100f64) as u64,
     ));
 
     loop {
         match handle.try_wait() {
-            Ok(Some(_)) => break, // it has exited, and so can we
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => {
+                return Err(match status.code() {
+                    Some(code) => format!("'{}' exited with the status {}", program, code),
+                    None => format!("'{}' was stopped by a signal", program),
+                })
+            }
             Ok(None) => {
                 select! {
                     recv(ticks) -> _ => {
                     }
                     recv(interrupt_signal_receiver) -> _ => {
-                        handle.kill().expect("Could not kill the command");
-                        break; // terminate the child and exit
+                        // it was stopped on purpose, so it is not an error
+                        return handle
+                            .kill()
+                            .map_err(|err| format!("Could not stop '{}': {}", program, err));
                     }
                 }
             }
-            Err(e) => panic!("Error attempting to wait for a command: {}", e),
+            Err(err) => return Err(format!("Could not wait for '{}': {}", program, err)),
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        thread,
+        time::{Duration, Instant},
+    };
 
-    if pause_before_exiting {
-        println!("The command has terminated. Press ENTER to continue.");
-        let stdin = stdin();
-        let mut buf = String::new();
-        stdin
-            .lock()
-            .read_line(&mut buf)
-            .expect("Could not read the input");
+    use unicode_width::UnicodeWidthStr;
+
+    use crate::{
+        compile_time_settings::MAX_COUNTED_CHILDREN, helper_types::SearchMatch, test_util::TestApp,
+        util::PrivateTempDir,
+    };
+
+    use super::{format_children_num, ChildrenCounter, FileTreeNode};
+
+    fn node(path: &str) -> FileTreeNode {
+        FileTreeNode::new(PathBuf::from(path))
     }
 
-    enter_captured_mode(terminal).expect("Could not re-enter the terminal capture");
+    #[test]
+    fn empty_query_matches_with_zero() {
+        let base_dir = Path::new("/fphile-test-root");
+        assert_eq!(
+            node("/fphile-test-root/file").compute_score("", SearchMatch::Name, base_dir),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn non_match_has_no_score() {
+        let base_dir = Path::new("/fphile-test-root");
+        assert_eq!(
+            node("/fphile-test-root/file").compute_score("xyz", SearchMatch::Name, base_dir),
+            None
+        );
+    }
+
+    #[test]
+    fn relative_path_matches_the_directories_too() {
+        let base_dir = Path::new("/fphile-test-root");
+        let item = node("/fphile-test-root/src/main.rs");
+        assert_eq!(
+            item.compute_score("srcmain", SearchMatch::Name, base_dir),
+            None
+        );
+        assert!(item
+            .compute_score("srcmain", SearchMatch::RelativePath, base_dir)
+            .is_some());
+    }
+
+    #[test]
+    fn wide_names_fit_into_the_row() {
+        let root = Path::new("/fphile-test-root");
+        let app = TestApp::new(root);
+        for name in ["日本語のファイル名.txt", "🎉🎉🎉 party.txt", "한국어"] {
+            let item = FileTreeNode::new(root.join(name));
+            for available_width in [1, 3, 5, 7, 9, 11, 13] {
+                let spans = item.get_tui_spans(&app.config, &app.app_state, available_width, None);
+                assert!(
+                    spans.width() <= available_width,
+                    "'{}' is wider than {} columns",
+                    spans
+                        .0
+                        .iter()
+                        .map(|span| span.content.as_ref())
+                        .collect::<String>(),
+                    available_width
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn wide_names_keep_the_line_number_and_the_extension() {
+        let root = Path::new("/fphile-test-root");
+        let app = TestApp::new(root);
+        let item = FileTreeNode::new(root.join("日本語のファイル名.txt"));
+
+        let spans = item.get_tui_spans(&app.config, &app.app_state, 13, Some(String::from("12 ")));
+        let text: String = spans.0.iter().map(|span| span.content.as_ref()).collect();
+
+        assert!(text.starts_with("12 "));
+        assert!(text.ends_with(".txt"));
+        assert!(text.width() <= 13);
+    }
+
+    /// Waits a bit for the counts, since they come from another thread
+    fn wait_for_counts(children_counter: &ChildrenCounter) -> Vec<(PathBuf, Option<usize>)> {
+        let started_at = Instant::now();
+        while started_at.elapsed() < Duration::from_secs(2) {
+            let children_nums = children_counter.poll();
+            if !children_nums.is_empty() {
+                return children_nums;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        vec![]
+    }
+
+    #[test]
+    fn only_modified_directories_are_counted_again() {
+        let temp_dir = PrivateTempDir::new("fphile-test-children").unwrap();
+        let unchanged_dir = temp_dir.get_path().join("unchanged");
+        let changed_dir = temp_dir.get_path().join("changed");
+        fs::create_dir_all(&unchanged_dir).unwrap();
+        fs::create_dir_all(&changed_dir).unwrap();
+        fs::write(changed_dir.join("first"), "").unwrap();
+        let mut children_counter = ChildrenCounter::new();
+
+        children_counter.count(changed_dir.clone());
+        assert!(children_counter.is_pending(&changed_dir));
+        assert_eq!(
+            wait_for_counts(&children_counter),
+            vec![(changed_dir.clone(), Some(1))]
+        );
+        children_counter.finish(&changed_dir);
+        children_counter.count(unchanged_dir.clone());
+        assert_eq!(
+            wait_for_counts(&children_counter),
+            vec![(unchanged_dir.clone(), Some(0))]
+        );
+
+        fs::write(changed_dir.join("second"), "").unwrap();
+        children_counter.recount([unchanged_dir.clone(), changed_dir.clone()]);
+        let children_nums = wait_for_counts(&children_counter);
+        // a count that was forgotten is sent back even though the directory did not change
+        children_counter.count(unchanged_dir.clone());
+        let forgotten_children_nums = wait_for_counts(&children_counter);
+
+        assert!(!children_counter.is_pending(&changed_dir));
+        assert_eq!(children_nums, vec![(changed_dir, Some(2))]);
+        assert_eq!(forgotten_children_nums, vec![(unchanged_dir, Some(0))]);
+    }
+
+    #[test]
+    fn children_nums_past_the_limit_are_shown_as_the_limit() {
+        assert_eq!(format_children_num(None), "?");
+        assert_eq!(format_children_num(Some(&None)), "?");
+        assert_eq!(format_children_num(Some(&Some(12))), "12");
+        assert_eq!(
+            format_children_num(Some(&Some(MAX_COUNTED_CHILDREN))),
+            MAX_COUNTED_CHILDREN.to_string()
+        );
+        assert_eq!(
+            format_children_num(Some(&Some(MAX_COUNTED_CHILDREN + 1))),
+            format!("{}+", MAX_COUNTED_CHILDREN)
+        );
+    }
 }
@@ -0,0 +1,151 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    directory_tree::FileTreeNode,
+    modes::delete_mode::{
+        copy_path, delete_file_tree_node, move_path, touch_path, trash_file_tree_node,
+    },
+};
+
+/// The changes that the actions make to the files. They all go through this, so that `--dry-run` can log them instead
+pub trait Filesystem {
+    /// Deletes a file, or a directory with everything in it
+    fn delete(&mut self, path: &Path) -> io::Result<()>;
+    fn trash(&mut self, path: &Path) -> io::Result<()>;
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Copies a file or a directory, refusing to overwrite anything
+    fn copy(&mut self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Moves a file or a directory, refusing to overwrite anything
+    fn move_path(&mut self, from: &Path, to: &Path) -> io::Result<()>;
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()>;
+    /// Creates the file, or empties it if it exists, and writes the contents into it
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()>;
+    fn touch(&mut self, path: &Path) -> io::Result<()>;
+    /// Whether to start a background job that changes the files, like extracting an archive
+    fn start_job(&mut self, description: &str) -> bool;
+    fn is_dry_run(&self) -> bool;
+    /// The operations that were only logged, oldest first
+    fn get_log(&self) -> &[String];
+}
+
+/// Changes the files for real
+pub struct RealFilesystem;
+
+impl Filesystem for RealFilesystem {
+    fn delete(&mut self, path: &Path) -> io::Result<()> {
+        delete_file_tree_node(&FileTreeNode::new(path.to_owned()))
+    }
+
+    fn trash(&mut self, path: &Path) -> io::Result<()> {
+        trash_file_tree_node(&FileTreeNode::new(path.to_owned()))
+            .map_err(|err| io::Error::other(err.to_string()))
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn copy(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        copy_path(from, to)
+    }
+
+    fn move_path(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        move_path(from, to)
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        File::create(path)?.write_all(contents)
+    }
+
+    fn touch(&mut self, path: &Path) -> io::Result<()> {
+        touch_path(path)
+    }
+
+    fn start_job(&mut self, _description: &str) -> bool {
+        true
+    }
+
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+
+    fn get_log(&self) -> &[String] {
+        &[]
+    }
+}
+
+/// Leaves the files alone and only logs what would have been done
+#[derive(Default)]
+pub struct DryRunFilesystem {
+    log: Vec<String>,
+}
+
+impl DryRunFilesystem {
+    fn record(&mut self, operation: String) -> io::Result<()> {
+        self.log.push(operation);
+        Ok(())
+    }
+}
+
+impl Filesystem for DryRunFilesystem {
+    fn delete(&mut self, path: &Path) -> io::Result<()> {
+        self.record(format!("delete '{}'", path.display()))
+    }
+
+    fn trash(&mut self, path: &Path) -> io::Result<()> {
+        self.record(format!("move '{}' to the trash", path.display()))
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        self.record(format!("rename '{}' to '{}'", from.display(), to.display()))
+    }
+
+    fn copy(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        self.record(format!("copy '{}' to '{}'", from.display(), to.display()))
+    }
+
+    fn move_path(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        self.record(format!("move '{}' to '{}'", from.display(), to.display()))
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        // the parent directories of new files are usually there already, which is not worth logging
+        if path.is_dir() {
+            return Ok(());
+        }
+        self.record(format!("create the directory '{}'", path.display()))
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.record(format!(
+            "write {} byte(s) to '{}'",
+            contents.len(),
+            path.display()
+        ))
+    }
+
+    fn touch(&mut self, path: &Path) -> io::Result<()> {
+        self.record(format!("touch '{}'", path.display()))
+    }
+
+    fn start_job(&mut self, description: &str) -> bool {
+        self.log.push(description.to_owned());
+        false
+    }
+
+    fn is_dry_run(&self) -> bool {
+        true
+    }
+
+    fn get_log(&self) -> &[String] {
+        &self.log
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compile_time_settings::MAX_VISITED_DIRS, directory_tree::FileTreeNode,
+    helper_types::SearchMatch,
+};
+
+const HOUR_SECS: u64 = 60 * 60;
+const DAY_SECS: u64 = 24 * HOUR_SECS;
+const WEEK_SECS: u64 = 7 * DAY_SECS;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct DirVisits {
+    visits_num: u64,
+    // seconds since the unix epoch
+    last_visit: u64,
+}
+
+impl DirVisits {
+    /// Like zoxide, recent visits count for more than old ones
+    fn get_frecency(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_visit);
+        let recency_weight = if age < HOUR_SECS {
+            4.0
+        } else if age < DAY_SECS {
+            2.0
+        } else if age < WEEK_SECS {
+            0.5
+        } else {
+            0.25
+        };
+        self.visits_num as f64 * recency_weight
+    }
+}
+
+/// The directories that were visited, and how often and how recently, so that they can be jumped back to
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct VisitedDirs {
+    dirs: BTreeMap<PathBuf, DirVisits>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    // when the first visit that is not on the disk yet was made
+    #[serde(skip)]
+    unsaved_since: Option<Instant>,
+}
+
+impl VisitedDirs {
+    /// Reads the database from `path`. A missing file means that nothing was visited yet
+    pub fn load(path: Option<PathBuf>) -> io::Result<Self> {
+        let mut visited_dirs = match &path {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(contents) => serde_json::from_str(&contents)?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => VisitedDirs::default(),
+                Err(err) => return Err(err),
+            },
+            None => VisitedDirs::default(),
+        };
+        visited_dirs.path = path;
+        Ok(visited_dirs)
+    }
+
+    /// Where the database is kept when `visited_dirs_path` is not set, e.g. "~/.local/share/fphile/visited_dirs.json"
+    pub fn get_default_path() -> Option<PathBuf> {
+        let data_dir = env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+            .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+        Some(data_dir.join("fphile").join("visited_dirs.json"))
+    }
+
+    /// Counts a visit to the directory. It is only written to the disk by `save`
+    pub fn record_visit(&mut self, dir: &Path) {
+        let now = get_now();
+        let dir_visits = self.dirs.entry(dir.to_owned()).or_insert(DirVisits {
+            visits_num: 0,
+            last_visit: now,
+        });
+        dir_visits.visits_num += 1;
+        dir_visits.last_visit = now;
+
+        // forget the least useful directories, so that the file does not grow forever
+        if self.dirs.len() > MAX_VISITED_DIRS {
+            let mut frecencies: Vec<_> = self
+                .dirs
+                .iter()
+                .map(|(path, dir_visits)| (dir_visits.get_frecency(now), path.clone()))
+                .collect();
+            frecencies.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for (_, path) in frecencies
+                .into_iter()
+                .take(self.dirs.len() - MAX_VISITED_DIRS)
+            {
+                self.dirs.remove(&path);
+            }
+        }
+        self.unsaved_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Whether there are visits that have not been written to the disk for at least `save_interval`
+    pub fn is_save_due(&self, save_interval: Duration) -> bool {
+        self.unsaved_since
+            .is_some_and(|unsaved_since| unsaved_since.elapsed() >= save_interval)
+    }
+
+    /// Writes the database to the disk if any visits were recorded since the last time
+    pub fn save(&mut self) -> io::Result<()> {
+        let path = match (&self.path, self.unsaved_since) {
+            (Some(path), Some(_)) => path,
+            _ => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // the file is replaced in one go, so that it is never left half written if the app dies
+        // or another instance saves at the same time
+        let mut temp_file_name = path.file_name().unwrap_or_default().to_owned();
+        temp_file_name.push(format!(".{}.tmp", process::id()));
+        let temp_path = path.with_file_name(temp_file_name);
+        let result = fs::write(&temp_path, serde_json::to_string(self)?)
+            .and_then(|()| fs::rename(&temp_path, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+            // try again after another interval instead of on every tick
+            self.unsaved_since = Some(Instant::now());
+        } else {
+            self.unsaved_since = None;
+        }
+        result
+    }
+
+    /// The best `max_num` directories whose names match the query, best first.
+    /// The fuzzy score is weighted by how often and how recently the directory was visited
+    pub fn get_matches(&self, query: &str, max_num: usize) -> Vec<PathBuf> {
+        let now = get_now();
+        let mut matches: Vec<_> = self
+            .dirs
+            .iter()
+            .filter_map(|(path, dir_visits)| {
+                let frecency_weight = (1.0 + dir_visits.get_frecency(now)).ln();
+                let rank = if query.is_empty() {
+                    frecency_weight
+                } else {
+                    // only the name, since the parent directories of every path would match too
+                    FileTreeNode::new(path.clone()).compute_score(query, SearchMatch::Name, path)?
+                        as f64
+                        * frecency_weight
+                };
+                Some((rank, path))
+            })
+            .collect();
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+        matches
+            .into_iter()
+            .map(|(_, path)| path.clone())
+            // the directories that were deleted since are left in the file, since they might come back
+            .filter(|path| path.is_dir())
+            .take(max_num)
+            .collect()
+    }
+}
+
+fn get_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, time::Duration};
+
+    use crate::util::PrivateTempDir;
+
+    use super::VisitedDirs;
+
+    #[test]
+    fn visits_are_saved_only_when_asked_and_without_leftovers() {
+        let temp_dir = PrivateTempDir::new("fphile-test-frecency").unwrap();
+        let path = temp_dir.get_path().join("data").join("visited_dirs.json");
+        let mut visited_dirs = VisitedDirs::load(Some(path.clone())).unwrap();
+
+        visited_dirs.record_visit(Path::new("/fphile-test-root"));
+        assert!(!path.exists());
+        assert!(visited_dirs.is_save_due(Duration::ZERO));
+        assert!(!visited_dirs.is_save_due(Duration::from_secs(60)));
+
+        visited_dirs.save().unwrap();
+        assert!(!visited_dirs.is_save_due(Duration::ZERO));
+        // only the database is left, not the temporary file that it was written to
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        let loaded_visited_dirs = VisitedDirs::load(Some(path)).unwrap();
+        assert!(loaded_visited_dirs
+            .dirs
+            .contains_key(Path::new("/fphile-test-root")));
+    }
+}
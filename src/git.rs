@@ -0,0 +1,211 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use crossbeam_channel::{bounded, Receiver};
+use git2::{IndexAddOption, Repository, Status, StatusOptions};
+use tui::style::Color;
+
+/// The git status shown next to an entry. The later variants are more important,
+/// so a directory shows the greatest status of its contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GitFileStatus {
+    Ignored,
+    Untracked,
+    Added,
+    Modified,
+}
+
+impl GitFileStatus {
+    fn from_git2(status: Status) -> Option<Self> {
+        if status.intersects(
+            Status::INDEX_MODIFIED
+                | Status::INDEX_DELETED
+                | Status::INDEX_RENAMED
+                | Status::INDEX_TYPECHANGE
+                | Status::WT_MODIFIED
+                | Status::WT_DELETED
+                | Status::WT_RENAMED
+                | Status::WT_TYPECHANGE
+                | Status::CONFLICTED,
+        ) {
+            Some(GitFileStatus::Modified)
+        } else if status.contains(Status::INDEX_NEW) {
+            Some(GitFileStatus::Added)
+        } else if status.contains(Status::WT_NEW) {
+            Some(GitFileStatus::Untracked)
+        } else if status.contains(Status::IGNORED) {
+            Some(GitFileStatus::Ignored)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_letter(&self) -> &'static str {
+        match self {
+            GitFileStatus::Ignored => "!",
+            GitFileStatus::Untracked => "?",
+            GitFileStatus::Added => "A",
+            GitFileStatus::Modified => "M",
+        }
+    }
+
+    pub fn get_color(&self) -> Color {
+        match self {
+            GitFileStatus::Ignored => Color::DarkGray,
+            GitFileStatus::Untracked => Color::Red,
+            GitFileStatus::Added => Color::Green,
+            GitFileStatus::Modified => Color::Yellow,
+        }
+    }
+}
+
+/// Maps the paths of the entries (in the same form as `FileTreeNode::get_path_buf`) to their statuses
+pub type GitStatuses = BTreeMap<PathBuf, GitFileStatus>;
+
+/// The statuses of the entries inside `dir`, including the ones in its subdirectories.
+/// None if `dir` is not inside a git repository
+pub fn get_git_statuses(dir: &Path) -> Option<GitStatuses> {
+    let repo = Repository::discover(dir).ok()?;
+    // bare repositories have no files to show the status of
+    let workdir = repo.workdir()?.canonicalize().ok()?;
+    // the paths from git are relative to the root of the repository, which might be reached through a symlink
+    let dir_in_repo = dir
+        .canonicalize()
+        .ok()?
+        .strip_prefix(&workdir)
+        .ok()?
+        .to_owned();
+
+    let mut options = StatusOptions::new();
+    // untracked and ignored directories are reported as a whole, which keeps e.g. "target" cheap
+    options
+        .include_untracked(true)
+        .include_ignored(true)
+        .recurse_untracked_dirs(false)
+        .recurse_ignored_dirs(false);
+    let statuses = repo.statuses(Some(&mut options)).ok()?;
+
+    let mut ret = GitStatuses::new();
+    for entry in statuses.iter() {
+        let status = match GitFileStatus::from_git2(entry.status()) {
+            Some(status) => status,
+            None => continue,
+        };
+        // git paths that are not valid UTF-8 are skipped
+        let path = match entry
+            .path()
+            .map(|path| Path::new(path).strip_prefix(&dir_in_repo))
+        {
+            Some(Ok(path)) => dir.join(path),
+            _ => continue,
+        };
+
+        // a directory containing an ignored file is not ignored itself
+        let num_to_mark = if status == GitFileStatus::Ignored {
+            1
+        } else {
+            usize::MAX
+        };
+        for ancestor in path
+            .ancestors()
+            .take_while(|ancestor| *ancestor != dir)
+            .take(num_to_mark)
+        {
+            let ancestor_status = ret.entry(ancestor.to_owned()).or_insert(status);
+            *ancestor_status = (*ancestor_status).max(status);
+        }
+    }
+    Some(ret)
+}
+
+/// The repository that `dir` is in, if it has a working directory
+pub fn discover_repository(dir: &Path) -> Option<Repository> {
+    Repository::discover(dir)
+        .ok()
+        .filter(|repo| repo.workdir().is_some())
+}
+
+/// Turns the paths of entries into pathspecs relative to the root of the repository
+fn get_pathspecs(repo: &Repository, paths: &[PathBuf]) -> Result<Vec<String>, git2::Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| git2::Error::from_str("The repository has no working directory"))?
+        .canonicalize()
+        .map_err(|err| git2::Error::from_str(&err.to_string()))?;
+    paths
+        .iter()
+        .map(|path| {
+            // only the parent is resolved, so that symlinks are staged as links rather than as their targets
+            let canonical_path = match (path.parent(), path.file_name()) {
+                (Some(parent), Some(file_name)) => parent
+                    .canonicalize()
+                    .map(|parent| parent.join(file_name))
+                    .map_err(|err| git2::Error::from_str(&err.to_string()))?,
+                _ => path.to_owned(),
+            };
+            canonical_path
+                .strip_prefix(&workdir)
+                .map(|relative_path| relative_path.to_string_lossy().into_owned())
+                .map_err(|_| {
+                    git2::Error::from_str(&format!(
+                        "'{}' is not in the repository",
+                        path.to_string_lossy()
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// Adds the current contents of the files and directories to the index, including deletions inside the directories
+pub fn stage_paths(repo: &Repository, paths: &[PathBuf]) -> Result<(), git2::Error> {
+    let pathspecs = get_pathspecs(repo, paths)?;
+    let mut index = repo.index()?;
+    // the names are taken literally, so e.g. "[a].txt" does not act as a pattern
+    index.add_all(&pathspecs, IndexAddOption::DISABLE_PATHSPEC_MATCH, None)?;
+    index.update_all(&pathspecs, None)?;
+    index.write()
+}
+
+/// Makes the index match HEAD for the files and directories, like `git reset -- <paths>`
+pub fn unstage_paths(repo: &Repository, paths: &[PathBuf]) -> Result<(), git2::Error> {
+    let pathspecs = get_pathspecs(repo, paths)?;
+    match repo.head().and_then(|head| head.peel_to_commit()) {
+        Ok(head_commit) => repo.reset_default(Some(head_commit.as_object()), &pathspecs),
+        // before the first commit, everything that is staged is new
+        Err(_) => {
+            let mut index = repo.index()?;
+            index.remove_all(&pathspecs, None)?;
+            index.write()
+        }
+    }
+}
+
+/// Gets the git statuses on a background thread, since that can take a while in big repositories
+pub struct GitStatusListing {
+    dir: PathBuf,
+    receiver: Receiver<Option<GitStatuses>>,
+}
+
+impl GitStatusListing {
+    pub fn new(dir: PathBuf) -> Self {
+        let (sender, receiver) = bounded(1);
+        let thread_dir = dir.clone();
+        thread::spawn(move || {
+            // this fails if the listing was replaced, in which case nobody needs the result
+            let _ = sender.send(get_git_statuses(&thread_dir));
+        });
+        GitStatusListing { dir, receiver }
+    }
+
+    pub fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The statuses if they are ready
+    pub fn poll(&self) -> Option<Option<GitStatuses>> {
+        self.receiver.try_recv().ok()
+    }
+}
@@ -1,20 +1,39 @@
 use crate::{
     actions::{ActionClosure, ActionMapper},
-    directory_tree::{get_file_cursor_index, FileTreeNode},
-    modes::{Mode, SimpleMode},
+    archive::ArchiveJob,
+    compile_time_settings::{
+        CASE_INSENSITIVE_NAMES_BY_DEFAULT, DEFAULT_CONFIG, DIR_CONFIG_FILE_NAME,
+        MAX_CACHED_CHILDREN_NUMS, MAX_CACHED_GIT_STATUSES, MAX_CACHED_LISTINGS, MAX_DIR_HISTORY,
+        MAX_TOASTS,
+    },
+    directory_tree::{
+        get_file_cursor_index, ChildrenCounter, DiskUsage, FileHashing, FileTreeNode,
+    },
+    frecency::VisitedDirs,
+    git::GitStatuses,
+    modes::{Mode, PreviewSearch, SimpleMode},
 };
+use arboard::Clipboard;
 use crossbeam_channel::{bounded, Receiver};
-use crossterm::event::KeyCode;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use std::{
+    borrow::Borrow,
     collections::BTreeMap,
-    fs,
-    io::{self, Error, ErrorKind},
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Error, ErrorKind, Write},
     ops::Deref,
-    path::Path,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 use serde::{Deserialize, Serialize};
-use tui::style::Style;
+use tui::{
+    layout::Rect,
+    style::{Color, Style},
+    widgets::{Block, BorderType, Borders},
+};
+use unicode_width::UnicodeWidthStr;
 
 type StringMap = BTreeMap<String, String>;
 
@@ -38,6 +57,11 @@ impl<T> TrackedModifiable<T> {
     pub fn reset_modified_flag(&mut self) {
         self.is_modified = false;
     }
+
+    /// Forces an update without changing anything
+    pub fn mark_modified(&mut self) {
+        self.is_modified = true;
+    }
 }
 
 impl<T> TrackedModifiable<T> {
@@ -55,24 +79,421 @@ impl<T> Deref for TrackedModifiable<T> {
     }
 }
 
+/// A map that forgets the entries that were stored the longest time ago once it has more than `capacity` of them.
+/// The caches are stored again whenever their directory is listed, so those are the least recently visited ones
+pub struct LruCache<K, V> {
+    entries: BTreeMap<K, (V, u64)>,
+    capacity: usize,
+    // counts the insertions, to tell which entries were stored the longest time ago
+    last_stored: u64,
+}
+
+impl<K: Ord + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LruCache {
+            entries: BTreeMap::new(),
+            capacity,
+            last_stored: 0,
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.entries.contains_key(key)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.entries.remove(key).map(|(value, _)| value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.last_stored += 1;
+        self.entries.insert(key, (value, self.last_stored));
+        if self.entries.len() > self.capacity {
+            let oldest_key = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, stored))| *stored)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest_key) = oldest_key {
+                self.entries.remove(&oldest_key);
+            }
+        }
+    }
+}
+
 pub struct AppState {
     pub mode: Mode,
     pub current_dir: FileTreeNode,
     pub input_reader: InputReader,
     pub error_popup: Option<ErrorPopup>,
+    pub info_popup: Option<InfoPopup>,
     pub error_message_line: Option<String>,
     pub selected_file: Option<FileTreeNode>,
+    // how many items are scrolled past and how many fit in the list, as of the last time that it was drawn
+    pub scroll_offset: usize,
+    pub visible_rows: usize,
+    // where the rows of the list were drawn, for finding the item under the mouse
+    pub list_area: Rect,
 
     pub entered_text: String,
+    // a byte index into `entered_text`, always on a character boundary
+    pub cursor_pos: usize,
+    // the matches of the last tab-completion, so that pressing tab again cycles through them
+    pub path_completion: Option<PathCompletion>,
+    // the index of the highlighted search result, with the query that it was picked for. Use get_search_cursor
+    pub search_cursor: Option<(String, usize)>,
 
-    pub marked_files: Vec<FileTreeNode>,
-    pub mark_type: MarkType,
+    // each marked file has its own mark, so that e.g. some files can be copied and others deleted at once
+    pub marked_files: BTreeMap<PathBuf, MarkType>,
+
+    // the name and the count of the last normal mode action that worked, for `repeat_last_action`
+    pub last_action: Option<(String, Option<usize>)>,
 
     pub interrupt_signal_receiver: Receiver<()>,
+
+    pub follow_symlinks: bool,
+
+    pub dir_history: Vec<PathBuf>,
+    pub history_pos: usize,
+    // the directory that was left last, with the file that was selected there, for `toggle_last_dir`
+    pub previous_dir: Option<(PathBuf, Option<FileTreeNode>)>,
+
+    pub config_reload_requested: bool,
+
+    // the sort chosen in this session. Use get_sort_key and get_is_sort_reversed, which take the dir config into account
+    pub sort_key: SortKey,
+    pub is_sort_reversed: bool,
+
+    // whether the entries from the `ignore` setting are hidden
+    pub is_ignore_active: bool,
+
+    // the overrides from the current directory's config file
+    pub dir_config: DirConfig,
+
+    // the last known contents of the visited directories, so that they can be shown straight away when coming back
+    pub dir_listing_cache: LruCache<PathBuf, Vec<FileTreeNode>>,
+    pub is_listing_outdated: bool,
+
+    // the git statuses of the entries in the current directory. None when not in a git repository or when they are not shown
+    pub git_statuses: Option<GitStatuses>,
+    // the last known git statuses of the visited directories, shown until they are updated
+    pub git_status_cache: LruCache<PathBuf, Option<GitStatuses>>,
+
+    // how many entries the shown directories have, counted when they are first shown. None when the directory can not be read
+    pub children_nums: LruCache<PathBuf, Option<usize>>,
+    pub children_counter: ChildrenCounter,
+    // set when the current directory was listed again, so that the directories on the screen are counted again
+    pub is_children_recount_requested: bool,
+
+    // the directories that are expanded in place (as in a tree view), with their contents listed when they were expanded
+    pub expanded_dirs: BTreeMap<PathBuf, Vec<FileTreeNode>>,
+    // the expanded directories that are still being listed, with when they were expanded, for the spinner
+    pub loading_expanded_dirs: BTreeMap<PathBuf, Instant>,
+
+    // created on first use. It is kept around because on some platforms the copied text disappears when it is dropped
+    pub clipboard: Option<Clipboard>,
+
+    pub is_read_only: bool,
+    // whether renames that only change the case go through a temporary name
+    pub case_insensitive_names: bool,
+
+    pub view_mode: ViewMode,
+    pub is_preview_shown: bool,
+    // the file in the preview pane and when the cursor got to it, for `preview_delay_ms`
+    pub preview_selected_at: Option<(PathBuf, Instant)>,
+    // with `lazy_preview`, the file that `load_preview` was used on. Only it is read for the preview
+    pub loaded_preview: Option<PathBuf>,
+    pub line_numbers: LineNumbers,
+    pub path_display: PathDisplay,
+
+    // Some while `show_hash` is hashing a file in the background
+    pub file_hashing: Option<FileHashing>,
+
+    // Some while the disk usage overlay is open
+    pub disk_usage: Option<DiskUsage>,
+
+    // Some while an archive is being created in the background
+    pub archive_job: Option<ArchiveJob>,
+
+    // Some after searching in the preview, until the mode is reset
+    pub preview_search: Option<PreviewSearch>,
+
+    // the index of the item where the visual selection started. The next mark action marks everything from it to the cursor
+    pub visual_anchor: Option<usize>,
+    // whether the visual selection was started by pressing the mouse button, so that releasing it marks the selection
+    pub is_mouse_dragging: bool,
+
+    // the files that were yanked or cut, by the register that they are in
+    pub registers: BTreeMap<char, Register>,
+    // picked in the registers overlay, and used by the next yank, cut or paste instead of the unnamed register
+    pub pending_register: Option<char>,
+
+    // how often and how recently the directories were visited, for jump_to_dir
+    pub visited_dirs: VisitedDirs,
+
+    // where `cd_and_quit` writes the current directory for the shell to go to, from --last-dir-path
+    pub last_dir_path: Option<PathBuf>,
+    // set by `cd_and_quit`, so that the directory is written once the terminal is restored
+    pub is_cd_requested: bool,
+
+    // the messages about what the actions did, with when they were shown. They disappear after `toast_secs`
+    pub toasts: Vec<(String, Instant)>,
 }
 
+/// Files held by a register until they are pasted
+pub struct Register {
+    pub files: Vec<FileTreeNode>,
+    // cut files are moved when they are pasted, and the others are copied
+    pub is_cut: bool,
+}
+
+pub struct PathCompletion {
+    pub candidates: Vec<String>,
+    pub index: Option<usize>,
+    // the text that the completion has left in the input. If it was edited since, the completion starts over
+    pub completed_text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MarkType {
     Delete,
+    // copied into the current directory when the marks are applied
+    Copy,
+    // moved into the current directory when the marks are applied
+    Cut,
+}
+
+impl MarkType {
+    /// The letter shown next to the marked files
+    pub fn get_letter(&self) -> &'static str {
+        match self {
+            MarkType::Delete => "D",
+            MarkType::Copy => "C",
+            MarkType::Cut => "X",
+        }
+    }
+    pub fn get_color(&self) -> Color {
+        match self {
+            MarkType::Delete => Color::Red,
+            MarkType::Copy => Color::Green,
+            MarkType::Cut => Color::Yellow,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Extension,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewMode {
+    /// Only the names
+    #[default]
+    Compact,
+    /// The permissions, sizes and modification times next to the names
+    Detailed,
+}
+
+/// How the path of the current directory is shown in the title
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathDisplay {
+    Absolute,
+    /// e.g. "~/projects" for paths inside the home directory
+    Home,
+    /// relative to `path_display_root`, starting with the root's name
+    Root,
+}
+
+impl PathDisplay {
+    /// The next form to switch to. `Root` is skipped when there is no root to show the path relative to
+    pub fn get_next(&self, has_root: bool) -> Self {
+        match self {
+            PathDisplay::Absolute => PathDisplay::Home,
+            PathDisplay::Home if has_root => PathDisplay::Root,
+            PathDisplay::Home | PathDisplay::Root => PathDisplay::Absolute,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    /// e.g. "2h ago" for recent times
+    #[default]
+    Relative,
+    Absolute,
+}
+
+/// What the search query is fuzzy matched against
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMatch {
+    /// e.g. "main.rs"
+    #[default]
+    Name,
+    /// e.g. "src/main.rs" when in the directory above `src`
+    RelativePath,
+}
+
+/// Which part of a name that is too long to fit is replaced with an ellipsis
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NameTruncation {
+    Start,
+    #[default]
+    Middle,
+    End,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    #[default]
+    Sha256,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    Tar,
+    TarGz,
+}
+
+/// Where the directories go in the listing
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupDirectories {
+    #[default]
+    First,
+    Last,
+    /// Sorted together with the files, e.g. so that the newest entry is at the top whatever it is
+    Mixed,
+}
+
+/// The numbers shown before the names of the entries
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LineNumbers {
+    #[default]
+    Off,
+    /// The position of each entry, counting from 1
+    Absolute,
+    /// How far each entry is from the cursor, like vim's `relativenumber`
+    Relative,
+    /// Like `Relative`, but with the position of the entry at the cursor instead of 0
+    Hybrid,
+}
+
+/// The lines drawn around the panes and popups
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BorderStyle {
+    #[default]
+    Plain,
+    Rounded,
+    Double,
+    Thick,
+    /// Only the titles are shown
+    None,
+}
+
+/// The terminal colors that can be used in the config
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    Gray,
+    DarkGray,
+    LightRed,
+    LightGreen,
+    LightYellow,
+    LightBlue,
+    LightMagenta,
+    LightCyan,
+    White,
+}
+
+impl From<ConfigColor> for Color {
+    fn from(color: ConfigColor) -> Self {
+        match color {
+            ConfigColor::Black => Color::Black,
+            ConfigColor::Red => Color::Red,
+            ConfigColor::Green => Color::Green,
+            ConfigColor::Yellow => Color::Yellow,
+            ConfigColor::Blue => Color::Blue,
+            ConfigColor::Magenta => Color::Magenta,
+            ConfigColor::Cyan => Color::Cyan,
+            ConfigColor::Gray => Color::Gray,
+            ConfigColor::DarkGray => Color::DarkGray,
+            ConfigColor::LightRed => Color::LightRed,
+            ConfigColor::LightGreen => Color::LightGreen,
+            ConfigColor::LightYellow => Color::LightYellow,
+            ConfigColor::LightBlue => Color::LightBlue,
+            ConfigColor::LightMagenta => Color::LightMagenta,
+            ConfigColor::LightCyan => Color::LightCyan,
+            ConfigColor::White => Color::White,
+        }
+    }
+}
+
+/// Which of the actions that can lose data ask before doing it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct Confirmations {
+    /// Deleting files permanently, with `delete_instantly` or the delete marks
+    pub delete: bool,
+    /// Moving files to the trash
+    pub trash: bool,
+    /// Replacing existing files when renaming or extracting
+    pub overwrite: bool,
+    /// Moving the files marked with the cut mark
+    #[serde(rename = "move")]
+    pub move_files: bool,
+}
+
+impl Default for Confirmations {
+    fn default() -> Self {
+        Confirmations {
+            delete: true,
+            trash: false,
+            overwrite: true,
+            move_files: false,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -83,13 +504,165 @@ pub struct AppSettings {
     pub text_input_mode_key_bindings: StringMap,
     pub min_distance_from_cursor_to_bottom: usize,
     pub default_file_editor_command: Option<Vec<String>>,
+    /// Opening files larger than this many megabytes asks for confirmation first. Defaults to 100
+    pub large_file_threshold_mb: Option<u64>,
+    /// Renaming a file only asks for the new name without the extension, and keeps the old extension
+    #[serde(default)]
+    pub rename_keeps_extension: bool,
+    /// Whether the filesystem treats names that only differ in case as the same, so that renaming e.g. "File" to
+    /// "file" has to go through a temporary name. Defaults to true on macOS and Windows
+    pub case_insensitive_names: Option<bool>,
+    /// Maps file extensions to the commands that open them instead of the editor
+    #[serde(default)]
+    pub open_commands: BTreeMap<String, Vec<String>>,
+    /// The command that opens files and directories in the GUI, e.g. a file manager
+    pub os_open_command: Option<Vec<String>>,
+    /// The command that opens `<DIR>` in a new pane of the terminal multiplexer. Defaults to the one for tmux or zellij
+    pub multiplexer_command: Option<Vec<String>>,
+    /// The command that opens a new terminal window in `<DIR>`. Defaults to the program in $TERMINAL
+    pub terminal_command: Option<Vec<String>>,
     pub command_status_refresh_secs: f64,
+    /// How often to list the current directory again to pick up changes made outside the app
+    pub listing_refresh_secs: Option<f64>,
+    /// Disables all the actions that change files
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    #[serde(default)]
+    pub sort_key: SortKey,
+    #[serde(default)]
+    pub group_directories: GroupDirectories,
+    #[serde(default)]
+    pub search_match: SearchMatch,
+    #[serde(default)]
+    pub show_icons: bool,
+    #[serde(default)]
+    pub icons: StringMap,
+    /// Maps file extensions to the contents of newly created files
+    #[serde(default)]
+    pub templates: BTreeMap<String, FileTemplate>,
+
+    /// The view that the app starts with
+    #[serde(default)]
+    pub view_mode: ViewMode,
+    /// How modification times are shown
+    #[serde(default)]
+    pub time_format: TimeFormat,
+    /// Names wider than this many columns are shortened even if there is room for them
+    pub max_name_width: Option<usize>,
+    #[serde(default)]
+    pub name_truncation: NameTruncation,
+    /// How the path of the current directory is shown. Defaults to `home` on Unix and `absolute` elsewhere
+    pub path_display: Option<PathDisplay>,
+    /// The directory that the `root` path display is relative to, e.g. the directory with all the projects
+    pub path_display_root: Option<PathBuf>,
+    /// The directory that the app starts in instead of the current one, unless `--dir` or `--select` is given
+    pub start_dir: Option<String>,
+    /// How long the messages about what the actions did are shown for. Defaults to 3
+    pub toast_secs: Option<f64>,
+    /// Whether the app starts with the preview pane shown. Defaults to true
+    pub show_preview: Option<bool>,
+    /// How much of the width the preview pane takes up. Defaults to 50
+    pub preview_width_percent: Option<u16>,
+    /// How long the cursor has to stay on a file before it is read for the preview, so that holding down a key
+    /// does not read every file on the way. Defaults to 0
+    pub preview_delay_ms: Option<u64>,
+    /// Only reads the selected file for the preview after `load_preview`, e.g. on slow network drives
+    #[serde(default)]
+    pub lazy_preview: bool,
+    /// Which numbers the app starts with next to the entries
+    #[serde(default)]
+    pub line_numbers: LineNumbers,
+    /// Whether to show files starting with a dot. Defaults to true
+    pub show_hidden: Option<bool>,
+    /// Names of entries that are not shown in any directory, e.g. "target" or ".git"
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Treat the `ignore` entries as glob patterns, e.g. "*.o", instead of exact names
+    #[serde(default)]
+    pub ignore_globs: bool,
+    /// Used by `show_hash`
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    /// The name of the copies made by `backup`, e.g. "<NAME>.bak". Defaults to "<STEM>.%Y-%m-%d<EXT>"
+    pub backup_name_format: Option<String>,
+    /// The format of the archives made by `archive` when the typed name does not say which one to use
+    #[serde(default)]
+    pub archive_format: ArchiveFormat,
+    /// Shows whether the entries are modified, added, untracked or ignored when in a git repository
+    #[serde(default)]
+    pub show_git_status: bool,
+    /// Shows how many entries each directory has next to its name
+    #[serde(default)]
+    pub show_children_num: bool,
+    /// The actions whose count can also be typed in the middle of their key sequence, e.g. "g 3 v" as well as "3 g v"
+    #[serde(default)]
+    pub trailing_count_actions: Vec<String>,
+    /// The actions that `repeat_last_action` does not remember, e.g. moving the cursor
+    #[serde(default)]
+    pub unrepeated_actions: Vec<String>,
+    /// Named commands that can be picked from the command menu
+    #[serde(default)]
+    pub commands: BTreeMap<String, CustomCommand>,
+    #[serde(default)]
+    pub confirmations: Confirmations,
+    /// Where the visited directories for `jump_to_dir` are kept. Defaults to "visited_dirs.json" in the data directory
+    pub visited_dirs_path: Option<PathBuf>,
+    /// Whether to record the visited directories on disk. Defaults to true
+    pub remember_visited_dirs: Option<bool>,
+    /// Named directories that files can be copied into without going there first
+    #[serde(default)]
+    pub bookmarks: BTreeMap<String, String>,
+    /// Key sequences that go straight to directories, e.g. "g h" for the home directory.
+    /// They are added to the normal mode key bindings as `quick_nav`, unless the sequence is bound to something else
+    #[serde(default)]
+    pub quick_nav: BTreeMap<String, String>,
+    #[serde(default)]
+    pub border_style: BorderStyle,
+    /// The color of the borders, except for those of the error popups. Defaults to the terminal's text color
+    pub border_color: Option<ConfigColor>,
+
+    /// The path of the config file that these settings were loaded from
+    #[serde(skip)]
+    pub loaded_from: Option<PathBuf>,
+}
+
+/// The settings from a directory's `.fphile.toml`. Only the fields that are set override the global ones
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DirConfig {
+    pub sort_key: Option<SortKey>,
+    pub reverse_sort: Option<bool>,
+    pub show_hidden: Option<bool>,
+    /// Names of the entries that are not shown in this directory, e.g. "target"
+    #[serde(default)]
+    pub hide: Vec<String>,
+}
+
+/// The initial contents of a new file, either written directly in the config or read from another file
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum FileTemplate {
+    Inline(String),
+    FromFile { path: PathBuf },
+}
+
+/// A command from the `[commands]` config section.
+/// `<FILE>` is replaced with the name of the selected file and `<DIR>` with the path of the current directory
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CustomCommand {
+    pub command: Vec<String>,
+    /// Wait for a key press after the command finishes, so that its output can be read
+    #[serde(default)]
+    pub pause: bool,
 }
 
 #[derive(Clone)]
 pub struct StyleSet {
     pub file: Style,
     pub dir: Style,
+    pub executable: Style,
 }
 
 pub enum InputReaderDigestResult {
@@ -107,6 +680,14 @@ pub struct ErrorPopup {
     pub desc: String,
 }
 
+/// Like `ErrorPopup`, but for results that are not errors, e.g. a file's hash
+pub struct InfoPopup {
+    pub title: String,
+    pub desc: String,
+    // the lines are the rows of a table, which only line up when they are not centered
+    pub is_table: bool,
+}
+
 pub trait FindKeyByActionName {
     fn find_key_by_action_name<'a>(&'a self, action_name: &'a str) -> Option<&'a str>;
 }
@@ -120,38 +701,166 @@ impl FindKeyByActionName for StringMap {
 
 impl AppSettings {
     pub fn load_config<P: AsRef<Path>>(paths: Vec<P>) -> io::Result<AppSettings> {
-        let config = paths
-            .iter()
-            .find_map(|path| fs::read_to_string(path).ok())
-            .ok_or(Error::new(
+        let mut found_config = None;
+        for path in &paths {
+            match fs::read_to_string(path) {
+                Ok(config) => {
+                    found_config = Some((path.as_ref(), config));
+                    break;
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                // e.g. a config that is not UTF-8 should not be taken as a missing one, which would be replaced
+                Err(err) => {
+                    return Err(Error::new(
+                        err.kind(),
+                        format!("Could not read {}: {}", path.as_ref().display(), err),
+                    ))
+                }
+            }
+        }
+        let (path, config) = found_config.ok_or_else(|| {
+            let searched_paths: Vec<_> = paths
+                .iter()
+                .map(|path| path.as_ref().to_string_lossy().into_owned())
+                .collect();
+            Error::new(
                 ErrorKind::NotFound,
-                "Could not find a config file",
-            ))?;
+                format!(
+                    "Could not find a config file. Looked for {}",
+                    searched_paths.join(", ")
+                ),
+            )
+        })?;
 
-        let config: AppSettings = toml::from_str(config.as_str())?;
+        let mut config: AppSettings = toml::from_str(config.as_str())?;
+        for key_sequence in config.quick_nav.keys() {
+            config
+                .normal_mode_key_bindings
+                .entry(key_sequence.clone())
+                .or_insert_with(|| String::from("quick_nav"));
+        }
+        // make it absolute so that it still points to the same file after changing directories
+        config.loaded_from = Some(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
         Ok(config)
     }
+
+    /// Where the user's own config file goes, e.g. "~/.config/fphile/config.toml". These come before the other places
+    pub fn get_user_config_paths() -> Vec<PathBuf> {
+        let config_dirs = [
+            env::var_os("XDG_CONFIG_HOME").map(PathBuf::from),
+            env::var_os("HOME").map(|home| Path::new(&home).join(".config")),
+            env::var_os("APPDATA").map(PathBuf::from),
+        ];
+        let mut paths: Vec<PathBuf> = Vec::new();
+        // the XDG spec says to ignore relative paths, which includes the variables that are set but empty
+        for config_dir in config_dirs
+            .into_iter()
+            .flatten()
+            .filter(|config_dir| config_dir.is_absolute())
+        {
+            let path = config_dir.join("fphile").join("config.toml");
+            // XDG_CONFIG_HOME is often set to ~/.config anyway
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    /// Writes the default config to `path`. Without `replace_existing`, a file that is there already is left alone
+    /// and is an error
+    pub fn write_default_config(path: &Path, replace_existing: bool) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = if replace_existing {
+            File::create(path)?
+        } else {
+            OpenOptions::new().write(true).create_new(true).open(path)?
+        };
+        file.write_all(DEFAULT_CONFIG.as_bytes())
+    }
+
+    /// Finds a bookmark by its number (counting from 1 in the order of the names) or by its name
+    pub fn get_bookmark(&self, number_or_name: &str) -> Option<(&String, &String)> {
+        match number_or_name.parse::<usize>() {
+            Ok(number) => number
+                .checked_sub(1)
+                .and_then(|index| self.bookmarks.iter().nth(index)),
+            Err(_) => self.bookmarks.get_key_value(number_or_name),
+        }
+    }
+
+    pub fn get_preview_delay(&self) -> Duration {
+        Duration::from_millis(self.preview_delay_ms.unwrap_or(0))
+    }
+
+    /// A block with the borders from the config, which all the panes and popups are drawn in
+    pub fn get_block<'a>(&self) -> Block<'a> {
+        let block = Block::default().border_style(match self.border_color {
+            Some(color) => Style::default().fg(color.into()),
+            None => Style::default(),
+        });
+        let border_type = match self.border_style {
+            BorderStyle::Plain => BorderType::Plain,
+            BorderStyle::Rounded => BorderType::Rounded,
+            BorderStyle::Double => BorderType::Double,
+            BorderStyle::Thick => BorderType::Thick,
+            BorderStyle::None => return block.borders(Borders::NONE),
+        };
+        block.borders(Borders::ALL).border_type(border_type)
+    }
+}
+
+impl DirConfig {
+    /// Reads the directory's config file, if it has one
+    pub fn load(dir: &Path) -> io::Result<DirConfig> {
+        match fs::read_to_string(dir.join(DIR_CONFIG_FILE_NAME)) {
+            Ok(dir_config) => Ok(toml::from_str(dir_config.as_str())?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(DirConfig::default()),
+            Err(err) => Err(err),
+        }
+    }
 }
 
 impl AppState {
-    pub fn new(current_dir: FileTreeNode) -> Result<Self, ctrlc::Error> {
+    pub fn new(current_dir: FileTreeNode, config: &AppSettings) -> Result<Self, ctrlc::Error> {
         let (sender, receiver) = bounded(100);
         ctrlc::set_handler(move || {
             let _ = sender.send(());
         })?;
 
-        Ok(Self {
+        Ok(Self::with_interrupt_receiver(current_dir, config, receiver))
+    }
+
+    /// Like `new`, but the interrupts come from `receiver` instead of ctrl-c, which can only be handled once
+    pub fn with_interrupt_receiver(
+        current_dir: FileTreeNode,
+        config: &AppSettings,
+        receiver: Receiver<()>,
+    ) -> Self {
+        Self {
             mode: Mode::SimpleMode(SimpleMode::Normal),
+            dir_history: vec![current_dir.get_path_buf().to_owned()],
+            history_pos: 0,
+            previous_dir: None,
             current_dir,
             input_reader: InputReader {
                 modifier_key_sequence: String::new(),
                 verb_key_sequence: Vec::new(),
             },
             error_popup: None,
+            info_popup: None,
             error_message_line: None,
             selected_file: None,
+            scroll_offset: 0,
+            visible_rows: 0,
+            list_area: Rect::default(),
 
             entered_text: String::new(),
+            cursor_pos: 0,
+            path_completion: None,
+            search_cursor: None,
             // NOTE: this would look good for multi-selection, maybe we should use it in the future
             // file: Style::default()
             //     .bg(tui::style::Color::DarkGray)
@@ -159,30 +868,276 @@ impl AppState {
             // dir: Style::default()
             //     .bg(tui::style::Color::DarkGray)
             //     .fg(tui::style::Color::LightBlue),
-            mark_type: MarkType::Delete,
-            marked_files: vec![],
+            marked_files: BTreeMap::new(),
+            last_action: None,
 
             interrupt_signal_receiver: receiver,
-        })
+
+            follow_symlinks: config.follow_symlinks,
+
+            config_reload_requested: false,
+
+            sort_key: config.sort_key,
+            is_sort_reversed: false,
+
+            is_ignore_active: true,
+
+            dir_config: DirConfig::default(),
+
+            dir_listing_cache: LruCache::new(MAX_CACHED_LISTINGS),
+            is_listing_outdated: false,
+
+            git_statuses: None,
+            children_nums: LruCache::new(MAX_CACHED_CHILDREN_NUMS),
+            children_counter: ChildrenCounter::new(),
+            is_children_recount_requested: false,
+            git_status_cache: LruCache::new(MAX_CACHED_GIT_STATUSES),
+
+            expanded_dirs: BTreeMap::new(),
+            loading_expanded_dirs: BTreeMap::new(),
+
+            clipboard: None,
+
+            is_read_only: config.read_only,
+            case_insensitive_names: config
+                .case_insensitive_names
+                .unwrap_or(CASE_INSENSITIVE_NAMES_BY_DEFAULT),
+
+            view_mode: config.view_mode,
+            is_preview_shown: config.show_preview.unwrap_or(true),
+            preview_selected_at: None,
+            loaded_preview: None,
+            line_numbers: config.line_numbers,
+            path_display: config.path_display.unwrap_or(if cfg!(unix) {
+                PathDisplay::Home
+            } else {
+                PathDisplay::Absolute
+            }),
+
+            file_hashing: None,
+            archive_job: None,
+            preview_search: None,
+            visual_anchor: None,
+            is_mouse_dragging: false,
+            registers: BTreeMap::new(),
+            pending_register: None,
+            visited_dirs: VisitedDirs::default(),
+            last_dir_path: None,
+            is_cd_requested: false,
+            toasts: Vec::new(),
+
+            disk_usage: None,
+        }
+    }
+
+    fn get_clipboard(&mut self) -> Result<&mut Clipboard, arboard::Error> {
+        if self.clipboard.is_none() {
+            self.clipboard = Some(Clipboard::new()?);
+        }
+        Ok(self
+            .clipboard
+            .as_mut()
+            .expect("The clipboard should have been created"))
+    }
+
+    pub fn set_clipboard_text(&mut self, text: String) -> Result<(), arboard::Error> {
+        self.get_clipboard()?.set_text(text)
+    }
+
+    pub fn get_clipboard_text(&mut self) -> Result<String, arboard::Error> {
+        self.get_clipboard()?.get_text()
+    }
+
+    pub fn get_sort_key(&self) -> SortKey {
+        self.dir_config.sort_key.unwrap_or(self.sort_key)
+    }
+
+    pub fn get_is_sort_reversed(&self) -> bool {
+        self.dir_config
+            .reverse_sort
+            .unwrap_or(self.is_sort_reversed)
+    }
+
+    /// Makes the main loop list the current directory again, e.g. after an action has changed the files
+    pub fn invalidate_listing(&mut self) {
+        self.is_listing_outdated = true;
+    }
+
+    /// Starts counting the entries of the directories among `items` that were not counted yet, and after a refresh,
+    /// of the ones that were, in case they changed. The counts show up in `children_nums` once
+    /// `receive_children_nums` gets them
+    pub fn count_children(app_state: &mut TrackedModifiable<AppState>, items: &[FileTreeNode]) {
+        if app_state.is_children_recount_requested {
+            let app_state = app_state.get_mut();
+            app_state.is_children_recount_requested = false;
+            // only the modified ones are counted again
+            app_state.children_counter.recount(
+                items
+                    .iter()
+                    .map(FileTreeNode::get_path_buf)
+                    .filter(|path| app_state.children_nums.contains_key(*path))
+                    .cloned(),
+            );
+        }
+
+        let uncounted_dirs: Vec<_> = items
+            .iter()
+            .filter(|item| {
+                let path = item.get_path_buf();
+                item.is_dir()
+                    && !app_state.children_nums.contains_key(path)
+                    && !app_state.children_counter.is_pending(path)
+            })
+            .map(|item| item.get_path_buf().to_owned())
+            .collect();
+        // writing to the state forces a redraw, so do not do that when everything is already counted
+        if uncounted_dirs.is_empty() {
+            return;
+        }
+        let app_state = app_state.get_mut();
+        for dir in uncounted_dirs {
+            app_state.children_counter.count(dir);
+        }
+    }
+
+    /// Stores the counts of entries that have finished in the background
+    pub fn receive_children_nums(app_state: &mut TrackedModifiable<AppState>) {
+        let children_nums = app_state.children_counter.poll();
+        // recounting a directory that did not change sends nothing back, so this only redraws for new counts
+        if children_nums.is_empty() {
+            return;
+        }
+        let app_state = app_state.get_mut();
+        for (path, children_num) in children_nums {
+            app_state.children_counter.finish(&path);
+            app_state.children_nums.insert(path, children_num);
+        }
+    }
+
+    /// The line (counting from 1) of the current match of the search in the preview, if it is about this file
+    pub fn get_preview_search_line(&self, path: &Path) -> Option<usize> {
+        let preview_search = self
+            .preview_search
+            .as_ref()
+            .filter(|preview_search| preview_search.path == path)?;
+        preview_search
+            .matches
+            .get(preview_search.current_match)
+            .map(|preview_match| preview_match.line + 1)
+    }
+
+    /// The directory that new files go into: the one with the selected item in it, which is an expanded directory
+    /// rather than the current one when the cursor is inside it
+    pub fn get_target_dir(&self) -> &Path {
+        self.selected_file
+            .as_ref()
+            .and_then(|selected_file| selected_file.get_path_buf().parent())
+            .filter(|parent| parent.starts_with(self.current_dir.get_path_buf()))
+            .unwrap_or(self.current_dir.get_path_buf())
+    }
+
+    /// Changes the current directory and records it in the history, discarding the forward history
+    pub fn change_dir(&mut self, new_dir: FileTreeNode) {
+        self.dir_history.truncate(self.history_pos + 1);
+        self.dir_history.push(new_dir.get_path_buf().to_owned());
+        if self.dir_history.len() > MAX_DIR_HISTORY {
+            self.dir_history.remove(0);
+        }
+        self.history_pos = self.dir_history.len() - 1;
+
+        self.remember_previous_dir(new_dir.get_path_buf());
+        self.current_dir = new_dir;
+    }
+
+    /// Moves through the directory history by `offset` steps without recording it as a new navigation.
+    /// Returns false if there is nowhere to move
+    pub fn move_in_history(&mut self, offset: isize) -> bool {
+        let new_pos = self.history_pos as isize + offset;
+        if new_pos < 0 || new_pos as usize >= self.dir_history.len() {
+            return false;
+        }
+        self.history_pos = new_pos as usize;
+        let new_dir = self.dir_history[self.history_pos].clone();
+        self.remember_previous_dir(&new_dir);
+        self.current_dir = FileTreeNode::new(new_dir);
+        true
+    }
+
+    fn remember_previous_dir(&mut self, new_dir: &Path) {
+        // e.g. refreshing by changing into the same directory should not forget the other one
+        if self.current_dir.get_path_buf() != new_dir {
+            self.previous_dir = Some((
+                self.current_dir.get_path_buf().to_owned(),
+                self.selected_file.clone(),
+            ));
+        }
     }
 
     /// Resets all the data (including prompts, error messages entered text and input manager) and changes into the normal mode
     pub fn reset_state(&mut self) {
         self.error_message_line = None;
         self.error_popup = None;
+        self.info_popup = None;
 
         self.entered_text = String::new();
+        self.cursor_pos = 0;
+        self.path_completion = None;
+        self.search_cursor = None;
 
         self.mode = Mode::SimpleMode(SimpleMode::Normal);
-        self.marked_files = vec![];
+        self.marked_files = BTreeMap::new();
+        // this also stops computing the sizes
+        self.disk_usage = None;
+        self.preview_search = None;
+        self.visual_anchor = None;
+        self.pending_register = None;
     }
     pub fn copy_input_manager_verbs_to_entered_text(&mut self) {
-        let input_verbs_string = &self.input_reader.verb_key_sequence.concat();
-        self.entered_text.push_str(input_verbs_string);
+        // the keys without a binding, like "C-z" or "ESC", are not text
+        let input_verbs_string: String = self
+            .input_reader
+            .verb_key_sequence
+            .iter()
+            .filter(|key| key.chars().count() == 1)
+            .map(String::as_str)
+            .collect();
+        self.insert_text_at_cursor(&input_verbs_string);
+    }
+    pub fn insert_text_at_cursor(&mut self, text: &str) {
+        self.entered_text.insert_str(self.cursor_pos, text);
+        self.cursor_pos += text.len();
+    }
+    /// The index of the highlighted search result. Changing the query starts again from the best match
+    pub fn get_search_cursor(&self, results_num: usize) -> usize {
+        match &self.search_cursor {
+            Some((query, index)) if *query == self.entered_text => {
+                (*index).min(results_num.saturating_sub(1))
+            }
+            _ => 0,
+        }
+    }
+    /// Replaces the entered text and puts the cursor at its end
+    pub fn set_entered_text(&mut self, text: String) {
+        self.cursor_pos = text.len();
+        self.entered_text = text;
+    }
+    /// The byte index of the character before the cursor, if there is one
+    pub fn get_previous_char_pos(&self) -> Option<usize> {
+        self.entered_text[..self.cursor_pos]
+            .char_indices()
+            .next_back()
+            .map(|(i, _)| i)
+    }
+    /// The byte index of the character after the one under the cursor, if the cursor is not at the end
+    pub fn get_next_char_pos(&self) -> Option<usize> {
+        self.entered_text[self.cursor_pos..]
+            .chars()
+            .next()
+            .map(|c| self.cursor_pos + c.len_utf8())
     }
     pub fn set_file_cursor_highlight_index<F: FnOnce(usize, usize) -> usize>(
         &mut self,
-        dir_items: &Vec<FileTreeNode>,
+        dir_items: &[FileTreeNode],
         get_new_index: F,
     ) {
         let items_num = dir_items.len();
@@ -203,6 +1158,116 @@ impl AppState {
     pub fn error_popup(&mut self, title: String, body: String) {
         self.error_popup = Some(ErrorPopup::new(title, body));
     }
+    pub fn info_popup(&mut self, title: String, body: String) {
+        self.info_popup = Some(InfoPopup::new(title, body));
+    }
+    /// Shows the names and the values in two columns
+    pub fn table_popup(&mut self, title: String, rows: &[(&str, String)]) {
+        let name_width = rows.iter().map(|(name, _)| name.width()).max().unwrap_or(0);
+        let desc = rows
+            .iter()
+            .map(|(name, value)| format!("{:<name_width$}  {}", name, value))
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.info_popup = Some(InfoPopup {
+            is_table: true,
+            ..InfoPopup::new(title, desc)
+        });
+    }
+
+    /// Shows a message that goes away by itself, for when an action worked
+    pub fn toast(&mut self, message: String) {
+        if self.toasts.len() >= MAX_TOASTS {
+            self.toasts.remove(0);
+        }
+        self.toasts.push((message, Instant::now()));
+    }
+}
+
+impl FileTemplate {
+    pub fn get_contents(&self) -> io::Result<String> {
+        match self {
+            FileTemplate::Inline(contents) => Ok(contents.to_owned()),
+            FileTemplate::FromFile { path } => fs::read_to_string(path),
+        }
+    }
+}
+
+impl SortKey {
+    /// The order in which `cycle_sort` goes through the sort keys
+    pub fn next(&self) -> Self {
+        match self {
+            SortKey::Name => SortKey::Size,
+            SortKey::Size => SortKey::Modified,
+            SortKey::Modified => SortKey::Extension,
+            SortKey::Extension => SortKey::Name,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+            SortKey::Extension => "extension",
+        }
+    }
+
+    /// The sort key of the column next to this one in the detailed view, going right if `to_the_right` is true.
+    /// There is no extension column, so sorting by extension counts as the name column
+    pub fn get_adjacent_column(&self, to_the_right: bool) -> Self {
+        match (self, to_the_right) {
+            (SortKey::Size, false) => SortKey::Size,
+            (SortKey::Size, true) | (SortKey::Name | SortKey::Extension, false) => {
+                SortKey::Modified
+            }
+            (SortKey::Modified, false) => SortKey::Size,
+            (SortKey::Modified | SortKey::Name | SortKey::Extension, true) => SortKey::Name,
+        }
+    }
+
+    /// Whether the items are in ascending order when the sort is not reversed
+    pub fn is_ascending(&self) -> bool {
+        match self {
+            SortKey::Name | SortKey::Extension => true,
+            // the biggest and newest files are the most interesting, so they come first
+            SortKey::Size | SortKey::Modified => false,
+        }
+    }
+}
+
+impl ArchiveFormat {
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => ".zip",
+            ArchiveFormat::Tar => ".tar",
+            ArchiveFormat::TarGz => ".tar.gz",
+        }
+    }
+
+    /// The format that the extension of the file name stands for
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let file_name = file_name.to_lowercase();
+        if file_name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if file_name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+impl HashAlgorithm {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "MD5",
+            HashAlgorithm::Sha1 => "SHA-1",
+            HashAlgorithm::Sha256 => "SHA-256",
+        }
+    }
 }
 
 impl InputReader {
@@ -222,17 +1287,28 @@ impl InputReader {
         self.verb_key_sequence.clear();
     }
 
-    pub fn digest(&mut self, key: KeyCode, force_pushing_as_verb: bool) -> InputReaderDigestResult {
-        if let KeyCode::Char(character) = key {
+    pub fn digest(
+        &mut self,
+        key_event: KeyEvent,
+        force_pushing_as_verb: bool,
+        accepts_trailing_count: bool,
+    ) -> InputReaderDigestResult {
+        let key = key_event.code;
+        if let (KeyCode::Char(character), true) =
+            (key, key_event.modifiers.contains(KeyModifiers::CONTROL))
+        {
+            // e.g. "C-a", so that it is not confused with typing the character
+            self.verb_key_sequence.push(format!("C-{}", character));
+        } else if let KeyCode::Char(character) = key {
             // if it is a modifier
-            if !force_pushing_as_verb && character.is_digit(10) {
+            if !force_pushing_as_verb && character.is_ascii_digit() {
                 self.modifier_key_sequence.push(character);
 
-                // we can not add a movement after a verb, so fail in that case
-                if !self.verb_key_sequence.is_empty() {
+                // the count goes before the verb, unless the action also takes it after the start of its sequence
+                if !self.verb_key_sequence.is_empty() && !accepts_trailing_count {
                     self.clear();
                     return InputReaderDigestResult::DigestError(String::from(
-                        "Can not have a verb modifier after an verb",
+                        "Can not have a verb modifier after a verb",
                     ));
                 }
             } else {
@@ -244,6 +1320,16 @@ impl InputReader {
             self.verb_key_sequence.push("BACKSPACE".to_string());
         } else if let KeyCode::Enter = key {
             self.verb_key_sequence.push("ENTER".to_string());
+        } else if let KeyCode::Tab = key {
+            self.verb_key_sequence.push("TAB".to_string());
+        } else if let KeyCode::Left = key {
+            self.verb_key_sequence.push("LEFT".to_string());
+        } else if let KeyCode::Right = key {
+            self.verb_key_sequence.push("RIGHT".to_string());
+        } else if let KeyCode::Home = key {
+            self.verb_key_sequence.push("HOME".to_string());
+        } else if let KeyCode::End = key {
+            self.verb_key_sequence.push("END".to_string());
         }
         InputReaderDigestResult::DigestSuccessful
     }
@@ -253,22 +1339,51 @@ impl InputReader {
         key_to_action_mapping: &'a BTreeMap<String, String>,
         action_to_closure_mapping: &'a ActionMapper,
     ) -> Option<&'a ActionClosure> {
-        if let Some(action_name) =
-            key_to_action_mapping.get(&self.get_human_friendly_verb_key_sequence())
-        {
+        if let Some(action_name) = self.get_action_name(key_to_action_mapping) {
             return action_to_closure_mapping.find_action(action_name);
         }
-        return None;
+        None
     }
 
-    pub fn check_incomplete_commands(
+    /// The name of the action that the keys typed so far are bound to, if there is one
+    pub fn get_action_name<'a>(
         &self,
-        current_sequence: &str,
-        possiblities: Vec<&BTreeMap<String, String>>,
+        key_to_action_mapping: &'a BTreeMap<String, String>,
+    ) -> Option<&'a String> {
+        key_to_action_mapping.get(&self.get_human_friendly_verb_key_sequence())
+    }
+
+    /// Whether the keys typed so far start the sequence of one of `trailing_count_actions`, so that a count can follow them
+    pub fn accepts_trailing_count(
+        &self,
+        key_bindings: Vec<&BTreeMap<String, String>>,
+        trailing_count_actions: &[String],
     ) -> bool {
+        if self.verb_key_sequence.is_empty() {
+            return false;
+        }
+        // the space makes sure that "g" does not count as the start of "gg"
+        let sequence_start = format!("{} ", self.get_human_friendly_verb_key_sequence());
+        key_bindings.iter().any(|map| {
+            map.iter().any(|(key_sequence, action_name)| {
+                key_sequence.starts_with(&sequence_start)
+                    && trailing_count_actions.contains(action_name)
+            })
+        })
+    }
+
+    /// Whether the keys typed so far are the start of a longer bound sequence, so that more keys should be waited for.
+    /// The keys are compared one by one, so that e.g. "C" is not taken as the start of "C-x" or "ENTER"
+    pub fn check_incomplete_commands(&self, possiblities: Vec<&BTreeMap<String, String>>) -> bool {
         possiblities.iter().any(|map| {
-            map.keys()
-                .any(|string| string.starts_with(current_sequence))
+            map.keys().any(|key_sequence| {
+                let bound_keys: Vec<_> = key_sequence.split(' ').collect();
+                bound_keys.len() > self.verb_key_sequence.len()
+                    && bound_keys
+                        .iter()
+                        .zip(&self.verb_key_sequence)
+                        .all(|(bound_key, typed_key)| bound_key == typed_key)
+            })
         })
     }
 }
@@ -278,3 +1393,62 @@ impl ErrorPopup {
         ErrorPopup { title, desc }
     }
 }
+
+impl InfoPopup {
+    pub fn new(title: String, desc: String) -> Self {
+        InfoPopup {
+            title,
+            desc,
+            is_table: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::LruCache;
+    use crate::{
+        compile_time_settings::MAX_DIR_HISTORY, directory_tree::FileTreeNode, test_util::TestApp,
+    };
+
+    #[test]
+    fn lru_cache_forgets_the_oldest_entry() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        // storing it again makes it the newest
+        cache.insert("a", 3);
+        cache.insert("c", 4);
+
+        assert_eq!(cache.get("a"), Some(&3));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(&4));
+
+        assert_eq!(cache.remove("a"), Some(3));
+        assert!(!cache.contains_key("a"));
+    }
+
+    #[test]
+    fn dir_history_forgets_the_oldest_dirs() {
+        let root = Path::new("/fphile-test-root");
+        let mut test_app = TestApp::new(root);
+        for i in 0..MAX_DIR_HISTORY + 10 {
+            test_app
+                .app_state
+                .get_mut()
+                .change_dir(FileTreeNode::new(root.join(i.to_string())));
+        }
+
+        let app_state = test_app.app_state.get_mut();
+        assert_eq!(app_state.dir_history.len(), MAX_DIR_HISTORY);
+        assert_eq!(app_state.history_pos, MAX_DIR_HISTORY - 1);
+        assert_eq!(app_state.dir_history[0], root.join("10"));
+
+        // going back past the oldest kept dir stops there
+        assert!(app_state.move_in_history(-(MAX_DIR_HISTORY as isize - 1)));
+        assert!(!app_state.move_in_history(-1));
+        assert_eq!(app_state.current_dir.get_path_buf(), &root.join("10"));
+    }
+}
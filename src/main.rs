@@ -1,82 +1,362 @@
 mod actions;
+mod archive;
 mod compile_time_settings;
 mod directory_tree;
+mod filesystem;
+mod frecency;
+mod git;
 mod helper_types;
 mod modes;
+#[cfg(test)]
+mod test_util;
+mod util;
 
 use std::env;
+use std::fmt::Display;
 use std::io::Stdout;
+use std::process::exit;
 use std::{
-    io,
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
-use actions::{ActionData, ActionMapper, ActionResult, GLOBAL_ACTION_MAP};
-use crossterm::event::KeyCode;
+use actions::{
+    get_current_mark_type, get_visual_range, ActionData, ActionMapper, ActionResult,
+    GLOBAL_ACTION_MAP, NORMAL_MODE_ACTION_MAP,
+};
+use crossterm::event::{KeyEvent, MouseButton, MouseEvent, MouseEventKind};
 use crossterm::{event::EnableMouseCapture, terminal::EnterAlternateScreen};
-use helper_types::{AppSettings, AppState, InputReaderDigestResult, StyleSet};
+use glob::Pattern;
+use helper_types::{
+    AppSettings, AppState, BorderStyle, DirConfig, InputReaderDigestResult, StyleSet, TimeFormat,
+    ViewMode,
+};
 use modes::normal_mode::get_default_left_ui;
 use modes::search_mode::get_search_mode_left_ui;
-use modes::{get_file_text_preview, Mode::*, SimpleMode::*, TextInput::*};
+use modes::{get_file_text_preview, Mode::*, SimpleMode::*, TextInput::*, TextPreview};
 use tui::backend::{Backend, CrosstermBackend};
-use tui::layout::{Constraint, Direction, Layout, Rect};
-use tui::style::Style;
-use tui::widgets::{Block, Borders, Clear, Paragraph, Wrap};
+use tui::layout::{Alignment, Constraint, Direction, Layout, Margin, Rect};
+use tui::style::{Modifier, Style};
+use tui::text::{Span, Spans, Text};
+use tui::widgets::{Block, Clear, Paragraph, Wrap};
 use tui::Terminal;
+use unicode_width::UnicodeWidthStr;
 
-use crate::directory_tree::FileTreeNode;
+use crate::compile_time_settings::{
+    DIR_CONFIG_FILE_NAME, MIN_WIDTH_FOR_PREVIEW, PREVIEW_SEARCH_CONTEXT_LINES,
+    PREVIEW_TEXT_FETCH_LENGTH,
+};
+use crate::directory_tree::{
+    display_path, format_children_num, format_size, normalize_path, resolve_path, DirListing,
+    ExpandedDirsListing, FileTreeNode,
+};
+use crate::filesystem::{DryRunFilesystem, Filesystem, RealFilesystem};
+use crate::frecency::VisitedDirs;
+use crate::git::GitStatusListing;
 use crate::helper_types::TrackedModifiable;
-use crate::modes::cmp_by_dir_and_path;
+use crate::modes::{cmp_by_dir_and_path, sort_by_score, sort_dir_items};
+use crate::util::{format_mtime, get_path_bytes};
 
 use clap::Parser;
+use serde::Serialize;
 
 pub type CustomTerminal = Terminal<CrosstermBackend<Stdout>>;
 
+const SPINNER_REFRESH_TIME: Duration = Duration::from_millis(100);
+const SPINNER_DELAY: Duration = Duration::from_millis(50);
+// how long the visited directories can go without being written to the disk, in case the app does not exit normally
+const VISITED_DIRS_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
-pub struct CommandLineArguments {}
+pub struct CommandLineArguments {
+    /// Print the entries of the current directory as JSON instead of starting the file manager
+    #[clap(long)]
+    list_json: bool,
+    /// Print the path of the config file that would be loaded and exit
+    #[clap(long)]
+    print_config_path: bool,
+    /// Write the default config to the user's config directory, replacing the config file there, and exit
+    #[clap(long)]
+    write_default_config: bool,
+    /// Do not change any files. The changes that would have been made are logged and printed on exit
+    #[clap(long)]
+    dry_run: bool,
+    /// Start in this directory instead of the current one
+    #[clap(long, value_name = "PATH")]
+    dir: Option<PathBuf>,
+    /// Where cd_and_quit writes the current directory, so that a shell function can cd into it
+    #[clap(long, value_name = "FILE")]
+    last_dir_path: Option<PathBuf>,
+    /// Start in the directory of this file, with the cursor on it
+    #[clap(long, value_name = "PATH")]
+    select: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct JsonDirEntry {
+    name: String,
+    path: String,
+    is_dir: bool,
+    size: Option<u64>,
+}
 
 fn main() {
-    let _args = CommandLineArguments::parse();
-
-    let config = AppSettings::load_config(vec![
-        "../example_config.toml",
-        "/usr/share/fphile/global_config.toml",
-    ])
-    .expect("Could not load the config file");
-
-    let current_dir = FileTreeNode::new(
-        env::current_dir()
-            .expect("Could not get the current directory")
-            .to_path_buf(),
-    );
+    let args = CommandLineArguments::parse();
+
+    if args.list_json {
+        exit(print_dir_listing_as_json());
+    }
 
-    assert!(
-        current_dir.is_dir(),
-        "The current directory should be a directory"
+    let user_config_paths = AppSettings::get_user_config_paths();
+    // e.g. neither HOME nor XDG_CONFIG_HOME is set
+    let default_config_path = user_config_paths.first().cloned();
+    if args.write_default_config {
+        let path = default_config_path.unwrap_or_else(|| {
+            exit_with_error(
+                "Could not write the default config",
+                "There is no config directory to put it in",
+            )
+        });
+        if let Err(err) = AppSettings::write_default_config(&path, true) {
+            exit_with_error(
+                &format!("Could not write the default config to '{}'", path.display()),
+                err,
+            );
+        }
+        println!("Wrote the default config to '{}'", path.display());
+        return;
+    }
+
+    // e.g. for trying out another config without touching the usual one
+    let mut config_paths: Vec<_> = env::var_os("FPHILE_CONFIG")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .into_iter()
+        .collect();
+    config_paths.extend(user_config_paths);
+    config_paths.extend(
+        [
+            "../example_config.toml",
+            "/usr/share/fphile/global_config.toml",
+        ]
+        .map(PathBuf::from),
     );
+    let mut config = AppSettings::load_config(config_paths);
+
+    // on the first run, there is nothing to load yet. Printing the path should not change any files, though
+    let mut created_config_path = None;
+    if let (Err(err), Some(path), false) = (&config, &default_config_path, args.print_config_path) {
+        // the error could also be about another file in the list, and an existing config is never replaced
+        if err.kind() == io::ErrorKind::NotFound && !path.exists() {
+            if let Err(err) = AppSettings::write_default_config(path, false) {
+                exit_with_error(
+                    &format!(
+                        "Could not create a default config file at '{}'",
+                        path.display()
+                    ),
+                    err,
+                );
+            }
+            config = AppSettings::load_config(vec![path]);
+            created_config_path = Some(path);
+        }
+    }
+
+    if args.print_config_path {
+        match config.map(|config| config.loaded_from) {
+            Ok(Some(path)) => println!("{}", path.to_string_lossy()),
+            Ok(None) => unreachable!(), // load_config always records the path
+            Err(err) => exit_with_error("Could not load the config file", err),
+        }
+        return;
+    }
+
+    let config =
+        config.unwrap_or_else(|err| exit_with_error("Could not load the config file", err));
+
+    // e.g. the directory that the app was started in was deleted
+    let process_dir = env::current_dir()
+        .unwrap_or_else(|err| exit_with_error("Could not get the current directory", err));
+    let mut startup_errors = Vec::new();
+    // the flag is more specific than the config, and --select is more specific than both
+    let start_dir = match (&args.dir, &config.start_dir) {
+        (Some(dir), _) => Some(normalize_path(&process_dir.join(dir))),
+        (None, Some(start_dir)) => Some(resolve_path(&process_dir, start_dir)),
+        (None, None) => None,
+    };
+    let mut current_dir = FileTreeNode::new(match start_dir {
+        Some(start_dir) if start_dir.is_dir() => start_dir,
+        Some(start_dir) => {
+            startup_errors.push(format!(
+                "Could not start in '{}', since it is not a directory",
+                start_dir.display()
+            ));
+            process_dir
+        }
+        None => process_dir,
+    });
+    let mut selected_file = None;
+    if let Some(path) = &args.select {
+        match get_dir_and_file_to_select(path) {
+            Ok((dir, file)) => {
+                current_dir = dir;
+                selected_file = file;
+            }
+            Err(err) => {
+                startup_errors.push(format!("Could not select '{}': {}", path.display(), err))
+            }
+        }
+    }
+
+    if !current_dir.is_dir() {
+        exit_with_error(
+            "Could not start",
+            format!(
+                "'{}' is not a directory",
+                current_dir.get_path_buf().display()
+            ),
+        );
+    }
 
     // setup terminal
     let backend = tui::backend::CrosstermBackend::new(io::stdout());
-    let mut terminal = tui::Terminal::new(backend).expect("Failed to start a terminal");
+    let mut terminal = tui::Terminal::new(backend)
+        .unwrap_or_else(|err| exit_with_error("Could not start a terminal", err));
 
-    let app_state =
-        TrackedModifiable::new(AppState::new(current_dir).expect("Could not create app_state"));
-    enter_captured_mode(&mut terminal).expect("Could not capture the terminal");
+    let mut app_state = TrackedModifiable::new(
+        AppState::new(current_dir, &config)
+            .unwrap_or_else(|err| exit_with_error("Could not handle ctrl-c", err)),
+    );
+    // the list is scrolled to the selected file when it is first drawn
+    app_state.get_mut().selected_file = selected_file;
+    app_state.get_mut().last_dir_path = args.last_dir_path.clone();
+    if !startup_errors.is_empty() {
+        app_state.get_mut().error_message_line = Some(startup_errors.join(". "));
+    }
+    if let Some(path) = created_config_path {
+        app_state.get_mut().toast(format!(
+            "Created a default config file at '{}'",
+            path.display()
+        ));
+    }
+    let mut filesystem: Box<dyn Filesystem> = if args.dry_run {
+        Box::new(DryRunFilesystem::default())
+    } else {
+        Box::new(RealFilesystem)
+    };
+    load_visited_dirs(&config, &mut app_state);
+    if let Err(err) = enter_captured_mode(&mut terminal) {
+        // it might have been captured partly
+        let _ = exit_captured_mode(&mut terminal);
+        exit_with_error("Could not capture the terminal", err);
+    }
 
     // create app and run it
     let res = run_loop(
-        app_state,
+        &mut app_state,
         &mut terminal,
         Duration::from_millis(config.render_timeout.unwrap_or(250)),
         config,
+        filesystem.as_mut(),
     );
 
-    exit_captured_mode(&mut terminal).expect("Could not capture the terminal");
+    // the dry run log is still worth printing, even if the terminal is left in a strange state
+    if let Err(err) = exit_captured_mode(&mut terminal) {
+        eprintln!("Could not restore the terminal: {}", err);
+    }
+    // the visits are only written every now and then, so the latest ones are written on the way out
+    if let Err(err) = app_state.get_mut().visited_dirs.save() {
+        eprintln!("Could not save the visited directories: {}", err);
+    }
 
-    if let Err(err) = res {
-        println!("Exiting because of an error: {:?}", err)
+    match res {
+        // the shell reads it after the app has exited
+        Ok(Some(dir)) => {
+            if let Some(last_dir_path) = &args.last_dir_path {
+                if let Err(err) = fs::write(last_dir_path, get_path_bytes(&dir)) {
+                    eprintln!(
+                        "Could not write the directory to '{}': {}",
+                        last_dir_path.display(),
+                        err
+                    );
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(err) => println!("Exiting because of an error: {:?}", err),
+    }
+    if filesystem.is_dry_run() {
+        let dry_run_log = filesystem.get_log();
+        println!("Dry run, so {} change(s) were skipped:", dry_run_log.len());
+        for operation in dry_run_log {
+            println!("  {}", operation);
+        }
+    }
+}
+
+/// For the errors that the app can not start without, which are not worth a panic
+fn exit_with_error(message: &str, err: impl Display) -> ! {
+    eprintln!("{}: {}", message, err);
+    exit(1);
+}
+
+/// The directory to start in for `--select`, and the file in it to put the cursor on.
+/// Only the root directory has nothing to select, since it is not in any directory
+fn get_dir_and_file_to_select(path: &Path) -> io::Result<(FileTreeNode, Option<FileTreeNode>)> {
+    let path = env::current_dir()?.join(path);
+    // the file itself is not resolved, so that a symlink is selected rather than what it points to
+    path.symlink_metadata()?;
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => parent.canonicalize()?.join(file_name),
+        // e.g. ".." has to be resolved to know which directory it is
+        _ => path.canonicalize()?,
     };
+    Ok(match path.parent() {
+        Some(parent) => (
+            FileTreeNode::new(parent.to_owned()),
+            Some(FileTreeNode::new(path)),
+        ),
+        None => (FileTreeNode::new(path), None),
+    })
+}
+
+/// Prints the entries of the current directory to stdout as a JSON array and returns the exit code.
+/// On failure, a JSON object with an "error" field is printed instead
+fn print_dir_listing_as_json() -> i32 {
+    let result = env::current_dir().and_then(|current_dir| {
+        let mut dir_items = FileTreeNode::new(current_dir).list_files()?;
+        dir_items.sort_by(cmp_by_dir_and_path);
+        Ok(dir_items
+            .iter()
+            .map(|item| JsonDirEntry {
+                name: item
+                    .get_path_buf()
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| item.get_simple_name().to_owned()),
+                path: item.get_path_buf().to_string_lossy().into_owned(),
+                is_dir: item.is_dir(),
+                size: item.get_size().ok(),
+            })
+            .collect::<Vec<_>>())
+    });
+
+    match result {
+        Ok(entries) => {
+            println!(
+                "{}",
+                serde_json::to_string(&entries).expect("Could not serialise the directory entries")
+            );
+            0
+        }
+        Err(err) => {
+            println!("{}", serde_json::json!({ "error": err.to_string() }));
+            1
+        }
+    }
 }
 
 pub fn enter_captured_mode(terminal: &mut CustomTerminal) -> io::Result<()> {
@@ -101,22 +381,61 @@ pub fn exit_captured_mode(terminal: &mut CustomTerminal) -> io::Result<()> {
 }
 
 fn run_loop(
-    mut app_state: TrackedModifiable<AppState>,
+    app_state: &mut TrackedModifiable<AppState>,
     terminal: &mut CustomTerminal,
     tick_rate: Duration,
-    config: AppSettings,
-) -> io::Result<()> {
+    mut config: AppSettings,
+    filesystem: &mut dyn Filesystem,
+) -> io::Result<Option<PathBuf>> {
     let mut last_tick = Instant::now();
+    let mut dir_listing = DirListing::new(app_state.current_dir.clone(), None);
+    let mut git_status_listing = start_git_status_listing(&config, app_state);
+    let mut expanded_dirs_listing: Option<ExpandedDirsListing> = None;
+    load_dir_config(app_state);
 
     loop {
+        if app_state.config_reload_requested {
+            app_state.get_mut().config_reload_requested = false;
+            let reloaded_config = config
+                .loaded_from
+                .clone()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config file was loaded"))
+                .and_then(|path| AppSettings::load_config(vec![path]));
+            match reloaded_config {
+                Ok(reloaded_config) => config = reloaded_config,
+                Err(err) => app_state.get_mut().error_popup(
+                    String::from("Could not reload the config file"),
+                    err.to_string(),
+                ),
+            }
+        }
+
         if let SimpleMode(Quitting)
         | OverlayMode {
             background_mode: Quitting,
             ..
         } = app_state.mode
         {
-            return Ok(());
+            // the directory for the shell to go to, if `cd_and_quit` was used
+            let cd_dir = app_state
+                .is_cd_requested
+                .then(|| app_state.current_dir.get_path_buf().to_owned());
+            return Ok(cd_dir);
+        }
+
+        // only force a redraw when a toast actually goes away
+        let toast_duration = Duration::from_secs_f64(config.toast_secs.unwrap_or(3.0));
+        if app_state
+            .toasts
+            .iter()
+            .any(|(_, shown_at)| shown_at.elapsed() >= toast_duration)
+        {
+            app_state
+                .get_mut()
+                .toasts
+                .retain(|(_, shown_at)| shown_at.elapsed() < toast_duration);
         }
+
         // if an urgent update, fore it to update ASAP by reducing wait time to 0
         let timeout = if app_state.is_modified() {
             app_state.reset_modified_flag();
@@ -127,7 +446,124 @@ fn run_loop(
                 .unwrap_or_else(|| Duration::from_secs(0))
         };
 
-        let mut dir_items = app_state.current_dir.list_files().unwrap_or_else(|err| {
+        let listing_refresh_time =
+            Duration::from_secs_f64(config.listing_refresh_secs.unwrap_or(2.0));
+        if dir_listing.get_dir() != &app_state.current_dir {
+            // this also cancels the listing of the previous directory
+            let cached_items = app_state
+                .dir_listing_cache
+                .get(app_state.current_dir.get_path_buf())
+                .cloned();
+            dir_listing = DirListing::new(app_state.current_dir.clone(), cached_items);
+            git_status_listing = start_git_status_listing(&config, app_state);
+            let cached_git_statuses = app_state
+                .git_status_cache
+                .get(app_state.current_dir.get_path_buf())
+                .cloned()
+                .flatten();
+            if cached_git_statuses != app_state.git_statuses {
+                app_state.get_mut().git_statuses = cached_git_statuses;
+            }
+            load_dir_config(app_state);
+            record_visit(app_state);
+            // the visual selection is about the positions in the previous listing
+            if app_state.visual_anchor.is_some() {
+                app_state.get_mut().visual_anchor = None;
+            }
+            // the expanded directories belong to the previous listing
+            expanded_dirs_listing = None;
+            if !app_state.expanded_dirs.is_empty() {
+                let app_state = app_state.get_mut();
+                app_state.expanded_dirs = BTreeMap::new();
+                app_state.loading_expanded_dirs = BTreeMap::new();
+            }
+        } else if !dir_listing.is_pending()
+            && (app_state.is_listing_outdated || dir_listing.get_age() >= listing_refresh_time)
+        {
+            if app_state.is_listing_outdated {
+                app_state.get_mut().is_listing_outdated = false;
+            }
+            dir_listing.start();
+            git_status_listing = start_git_status_listing(&config, app_state);
+
+            if !app_state.expanded_dirs.is_empty() {
+                expanded_dirs_listing = Some(ExpandedDirsListing::new(
+                    app_state.expanded_dirs.keys().cloned().collect(),
+                ));
+            }
+        }
+
+        // the directories that were just expanded are listed as soon as nothing else is being listed
+        if expanded_dirs_listing.is_none() && !app_state.loading_expanded_dirs.is_empty() {
+            expanded_dirs_listing = Some(ExpandedDirsListing::new(
+                app_state.loading_expanded_dirs.keys().cloned().collect(),
+            ));
+        }
+
+        if let Some((listed_paths, expanded_dirs)) =
+            expanded_dirs_listing.as_ref().and_then(|listing| {
+                let expanded_dirs = listing.poll(&app_state.expanded_dirs)?;
+                Some((listing.get_paths().to_vec(), expanded_dirs))
+            })
+        {
+            expanded_dirs_listing = None;
+            let loaded_paths: Vec<_> = app_state
+                .loading_expanded_dirs
+                .keys()
+                .filter(|path| listed_paths.contains(path))
+                .cloned()
+                .collect();
+            if !loaded_paths.is_empty() {
+                let app_state = app_state.get_mut();
+                for path in loaded_paths {
+                    app_state.loading_expanded_dirs.remove(&path);
+                    // the listing leaves out the directories that could not be listed
+                    if app_state.expanded_dirs.contains_key(&path)
+                        && !expanded_dirs.contains_key(&path)
+                    {
+                        app_state.error_message_line = Some(format!(
+                            "Could not list '{}'",
+                            display_path(
+                                &path,
+                                app_state.path_display,
+                                config.path_display_root.as_deref()
+                            )
+                        ));
+                    }
+                }
+            }
+            // this happens every couple of seconds, so only force a redraw if something changed
+            if expanded_dirs != app_state.expanded_dirs {
+                app_state.get_mut().expanded_dirs = expanded_dirs;
+            }
+        }
+
+        AppState::receive_children_nums(app_state);
+
+        if app_state
+            .visited_dirs
+            .is_save_due(VISITED_DIRS_SAVE_INTERVAL)
+        {
+            save_visited_dirs(app_state);
+        }
+
+        // fast directories are usually listed within this time, which avoids flashing the spinner
+        let listing_wait_time = if dir_listing.is_loading() {
+            SPINNER_DELAY
+        } else {
+            Duration::from_secs(0)
+        };
+        let listing_result = dir_listing.poll(listing_wait_time);
+        if let Some(Ok(())) = listing_result {
+            let dir_path = dir_listing.get_dir().get_path_buf().to_owned();
+            app_state
+                .get_mut()
+                .dir_listing_cache
+                .insert(dir_path.clone(), dir_listing.get_items());
+            // the entries of the directories in it might have changed as well
+            app_state.get_mut().is_children_recount_requested = true;
+        }
+        if let Some(Err(err)) = listing_result {
             match err.kind() {
                 io::ErrorKind::PermissionDenied => {
                     app_state.get_mut().error_popup(
@@ -137,6 +573,14 @@ fn run_loop(
                         ),
                     );
                 }
+                io::ErrorKind::NotFound => {
+                    app_state.get_mut().error_popup(
+                        String::from("The directory does not exist"),
+                        String::from(
+                            "It might have been deleted. Go to another directory to continue",
+                        ),
+                    );
+                }
                 // unknown error
                 _ => {
                     app_state.get_mut().error_popup(
@@ -145,8 +589,118 @@ fn run_loop(
                     );
                 }
             }
+        }
+
+        if let Some(git_statuses) = git_status_listing.as_ref().and_then(|g| g.poll()) {
+            let dir = git_status_listing
+                .take()
+                .expect("The statuses came from this listing")
+                .get_dir()
+                .to_owned();
+            // this happens on every refresh, so only force a redraw if something changed
+            if app_state.git_status_cache.get(&dir) != Some(&git_statuses) {
+                app_state
+                    .get_mut()
+                    .git_status_cache
+                    .insert(dir, git_statuses.clone());
+            }
+            if git_statuses != app_state.git_statuses {
+                app_state.get_mut().git_statuses = git_statuses;
+            }
+        }
+
+        if let Some(file_hashing) = &app_state.file_hashing {
+            if let Some(result) = file_hashing.poll() {
+                let file_name = file_hashing.get_file().get_simple_name().to_owned();
+                let algorithm_name = file_hashing.get_algorithm().get_name();
+                app_state.get_mut().file_hashing = None;
+                match result {
+                    Ok(hash) => app_state
+                        .get_mut()
+                        .info_popup(format!("{} of '{}'", algorithm_name, file_name), hash),
+                    Err(err) => app_state.get_mut().error_popup(
+                        String::from("Could not hash the file"),
+                        format!("Error while reading '{}': {}", file_name, err),
+                    ),
+                }
+            }
+        }
+
+        if app_state.archive_job.is_some() {
+            // the progress is shown in the status bar, so this redraws it as well
+            let app_state = app_state.get_mut();
+            let finished_job = app_state.archive_job.as_mut().and_then(|archive_job| {
+                let result = archive_job.poll()?;
+                Some((archive_job.get_description().to_owned(), result))
+            });
+            if let Some((description, result)) = finished_job {
+                app_state.archive_job = None;
+                app_state.invalidate_listing();
+                match result {
+                    Ok(()) => app_state.toast(format!("Finished {}", description)),
+                    Err(err) => app_state
+                        .error_popup(format!("Error while {}", description), err.to_string()),
+                }
+            }
+        }
+
+        let is_disk_usage_running = match &app_state.disk_usage {
+            Some(disk_usage) => !disk_usage.is_finished(),
+            None => false,
+        };
+        if is_disk_usage_running {
+            // show the new sizes as they come in
+            if let Some(disk_usage) = app_state.get_mut().disk_usage.as_mut() {
+                disk_usage.poll();
+            }
+        }
+
+        // redraw often enough for the spinner to move
+        let timeout = if dir_listing.is_loading()
+            || !app_state.loading_expanded_dirs.is_empty()
+            || app_state.file_hashing.is_some()
+            || app_state.archive_job.is_some()
+            || is_disk_usage_running
+        {
+            timeout.min(SPINNER_REFRESH_TIME)
+        } else {
+            timeout
+        };
+
+        // wake up to read the preview once the cursor has stayed on a file for long enough
+        let timeout = match &app_state.preview_selected_at {
+            Some((_, selected_at)) => match config
+                .get_preview_delay()
+                .checked_sub(selected_at.elapsed())
+            {
+                Some(remaining_delay) if !remaining_delay.is_zero() => timeout.min(remaining_delay),
+                _ => timeout,
+            },
+            None => timeout,
+        };
+
+        let mut dir_items = dir_listing.get_items();
+        let show_hidden = app_state
+            .dir_config
+            .show_hidden
+            .or(config.show_hidden)
+            .unwrap_or(true);
+        let ignore_patterns = if app_state.is_ignore_active {
+            get_ignore_patterns(&config)
+        } else {
             vec![]
-        });
+        };
+        let is_shown = |item: &FileTreeNode| {
+            let name = item.get_simple_name().trim_end_matches('/');
+            (show_hidden || !name.starts_with('.'))
+                && !app_state
+                    .dir_config
+                    .hide
+                    .iter()
+                    .any(|hidden| hidden == name)
+                && !ignore_patterns.iter().any(|pattern| pattern.matches(name))
+        };
+        dir_items.retain(|item| is_shown(item));
 
         // sort
         let dir_items = match app_state.mode {
@@ -161,56 +715,49 @@ fn run_loop(
                 ..
             }
             | TextInputMode {
-                text_input_type: RunCommand,
+                text_input_type: RunCommand | PreviewSearch,
             } => {
-                dir_items.sort_by(|a, b| cmp_by_dir_and_path(a, b));
-                dir_items
+                sort_dir_items(
+                    &mut dir_items,
+                    &app_state.get_sort_key(),
+                    app_state.get_is_sort_reversed(),
+                    config.group_directories,
+                );
+                insert_expanded_dirs(dir_items, app_state, &config, &is_shown)
             }
             TextInputMode {
                 text_input_type: Search,
                 ..
             } => {
-                struct FileTreeNodeWrapper {
-                    item: FileTreeNode,
-                    score: i64,
-                }
-
-                let search_string = &app_state.entered_text;
-
-                if search_string.is_empty() {
-                    dir_items.sort_by(|a, b| cmp_by_dir_and_path(a, b));
-                    dir_items
-                } else {
-                    // get the scores
-                    let mut dir_items: Vec<_> = dir_items
-                        .into_iter()
-                        .map(|el| FileTreeNodeWrapper {
-                            score: el.compute_score(search_string),
-                            item: el,
-                        })
-                        .filter(|el| el.score > 0)
-                        .collect();
-
-                    dir_items.sort_by(|a, b| {
-                        if a.score != b.score {
-                            return b.score.cmp(&a.score);
-                        }
-                        cmp_by_dir_and_path(&a.item, &b.item)
-                    });
-
-                    dir_items.into_iter().map(|el| el.item).collect()
-                }
+                // the items that match equally well, e.g. all of them before anything is typed, stay in the usual order
+                sort_dir_items(
+                    &mut dir_items,
+                    &app_state.get_sort_key(),
+                    app_state.get_is_sort_reversed(),
+                    config.group_directories,
+                );
+                sort_by_score(
+                    dir_items,
+                    &app_state.entered_text,
+                    config.search_match,
+                    app_state.current_dir.get_path_buf(),
+                )
             }
         };
 
         if crossterm::event::poll(timeout)? {
-            if let crossterm::event::Event::Key(key) = crossterm::event::read()? {
+            match crossterm::event::read()? {
                 // handle inputs
-                inputs(key.code, dir_items, &config, &mut app_state, terminal);
+                crossterm::event::Event::Key(key) => {
+                    inputs(key, dir_items, &config, app_state, terminal, filesystem)
+                }
+                crossterm::event::Event::Mouse(mouse) => mouse_inputs(mouse, &dir_items, app_state),
+                // redraw straight away, otherwise the old layout stays on the screen until the next tick
+                crossterm::event::Event::Resize(_, _) => app_state.mark_modified(),
             }
         } else {
             // Processes and draws the output
-            terminal.draw(|f| draw(f, dir_items, &config, &mut app_state))?;
+            terminal.draw(|f| draw(f, dir_items, &dir_listing, &config, app_state, filesystem))?;
         }
 
         if last_tick.elapsed() >= tick_rate {
@@ -219,29 +766,187 @@ fn run_loop(
     }
 }
 
-pub(self) fn inputs(
-    k: KeyCode,
+/// The file that the preview pane is about: the selected one, or the best match while searching
+fn get_previewed_file<'a>(
+    app_state: &'a AppState,
+    dir_items: &'a [FileTreeNode],
+) -> Option<&'a FileTreeNode> {
+    match app_state.mode {
+        SimpleMode(Quitting) => unreachable!(), // should have exited the program by now
+        SimpleMode(Normal)
+        | TextInputMode {
+            text_input_type: PreviewSearch,
+        } => app_state.selected_file.as_ref(),
+        TextInputMode {
+            text_input_type: Search,
+            ..
+        } => dir_items.first(),
+        _ => None,
+    }
+}
+
+/// Turns the `ignore` setting into patterns. Without `ignore_globs`, they only match the exact names
+fn get_ignore_patterns(config: &AppSettings) -> Vec<Pattern> {
+    config
+        .ignore
+        .iter()
+        .filter_map(|entry| {
+            if config.ignore_globs {
+                // invalid patterns do not match anything
+                Pattern::new(entry).ok()
+            } else {
+                Pattern::new(&Pattern::escape(entry)).ok()
+            }
+        })
+        .collect()
+}
+
+/// Reads the overrides for the current directory, which replace those of the previous directory
+fn load_dir_config(app_state: &mut TrackedModifiable<AppState>) {
+    match DirConfig::load(app_state.current_dir.get_path_buf()) {
+        Ok(dir_config) => app_state.get_mut().dir_config = dir_config,
+        Err(err) => {
+            app_state.get_mut().dir_config = DirConfig::default();
+            app_state.get_mut().error_popup(
+                format!("Could not load {}", DIR_CONFIG_FILE_NAME),
+                err.to_string(),
+            );
+        }
+    }
+}
+
+/// Reads the visited directories for `jump_to_dir` and counts the starting directory as visited
+fn load_visited_dirs(config: &AppSettings, app_state: &mut TrackedModifiable<AppState>) {
+    let path = if config.remember_visited_dirs.unwrap_or(true) {
+        config
+            .visited_dirs_path
+            .clone()
+            .or_else(VisitedDirs::get_default_path)
+    } else {
+        None
+    };
+    match VisitedDirs::load(path) {
+        Ok(visited_dirs) => app_state.get_mut().visited_dirs = visited_dirs,
+        // the file is left alone instead of being overwritten with only this session's visits
+        Err(err) => {
+            app_state.get_mut().error_message_line =
+                Some(format!("Could not read the visited directories: {}", err))
+        }
+    }
+    record_visit(app_state);
+}
+
+fn record_visit(app_state: &mut TrackedModifiable<AppState>) {
+    let current_dir = app_state.current_dir.get_path_buf().to_owned();
+    app_state.get_mut().visited_dirs.record_visit(&current_dir);
+}
+
+fn save_visited_dirs(app_state: &mut TrackedModifiable<AppState>) {
+    if let Err(err) = app_state.get_mut().visited_dirs.save() {
+        app_state.get_mut().error_message_line =
+            Some(format!("Could not save the visited directories: {}", err));
+    }
+}
+
+/// Clicking selects the item under the mouse, and dragging selects a range like the visual mode,
+/// which is marked when the button is released
+fn mouse_inputs(
+    mouse: MouseEvent,
+    dir_items: &[FileTreeNode],
+    app_state: &mut TrackedModifiable<AppState>,
+) {
+    // the other modes show a different list, or a prompt on top of it
+    if !matches!(app_state.mode, SimpleMode(Normal)) || dir_items.is_empty() {
+        return;
+    }
+    let list_area = app_state.list_area;
+    let is_in_list = mouse.column >= list_area.left()
+        && mouse.column < list_area.right()
+        && mouse.row >= list_area.top()
+        && mouse.row < list_area.bottom();
+
+    match mouse.kind {
+        MouseEventKind::Down(MouseButton::Left) if is_in_list => {
+            let index = app_state.scroll_offset + (mouse.row - list_area.top()) as usize;
+            // the rows below the last item are empty
+            if let Some(item) = dir_items.get(index) {
+                let app_state = app_state.get_mut();
+                app_state.selected_file = Some(item.clone());
+                app_state.visual_anchor = Some(index);
+                app_state.is_mouse_dragging = true;
+            }
+        }
+        MouseEventKind::Drag(MouseButton::Left) if app_state.is_mouse_dragging => {
+            // dragging past the top or the bottom of the list moves one item further, which scrolls it
+            let index = if mouse.row < list_area.top() {
+                app_state.scroll_offset.saturating_sub(1)
+            } else if mouse.row >= list_area.bottom() {
+                app_state.scroll_offset + list_area.height as usize
+            } else {
+                app_state.scroll_offset + (mouse.row - list_area.top()) as usize
+            };
+            let item = &dir_items[index.min(dir_items.len() - 1)];
+            if app_state.selected_file.as_ref() != Some(item) {
+                app_state.get_mut().selected_file = Some(item.clone());
+            }
+        }
+        MouseEventKind::Up(MouseButton::Left) if app_state.is_mouse_dragging => {
+            let mark_type = get_current_mark_type(app_state);
+            // a click without dragging only moves the cursor
+            let visual_range =
+                get_visual_range(app_state, dir_items).filter(|range| range.start() != range.end());
+            let app_state = app_state.get_mut();
+            if let Some(visual_range) = visual_range {
+                app_state.marked_files.extend(
+                    dir_items[visual_range]
+                        .iter()
+                        .map(|item| (item.get_path_buf().to_owned(), mark_type)),
+                );
+            }
+            app_state.visual_anchor = None;
+            app_state.is_mouse_dragging = false;
+        }
+        _ => {}
+    }
+}
+
+fn inputs(
+    k: KeyEvent,
     dir_items: Vec<FileTreeNode>,
     config: &AppSettings,
     app_state: &mut TrackedModifiable<AppState>,
     terminal: &mut CustomTerminal,
+    filesystem: &mut dyn Filesystem,
 ) {
     // close the popups and error messages on a key press
-    if let Some(_) = app_state.error_popup {
+    if app_state.error_popup.is_some() {
         app_state.get_mut().error_popup = None;
     }
-    if let Some(_) = app_state.error_message_line {
+    if app_state.info_popup.is_some() {
+        app_state.get_mut().info_popup = None;
+    }
+    if app_state.error_message_line.is_some() {
         app_state.get_mut().error_message_line = None;
     }
 
+    let mode_key_binding = match app_state.mode {
+        SimpleMode(Quitting) => unreachable!(), // should have exited the program by now
+        SimpleMode(Normal) => &config.normal_mode_key_bindings,
+        OverlayMode { .. } | TextInputMode { .. } => &config.text_input_mode_key_bindings,
+    };
+
     let is_text_mode = app_state.mode.is_text_mode();
+    let accepts_trailing_count = app_state.input_reader.accepts_trailing_count(
+        vec![mode_key_binding, &config.global_key_bindings],
+        &config.trailing_count_actions,
+    );
     let digest_result = app_state
         .get_mut()
         .input_reader
         // if we are in a text, consider everything as verb text
         // (even digits, which would otherwise be considered as modifiers)
         // that is so that we can later use it as normal text, not as commands
-        .digest(k, is_text_mode);
+        .digest(k, is_text_mode, accepts_trailing_count);
 
     // show errors if we could not process the inputs
     if let InputReaderDigestResult::DigestError(error_message) = digest_result {
@@ -252,35 +957,55 @@ pub(self) fn inputs(
     // process commands
     let modifier = app_state.input_reader.modifier_key_sequence.parse().ok();
 
-    let mode_key_binding = match app_state.mode {
-        SimpleMode(Quitting) => unreachable!(), // should have exited the program by now
-        SimpleMode(Normal) => &config.normal_mode_key_bindings,
-        OverlayMode { .. } | TextInputMode { .. } => &config.text_input_mode_key_bindings,
-    };
-
     let mode_actions = app_state.mode.get_action_map();
 
     let global_map = ActionMapper::StaticActionMap(&GLOBAL_ACTION_MAP);
 
+    // only the normal mode actions can be repeated, since the others depend on the prompt that they are in
+    let repeatable_action_name = match app_state.mode {
+        SimpleMode(Normal) => app_state
+            .input_reader
+            .get_action_name(mode_key_binding)
+            .filter(|action_name| {
+                NORMAL_MODE_ACTION_MAP.contains_key(*action_name)
+                    && *action_name != "repeat_last_action"
+                    && !config.unrepeated_actions.contains(action_name)
+            })
+            .cloned(),
+        _ => None,
+    };
+
     let closure_option = app_state
         .input_reader
         .get_closure_by_key_bindings(mode_key_binding, &mode_actions)
         .or_else(|| {
-            // if we were not successful in finding a closure, look for a global key
+            // if we were not successful in finding a closure, look for a global key. The mode can replace the
+            // global actions with its own
             app_state
                 .input_reader
-                .get_closure_by_key_bindings(&config.global_key_bindings, &global_map)
+                .get_closure_by_key_bindings(&config.global_key_bindings, &mode_actions)
+                .or_else(|| {
+                    app_state
+                        .input_reader
+                        .get_closure_by_key_bindings(&config.global_key_bindings, &global_map)
+                })
         });
 
     if let Some(closure) = closure_option {
-        let action_data = ActionData::new(config, terminal, app_state, modifier, &dir_items);
+        let action_data = ActionData::new(
+            config, terminal, app_state, modifier, &dir_items, filesystem,
+        );
         let action_result = closure(action_data);
         match action_result {
             // set the error message if could not complete the action
             ActionResult::Invalid(error_message) => {
                 app_state.get_mut().error_message_line = Some(error_message);
             }
-            ActionResult::Valid => {}
+            ActionResult::Valid => {
+                if let Some(action_name) = repeatable_action_name {
+                    app_state.get_mut().last_action = Some((action_name, modifier));
+                }
+            }
         }
 
         // whether it was successful or not, clear the input state
@@ -290,15 +1015,30 @@ pub(self) fn inputs(
         let current_sequence = app_state
             .input_reader
             .get_human_friendly_verb_key_sequence();
-        if !app_state.input_reader.check_incomplete_commands(
-            &current_sequence.clone(),
-            vec![mode_key_binding, &config.global_key_bindings],
-        ) {
+        if !app_state
+            .input_reader
+            .check_incomplete_commands(vec![mode_key_binding, &config.global_key_bindings])
+        {
             // if it is a text mode, enter the entire sequence
             if app_state.mode.is_text_mode() {
                 app_state
                     .get_mut()
                     .copy_input_manager_verbs_to_entered_text();
+                // picking a register only takes a single key, so it does not wait for ENTER
+                if let OverlayMode {
+                    overlay_mode: modes::OverlayMode::Registers,
+                    ..
+                } = app_state.mode
+                {
+                    if let Some(select) = mode_actions.find_action(&String::from("select")) {
+                        let action_data = ActionData::new(
+                            config, terminal, app_state, modifier, &dir_items, filesystem,
+                        );
+                        if let ActionResult::Invalid(error_message) = select(action_data) {
+                            app_state.get_mut().error_message_line = Some(error_message);
+                        }
+                    }
+                }
             } else {
                 app_state.get_mut().error_message_line = Some(format!(
                     "Could not recognise that sequence: {}",
@@ -311,11 +1051,13 @@ pub(self) fn inputs(
     }
 }
 
-pub(self) fn draw<B: Backend>(
+fn draw<B: Backend>(
     f: &mut tui::Frame<B>,
     dir_items: Vec<FileTreeNode>,
+    dir_listing: &DirListing,
     config: &AppSettings,
     app_state: &mut TrackedModifiable<AppState>,
+    filesystem: &dyn Filesystem,
 ) {
     let default_styles = StyleSet {
         file: Style::default()
@@ -324,6 +1066,9 @@ pub(self) fn draw<B: Backend>(
         dir: Style::default()
             .bg(tui::style::Color::Black)
             .fg(tui::style::Color::LightBlue),
+        executable: Style::default()
+            .bg(tui::style::Color::Black)
+            .fg(tui::style::Color::LightGreen),
     };
 
     let cursor_styles = StyleSet {
@@ -333,6 +1078,9 @@ pub(self) fn draw<B: Backend>(
         dir: Style::default()
             .bg(tui::style::Color::White)
             .fg(tui::style::Color::Rgb(50, 50, 200)),
+        executable: Style::default()
+            .bg(tui::style::Color::White)
+            .fg(tui::style::Color::Rgb(0, 120, 0)),
     };
     let f_size = f.size();
     let bottom_text = app_state.error_message_line.clone().or_else(|| {
@@ -346,21 +1094,37 @@ pub(self) fn draw<B: Backend>(
         }
     });
 
-    // main division - main display vs the error line at the bottom
+    // main division - main display vs the error line at the bottom vs the status bar
     let chunks = tui::layout::Layout::default()
         .direction(tui::layout::Direction::Vertical)
         .constraints(
             [
                 Constraint::Min(1),
-                Constraint::Length(if bottom_text.is_some() { 3 } else { 0 }),
+                Constraint::Length(match (&bottom_text, config.border_style) {
+                    (None, _) => 0,
+                    (Some(_), BorderStyle::None) => 1,
+                    (Some(_), _) => 3,
+                }),
+                Constraint::Length(1),
             ]
             .as_ref(),
         )
         .split(f_size);
 
+    f.render_widget(
+        Paragraph::new(get_status_bar_text(
+            &dir_items,
+            dir_listing,
+            app_state,
+            filesystem,
+        ))
+        .style(Style::default().fg(tui::style::Color::Gray)),
+        chunks[2],
+    );
+
     // if the error line exists, write down the error text
     if let Some(bottom_text) = bottom_text {
-        let block = Block::default().borders(Borders::ALL);
+        let block = config.get_block();
         f.render_widget(
             Paragraph::new(bottom_text.to_owned()).block(block),
             chunks[1],
@@ -369,97 +1133,483 @@ pub(self) fn draw<B: Backend>(
 
     // main body
     {
+        // the toasts go over both panes
+        let main_area = chunks[0];
+        // there is not enough space for two panes in narrow terminals, so only show the list
+        let show_preview = app_state.is_preview_shown && chunks[0].width >= MIN_WIDTH_FOR_PREVIEW;
+        // neither pane can disappear completely
+        let preview_width_percent = config.preview_width_percent.unwrap_or(50).clamp(1, 99);
         let chunks = tui::layout::Layout::default()
             .direction(tui::layout::Direction::Horizontal)
-            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .constraints(
+                if show_preview {
+                    [
+                        Constraint::Percentage(100 - preview_width_percent),
+                        Constraint::Percentage(preview_width_percent),
+                    ]
+                } else {
+                    [Constraint::Percentage(100), Constraint::Percentage(0)]
+                }
+                .as_ref(),
+            )
             .split(chunks[0]);
 
-        let dir_path_display_string = app_state
-            .current_dir
-            .get_path_buf()
-            .as_os_str()
-            .to_string_lossy()
-            .into_owned();
+        let dir_path_display_string = display_path(
+            app_state.current_dir.get_path_buf(),
+            app_state.path_display,
+            config.path_display_root.as_deref(),
+        );
 
         let left_chunk = chunks[0];
         let right_chunk = chunks[1];
 
-        let block = Block::default().borders(Borders::ALL);
-        let selected_file = match app_state.mode {
-            SimpleMode(Quitting) => unreachable!(), // should have exited the program by now
-            SimpleMode(Normal) => app_state.selected_file.as_ref(),
-            TextInputMode {
-                text_input_type: Search,
-                ..
-            } => dir_items.get(0),
-            _ => None,
-        };
+        let block = config.get_block();
+        // the wait for the cursor to stop starts again whenever another file is selected
+        let previewed_path =
+            get_previewed_file(app_state, &dir_items).map(|file| file.get_path_buf().to_owned());
+        if previewed_path.as_ref() != app_state.preview_selected_at.as_ref().map(|(path, _)| path) {
+            app_state.get_mut().preview_selected_at =
+                previewed_path.map(|path| (path, Instant::now()));
+        }
+        let is_cursor_stopped = app_state
+            .preview_selected_at
+            .as_ref()
+            .is_none_or(|(_, selected_at)| selected_at.elapsed() >= config.get_preview_delay());
+        let selected_file = get_previewed_file(app_state, &dir_items);
 
-        let file_text_preview = selected_file.and_then(|f| get_file_text_preview(&f));
+        if show_preview {
+            let block = match selected_file {
+                Some(selected_file) => {
+                    block.title(get_preview_title(selected_file, config.time_format))
+                }
+                None => block,
+            };
+            // the search is about the file that was selected when it was made
+            let preview_search = app_state.preview_search.as_ref().filter(|preview_search| {
+                selected_file.map(FileTreeNode::get_path_buf) == Some(&preview_search.path)
+            });
+            // with lazy_preview, nothing is read until load_preview is used on the selected file
+            let is_preview_loaded = !config.lazy_preview
+                || selected_file.map(FileTreeNode::get_path_buf)
+                    == app_state.loaded_preview.as_ref();
+            let read_text_preview;
+            let file_text_preview = match preview_search {
+                // the matches are about the text that was searched, even if the file has changed since
+                Some(preview_search) => Some(&preview_search.text_preview),
+                None => {
+                    read_text_preview = selected_file
+                        .filter(|_| is_preview_loaded && is_cursor_stopped)
+                        .and_then(|selected_file| {
+                            get_file_text_preview(selected_file, PREVIEW_TEXT_FETCH_LENGTH)
+                        });
+                    read_text_preview.as_ref()
+                }
+            };
+            let header_style = Style::default()
+                .fg(tui::style::Color::Gray)
+                .add_modifier(Modifier::ITALIC);
 
-        if let Some(text_preview) = file_text_preview {
-            f.render_widget(Paragraph::new(text_preview).block(block), right_chunk);
-        } else {
-            f.render_widget(block, right_chunk);
+            match (selected_file, file_text_preview) {
+                (Some(selected_file), Some(text_preview)) => {
+                    let header =
+                        get_text_preview_header(selected_file, text_preview, preview_search);
+                    let mut text = Text::from(get_preview_lines(
+                        &text_preview.text,
+                        preview_search,
+                        right_chunk.height as usize,
+                    ));
+                    text.lines
+                        .insert(0, Spans::from(Span::styled(header, header_style)));
+                    f.render_widget(Paragraph::new(text).block(block), right_chunk);
+                }
+                (Some(_), None) if !is_preview_loaded => {
+                    let hint = match config
+                        .normal_mode_key_bindings
+                        .iter()
+                        .find(|(_, action_name)| *action_name == "load_preview")
+                    {
+                        Some((key_sequence, _)) => format!("Press {} to preview", key_sequence),
+                        None => String::from("Use load_preview to preview"),
+                    };
+                    f.render_widget(
+                        Paragraph::new(Span::styled(hint, header_style)).block(block),
+                        right_chunk,
+                    );
+                }
+                (Some(selected_file), None) if is_cursor_stopped && selected_file.is_dir() => {
+                    let header = match app_state.children_nums.get(selected_file.get_path_buf()) {
+                        Some(None) => String::from("The directory can not be read"),
+                        children_num => format!("{} entries", format_children_num(children_num)),
+                    };
+                    f.render_widget(
+                        Paragraph::new(Span::styled(header, header_style)).block(block),
+                        right_chunk,
+                    );
+                }
+                _ => f.render_widget(block, right_chunk),
+            }
         }
 
-        let block = Block::default()
-            .title(dir_path_display_string)
-            .borders(Borders::ALL);
+        let block = config.get_block().title(dir_path_display_string);
 
-        let left_widget = match app_state.mode {
-            SimpleMode(Quitting)
-            | OverlayMode {
-                background_mode: Quitting,
-                ..
-            } => unreachable!(), // should have exited the program by now
-            TextInputMode {
-                text_input_type: Search,
-                ..
-            } => get_search_mode_left_ui(app_state, &dir_items, &cursor_styles, &default_styles),
-            SimpleMode(Normal)
-            | OverlayMode {
-                background_mode: Normal,
-                ..
+        if dir_listing.is_loading() {
+            // do not touch the selection until we know what is in the directory
+            let loading_text = format!("{} Loading...", dir_listing.get_spinner_frame());
+            f.render_widget(Paragraph::new(loading_text).block(block), left_chunk);
+        } else {
+            let inner_area = block.inner(left_chunk);
+            f.render_widget(block, left_chunk);
+
+            // the detailed view has a row with the column names above the items
+            let list_area = if let ViewMode::Detailed = app_state.view_mode {
+                let areas = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
+                    .split(inner_area);
+                let header = FileTreeNode::get_details_header(
+                    config,
+                    app_state.get_sort_key(),
+                    app_state.get_is_sort_reversed(),
+                );
+                f.render_widget(
+                    Paragraph::new(header).style(Style::default().fg(tui::style::Color::Gray)),
+                    areas[0],
+                );
+                areas[1]
+            } else {
+                inner_area
+            };
+
+            let left_widget = match app_state.mode {
+                SimpleMode(Quitting)
+                | OverlayMode {
+                    background_mode: Quitting,
+                    ..
+                } => unreachable!(), // should have exited the program by now
+                TextInputMode {
+                    text_input_type: Search,
+                    ..
+                } => get_search_mode_left_ui(
+                    app_state,
+                    &dir_items,
+                    config,
+                    &cursor_styles,
+                    &default_styles,
+                    list_area.height as usize,
+                    list_area.width as usize,
+                ),
+                SimpleMode(Normal)
+                | OverlayMode {
+                    background_mode: Normal,
+                    ..
+                }
+                | TextInputMode {
+                    text_input_type: RunCommand | PreviewSearch,
+                } => get_default_left_ui(
+                    app_state,
+                    &dir_items,
+                    config,
+                    cursor_styles,
+                    default_styles,
+                    list_area.height as usize,
+                    list_area.width as usize,
+                ),
+            };
+
+            // only write it if it changed, since writing forces a redraw
+            if app_state.list_area != list_area {
+                app_state.get_mut().list_area = list_area;
             }
-            | TextInputMode {
-                text_input_type: RunCommand,
-            } => get_default_left_ui(
-                app_state,
-                &dir_items,
-                config.min_distance_from_cursor_to_bottom,
-                cursor_styles,
-                default_styles,
-                left_chunk.height as usize - 2, // -2 because one line from each side is used for the border
-            ),
-        };
+            f.render_widget(left_widget, list_area);
+        }
 
-        f.render_widget(left_widget.block(block), left_chunk);
+        // the entries of a selected directory are counted in the background, like the ones in the listing.
+        // This comes after the listing, so that the directories in it are counted again first after a refresh
+        if show_preview && is_cursor_stopped {
+            if let Some(selected_file) = get_previewed_file(app_state, &dir_items).cloned() {
+                AppState::count_children(app_state, &[selected_file]);
+            }
+        }
 
         // overlays
         if let OverlayMode { overlay_mode, .. } = &app_state.mode {
-            let widget = overlay_mode.get_popup_text(app_state.entered_text.clone(), &config);
+            let widget = overlay_mode.get_popup_text(app_state, config);
             let area = centered_rect(60, 60, f_size);
             f.render_widget(Clear, area); //this clears out the background
             f.render_widget(widget, area);
         }
 
-        // error popup
+        draw_toasts(f, &app_state.toasts, main_area, config);
+
+        if let Some(info_popup) = &app_state.info_popup {
+            let alignment = if info_popup.is_table {
+                Alignment::Left
+            } else {
+                Alignment::Center
+            };
+            draw_popup(
+                f,
+                config.get_block(),
+                &info_popup.title,
+                &info_popup.desc,
+                alignment,
+            );
+        }
+        // errors are drawn last, so that they are never hidden
         if let Some(error_popup) = &app_state.error_popup {
-            let block = Block::default()
-                .title(error_popup.title.clone())
-                .borders(Borders::ALL);
+            draw_popup(
+                f,
+                config
+                    .get_block()
+                    .border_style(Style::default().fg(tui::style::Color::Red)),
+                &error_popup.title,
+                &error_popup.desc,
+                Alignment::Center,
+            );
+        }
+    }
+}
 
-            let paragraph = Paragraph::new(error_popup.desc.clone())
-                .block(block)
-                .alignment(tui::layout::Alignment::Center)
-                .wrap(Wrap { trim: false });
+/// Stacks the toasts in the bottom right corner of `area`, with the newest one at the bottom
+fn draw_toasts<B: Backend>(
+    f: &mut tui::Frame<B>,
+    toasts: &[(String, Instant)],
+    area: Rect,
+    config: &AppSettings,
+) {
+    if toasts.is_empty() {
+        return;
+    }
+    // keep the borders of the panes around them
+    let area = area.inner(&Margin {
+        vertical: 1,
+        horizontal: 1,
+    });
+    let block = config.get_block();
+    // the size of the borders, which are not there with the "none" border style
+    let border_size = area.width - block.inner(area).width;
+    let text_width = toasts
+        .iter()
+        .map(|(message, _)| message.width())
+        .max()
+        .unwrap_or(0);
+    let width = (text_width as u16 + border_size).min(area.width);
+    let height = (toasts.len() as u16 + border_size).min(area.height);
+    let toast_area = Rect::new(area.right() - width, area.bottom() - height, width, height);
 
-            let area = centered_rect(60, 60, f_size);
-            f.render_widget(Clear, area); //this clears out the background
-            f.render_widget(paragraph, area);
+    let lines: Vec<_> = toasts
+        .iter()
+        .map(|(message, _)| Spans::from(message.as_str()))
+        .collect();
+    f.render_widget(Clear, toast_area);
+    f.render_widget(Paragraph::new(lines).block(block), toast_area);
+}
+
+fn draw_popup<B: Backend>(
+    f: &mut tui::Frame<B>,
+    block: Block,
+    title: &str,
+    desc: &str,
+    alignment: Alignment,
+) {
+    let block = block.title(title.to_owned());
+
+    let paragraph = Paragraph::new(desc.to_owned())
+        .block(block)
+        .alignment(alignment)
+        .wrap(Wrap { trim: false });
+
+    let area = centered_rect(60, 60, f.size());
+    f.render_widget(Clear, area); //this clears out the background
+    f.render_widget(paragraph, area);
+}
+
+/// Shows the size and the modification time of the selected item above its preview
+fn get_preview_title(selected_file: &FileTreeNode, time_format: TimeFormat) -> String {
+    let modified = match selected_file.get_modified() {
+        Ok(mtime) => format_mtime(mtime, time_format),
+        Err(_) => String::from("unknown"),
+    };
+    // the size of a directory is not the size of its contents, so it would be misleading
+    if selected_file.is_dir() {
+        format!("modified: {}", modified)
+    } else {
+        let size = match selected_file.get_size() {
+            Ok(size) => format_size(size),
+            Err(_) => String::from("?"),
+        };
+        format!("{} | modified: {}", size, modified)
+    }
+}
+
+/// Starts getting the git statuses of the current directory if they are shown
+fn start_git_status_listing(
+    config: &AppSettings,
+    app_state: &AppState,
+) -> Option<GitStatusListing> {
+    if config.show_git_status {
+        Some(GitStatusListing::new(
+            app_state.current_dir.get_path_buf().to_owned(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Puts the contents of the expanded directories right after them, so that the listing looks like a tree
+fn insert_expanded_dirs(
+    dir_items: Vec<FileTreeNode>,
+    app_state: &AppState,
+    config: &AppSettings,
+    is_shown: &dyn Fn(&FileTreeNode) -> bool,
+) -> Vec<FileTreeNode> {
+    if app_state.expanded_dirs.is_empty() {
+        return dir_items;
+    }
+
+    let mut ret = Vec::with_capacity(dir_items.len());
+    for item in dir_items {
+        let children = app_state.expanded_dirs.get(item.get_path_buf()).cloned();
+        ret.push(item);
+        if let Some(mut children) = children {
+            children.retain(|child| is_shown(child));
+            sort_dir_items(
+                &mut children,
+                &app_state.get_sort_key(),
+                app_state.get_is_sort_reversed(),
+                config.group_directories,
+            );
+            ret.extend(insert_expanded_dirs(children, app_state, config, is_shown));
+        }
+    }
+    ret
+}
+
+/// The line above the preview of a file, e.g. "1.2K | 40 lines"
+fn get_text_preview_header(
+    selected_file: &FileTreeNode,
+    text_preview: &TextPreview,
+    preview_search: Option<&modes::PreviewSearch>,
+) -> String {
+    let text = &text_preview.text;
+    // the last line does not have to end with a newline
+    let lines_num =
+        text.matches('\n').count() + usize::from(!text.is_empty() && !text.ends_with('\n'));
+    let size = match selected_file.get_size() {
+        Ok(size) => format_size(size),
+        Err(_) => String::from("?"),
+    };
+    let mut header = if text_preview.is_truncated {
+        format!("{} | {}+ lines (only the start is shown)", size, lines_num)
+    } else {
+        format!("{} | {} lines", size, lines_num)
+    };
+    if let Some(preview_search) = preview_search {
+        header.push_str(&format!(
+            " | match {}/{} for '{}'",
+            preview_search.current_match + 1,
+            preview_search.matches.len(),
+            preview_search.query
+        ));
+    }
+    header
+}
+
+/// The lines of the preview that fit into `height`, scrolled to the current match and with the matches highlighted
+fn get_preview_lines(
+    text: &str,
+    preview_search: Option<&modes::PreviewSearch>,
+    height: usize,
+) -> Vec<Spans<'static>> {
+    let matches = preview_search.map_or(&[][..], |preview_search| &preview_search.matches);
+    let current_match =
+        preview_search.and_then(|preview_search| matches.get(preview_search.current_match));
+    let first_line = current_match.map_or(0, |current_match| {
+        current_match
+            .line
+            .saturating_sub(PREVIEW_SEARCH_CONTEXT_LINES)
+    });
+    let match_style = Style::default()
+        .bg(tui::style::Color::Yellow)
+        .fg(tui::style::Color::Black);
+    let current_match_style = Style::default()
+        .bg(tui::style::Color::LightRed)
+        .fg(tui::style::Color::Black);
+
+    text.lines()
+        .enumerate()
+        .skip(first_line)
+        .take(height)
+        .map(|(line, line_text)| {
+            // the matches are in the order of the lines
+            let line_matches_start = matches.partition_point(|found| found.line < line);
+            let mut spans = vec![];
+            let mut shown_up_to = 0;
+            for found in matches[line_matches_start..]
+                .iter()
+                .take_while(|found| found.line == line)
+            {
+                spans.push(Span::raw(line_text[shown_up_to..found.start].to_owned()));
+                spans.push(Span::styled(
+                    line_text[found.start..found.end].to_owned(),
+                    if Some(found) == current_match {
+                        current_match_style
+                    } else {
+                        match_style
+                    },
+                ));
+                shown_up_to = found.end;
+            }
+            spans.push(Span::raw(line_text[shown_up_to..].to_owned()));
+            Spans::from(spans)
+        })
+        .collect()
+}
+
+/// The text of the line at the very bottom, which summarises the state of the app
+fn get_status_bar_text(
+    dir_items: &[FileTreeNode],
+    dir_listing: &DirListing,
+    app_state: &AppState,
+    filesystem: &dyn Filesystem,
+) -> String {
+    let mut status_bar_text = format!(" {} items", dir_items.len());
+    // e.g. entries that vanished while being listed, or that are not accessible in system directories
+    if dir_listing.get_skipped_num() > 0 {
+        status_bar_text.push_str(&format!(
+            " ({} unreadable skipped)",
+            dir_listing.get_skipped_num()
+        ));
+    }
+    status_bar_text.push_str(&format!(
+        " | sort: {}{}",
+        app_state.get_sort_key().get_name(),
+        if app_state.get_is_sort_reversed() {
+            " (reversed)"
+        } else {
+            ""
         }
+    ));
+    if app_state.is_read_only {
+        status_bar_text.push_str(" | 🔒 read-only");
+    }
+    if filesystem.is_dry_run() {
+        status_bar_text.push_str(&format!(
+            " | dry run: {} change(s) skipped",
+            filesystem.get_log().len()
+        ));
     }
+    if let Some(archive_job) = &app_state.archive_job {
+        status_bar_text.push_str(&format!(" | {}", archive_job.get_status_text()));
+    }
+    if let Some(file_hashing) = &app_state.file_hashing {
+        status_bar_text.push_str(&format!(
+            " | {} hashing '{}'",
+            file_hashing.get_spinner_frame(),
+            file_hashing.get_file().get_simple_name()
+        ));
+    }
+    status_bar_text
 }
 
 // from https://github.com/fdehau/tui-rs/blob/master/examples/popup.rs
@@ -489,3 +1639,89 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         )
         .split(layout[1])[1]
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tui::{backend::TestBackend, Terminal};
+
+    use crate::{
+        directory_tree::{DirListing, FileTreeNode},
+        test_util::TestApp,
+    };
+
+    use super::draw;
+
+    const SIZES: [(u16, u16); 7] = [(0, 0), (1, 1), (2, 2), (10, 3), (49, 5), (50, 4), (80, 2)];
+
+    /// Draws the app at every size, which should not panic however little room there is
+    fn draw_at_tiny_sizes(app: &mut TestApp, dir_items: &[FileTreeNode]) {
+        let dir_listing =
+            DirListing::new(app.app_state.current_dir.clone(), Some(dir_items.to_vec()));
+        for (width, height) in SIZES {
+            let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+            terminal
+                .draw(|f| {
+                    draw(
+                        f,
+                        dir_items.to_vec(),
+                        &dir_listing,
+                        &app.config,
+                        &mut app.app_state,
+                        &app.filesystem,
+                    )
+                })
+                .unwrap();
+        }
+    }
+
+    fn get_dir_items(root: &Path) -> Vec<FileTreeNode> {
+        ["日本語のファイル名.txt", "file", "other"]
+            .iter()
+            .map(|name| FileTreeNode::new(root.join(name)))
+            .collect()
+    }
+
+    #[test]
+    fn normal_mode_draws_at_tiny_sizes() {
+        let root = Path::new("/fphile-test-root");
+        let mut app = TestApp::new(root);
+        let dir_items = get_dir_items(root);
+        app.select(&root.join("other"));
+
+        draw_at_tiny_sizes(&mut app, &dir_items);
+    }
+
+    #[test]
+    fn prompts_and_popups_draw_at_tiny_sizes() {
+        let root = Path::new("/fphile-test-root");
+        let mut app = TestApp::new(root);
+        let dir_items = get_dir_items(root);
+        app.select(&root.join("file"));
+
+        app.run_action("rename", &[]);
+        draw_at_tiny_sizes(&mut app, &dir_items);
+
+        app.app_state.get_mut().reset_state();
+        app.app_state.get_mut().error_popup(
+            String::from("Something failed"),
+            String::from("with a long explanation of what went wrong"),
+        );
+        app.app_state
+            .get_mut()
+            .toast(String::from("A toast that is too long for the screen"));
+        draw_at_tiny_sizes(&mut app, &dir_items);
+    }
+
+    #[test]
+    fn search_draws_at_tiny_sizes() {
+        let root = Path::new("/fphile-test-root");
+        let mut app = TestApp::new(root);
+        let dir_items = get_dir_items(root);
+
+        app.run_action("search_mode", &[]);
+        app.app_state.get_mut().set_entered_text(String::from("fi"));
+        draw_at_tiny_sizes(&mut app, &dir_items);
+    }
+}
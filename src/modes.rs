@@ -1,29 +1,48 @@
 pub mod delete_mode;
 pub mod normal_mode;
 pub mod search_mode;
+pub mod substitute_mode;
 
 use std::{
-    cmp::Ordering,
-    fs::{self, File},
+    cmp::{Ordering, Reverse},
+    collections::BTreeMap,
+    fs::File,
     io::Read,
     iter::once,
+    path::{Path, PathBuf},
 };
 
+use regex::RegexBuilder;
 use tui::{
-    style::Style,
+    style::{Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, Paragraph, Wrap},
+    widgets::{Paragraph, Wrap},
 };
 
 use crate::{
-    actions::{ActionMapper, ActionResult, NORMAL_MODE_ACTION_MAP},
-    compile_time_settings::PREVIEW_TEXT_FETCH_LENGTH,
-    directory_tree::{run_command_in_foreground, FileTreeNode},
-    helper_types::{AppSettings, FindKeyByActionName},
+    actions::{
+        apply_marks, copy_to_bookmark, delete_instantly, open_file, start_extracting, trash_files,
+        ActionData, ActionMapper, ActionResult, NORMAL_MODE_ACTION_MAP,
+    },
+    archive::{create_archive, ArchiveJob},
+    compile_time_settings::{
+        DISK_USAGE_SHOWN_ENTRIES, JUMP_SHOWN_DIRS, MAX_DISK_USAGE_ENTRIES,
+        PREVIEW_SEARCH_FETCH_LENGTH, UNNAMED_REGISTER,
+    },
+    directory_tree::{
+        display_path, format_size, resolve_path, run_command_in_foreground, DiskUsage, FileTreeNode,
+    },
+    helper_types::{
+        AppSettings, AppState, ArchiveFormat, FindKeyByActionName, GroupDirectories, MarkType,
+        Register, SearchMatch, SortKey,
+    },
 };
 
-use self::delete_mode::delete_file_tree_node;
+use self::substitute_mode::{
+    find_conflict, find_existing, get_substituted_names, is_taken, rename_all, rename_path,
+};
 
+#[allow(clippy::enum_variant_names)]
 pub enum Mode {
     SimpleMode(SimpleMode),
     OverlayMode {
@@ -43,13 +62,70 @@ pub enum SimpleMode {
 pub enum TextInput {
     Search,
     RunCommand,
+    // searches the text of the previewed file
+    PreviewSearch,
 }
 
 pub enum OverlayMode {
     CreateDirectory,
-    CreateFile,
-    Rename { old_file: FileTreeNode },
-    DeleteInstantlyConfirm { file: FileTreeNode },
+    // the contents of the file are taken from the clipboard instead of a template
+    CreateFile {
+        from_clipboard: bool,
+    },
+    // with keep_extension, the old file's extension is appended to the typed name
+    Rename {
+        old_file: FileTreeNode,
+        keep_extension: bool,
+    },
+    // the typed name is taken by another file
+    RenameOverwriteConfirm {
+        old_file: FileTreeNode,
+        new_path: PathBuf,
+    },
+    DeleteInstantlyConfirm {
+        file: FileTreeNode,
+    },
+    TrashConfirm {
+        files: Vec<FileTreeNode>,
+    },
+    // the marked files would be deleted or moved
+    ApplyMarksConfirm,
+    OpenLargeFileConfirm {
+        file: FileTreeNode,
+        size: u64,
+        // the line to open the file at, which is gone from the state once the prompt is shown
+        line: Option<usize>,
+    },
+    ReloadConfigConfirm,
+    GoToPath,
+    // asks for a find/replace pattern applied to the names of all the paths
+    SubstituteRename {
+        paths: Vec<PathBuf>,
+        is_regex: bool,
+    },
+    // asks for the name of the archive to put the paths into
+    Archive {
+        paths: Vec<PathBuf>,
+    },
+    // the directory that the archive would be extracted into already exists
+    ExtractOverwriteConfirm {
+        file: FileTreeNode,
+        format: ArchiveFormat,
+        destination: PathBuf,
+    },
+    // asks for the directory that the path of `file` is made relative to
+    CopyRelativePath {
+        file: FileTreeNode,
+    },
+    CommandMenu,
+    // fuzzy searches the visited directories
+    JumpToDir,
+    // asks which bookmark to copy the paths into
+    CopyToBookmark {
+        paths: Vec<PathBuf>,
+    },
+    DiskUsage,
+    Registers,
 }
 
 impl Mode {
@@ -75,7 +151,7 @@ impl Mode {
                     .map(ToOwned::to_owned)
                     .chain(once(quoted_command));
 
-                    run_command_in_foreground(
+                    let result = run_command_in_foreground(
                         options,
                         v.terminal,
                         v.app_state.current_dir.get_path_buf(),
@@ -84,8 +160,51 @@ impl Mode {
                         true,
                     );
 
+                    v.app_state.get_mut().reset_state();
+                    // the command might have changed the files
+                    v.app_state.get_mut().invalidate_listing();
+                    if let Err(err) = result {
+                        v.app_state
+                            .get_mut()
+                            .error_popup(String::from("The command failed"), err);
+                    }
+
+                    ActionResult::Valid
+                }),
+            ),
+            Mode::TextInputMode {
+                text_input_type: TextInput::PreviewSearch,
+            } => ActionMapper::new_dynamic(
+                String::from("select"),
+                Box::new(|v| {
+                    let query = v.app_state.entered_text.clone();
+                    let file = match &v.app_state.selected_file {
+                        Some(selected_file) => selected_file.clone(),
+                        None => return ActionResult::Invalid(String::from("No file selected")),
+                    };
+                    let text_preview =
+                        match get_file_text_preview(&file, PREVIEW_SEARCH_FETCH_LENGTH) {
+                            Some(text_preview) => text_preview,
+                            None => {
+                                return ActionResult::Invalid(String::from(
+                                    "The file can not be read",
+                                ))
+                            }
+                        };
+                    let matches = find_in_text(&text_preview.text, &query);
+
                     v.app_state.get_mut().reset_state();
 
+                    if matches.is_empty() {
+                        return ActionResult::Invalid(format!("'{}' was not found", query));
+                    }
+                    v.app_state.get_mut().preview_search = Some(PreviewSearch {
+                        path: file.get_path_buf().to_owned(),
+                        query,
+                        matches,
+                        current_match: 0,
+                        text_preview,
+                    });
                     ActionResult::Valid
                 }),
             ),
@@ -94,8 +213,9 @@ impl Mode {
             } => ActionMapper::new_dynamic(
                 String::from("select"),
                 Box::new(|v| {
-                    if let Some(first_item) = v.dir_items.get(0) {
-                        v.app_state.get_mut().selected_file = Some(first_item.to_owned());
+                    let search_cursor = v.app_state.get_search_cursor(v.dir_items.len());
+                    if let Some(item) = v.dir_items.get(search_cursor) {
+                        v.app_state.get_mut().selected_file = Some(item.to_owned());
                     }
 
                     v.app_state.get_mut().reset_state();
@@ -104,28 +224,103 @@ impl Mode {
                 }),
             ),
             Mode::OverlayMode {
-                overlay_mode: OverlayMode::Rename { old_file },
+                overlay_mode:
+                    OverlayMode::Rename {
+                        old_file,
+                        keep_extension,
+                    },
                 ..
             } => {
                 let old_file = old_file.to_owned();
+                let keep_extension = *keep_extension;
                 ActionMapper::new_dynamic(
                     String::from("select"),
                     Box::new(move |v| {
-                        // TODO: do we want to check if the new name is available?
-                        // NOTE: this check is not 100% reliable because of the race condition.
+                        let mut new_name = v.app_state.entered_text.clone();
+                        if keep_extension {
+                            if let Some(extension) = old_file.get_extension() {
+                                new_name = format!("{}.{}", new_name, extension);
+                            }
+                        }
+                        // the file stays in its directory, which is not the current one inside an expanded directory
+                        let new_path = match old_file.get_path_buf().parent() {
+                            Some(parent) => parent.join(new_name),
+                            None => v.app_state.current_dir.get_path_buf().join(new_name),
+                        };
+
+                        // reset the mode
+                        v.app_state.get_mut().reset_state();
 
-                        let new_name = &v.app_state.entered_text;
-                        let mut new_path = v.app_state.current_dir.get_path_buf().clone();
-                        new_path.push(new_name);
+                        // NOTE: this check is not 100% reliable because of the race condition.
+                        if is_taken(old_file.get_path_buf(), &new_path)
+                            && v.config.confirmations.overwrite
+                        {
+                            v.app_state.get_mut().mode = Mode::OverlayMode {
+                                background_mode: SimpleMode::Normal,
+                                overlay_mode: OverlayMode::RenameOverwriteConfirm {
+                                    old_file: old_file.clone(),
+                                    new_path,
+                                },
+                            };
+                            return ActionResult::Valid;
+                        }
+                        rename_file(v, &old_file, &new_path)
+                    }),
+                )
+            }
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::RenameOverwriteConfirm { old_file, new_path },
+                ..
+            } => {
+                let old_file = old_file.to_owned();
+                let new_path = new_path.to_owned();
+                ActionMapper::new_dynamic(
+                    String::from("select"),
+                    Box::new(move |v| {
+                        v.app_state.get_mut().reset_state();
+                        rename_file(v, &old_file, &new_path)
+                    }),
+                )
+            }
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::SubstituteRename { paths, is_regex },
+                ..
+            } => {
+                let paths = paths.clone();
+                let is_regex = *is_regex;
+                ActionMapper::new_dynamic(
+                    String::from("select"),
+                    Box::new(move |v| {
+                        // the prompt stays open on errors, so that the pattern can be fixed
+                        let renames = match get_substituted_names(
+                            &paths,
+                            &v.app_state.entered_text,
+                            is_regex,
+                        ) {
+                            Ok(renames) => renames,
+                            Err(err) => return ActionResult::Invalid(err),
+                        };
+                        let case_insensitive_names = v.app_state.case_insensitive_names;
+                        if let Some(conflict) = find_conflict(&renames, case_insensitive_names) {
+                            return ActionResult::Invalid(format!(
+                                "Nothing was renamed: {}",
+                                conflict
+                            ));
+                        }
+                        let result = rename_all(&renames, v.filesystem, case_insensitive_names);
 
                         // reset the mode
                         v.app_state.get_mut().reset_state();
+                        v.app_state.get_mut().invalidate_listing();
 
-                        match fs::rename(old_file.get_path_buf(), new_path) {
-                            Ok(_) => ActionResult::Valid,
-                            Err(err) => {
-                                ActionResult::Invalid(format!("Error while renaming: {}", err))
+                        match result {
+                            Ok(num_renamed) => {
+                                v.app_state
+                                    .get_mut()
+                                    .toast(format!("Renamed {} item(s)", num_renamed));
+                                ActionResult::Valid
                             }
+                            Err(err) => ActionResult::Invalid(err),
                         }
                     }),
                 )
@@ -135,53 +330,403 @@ impl Mode {
                 ..
             } => {
                 let file = file.to_owned();
+                ActionMapper::new_dynamic(
+                    String::from("select"),
+                    Box::new(move |v| delete_instantly(v, &file)),
+                )
+            }
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::TrashConfirm { files },
+                ..
+            } => {
+                let files = files.to_owned();
+                ActionMapper::new_dynamic(
+                    String::from("select"),
+                    Box::new(move |v| {
+                        v.app_state.get_mut().reset_state();
+                        trash_files(v, &files)
+                    }),
+                )
+            }
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::ApplyMarksConfirm,
+                ..
+            } => ActionMapper::new_dynamic(
+                String::from("select"),
+                Box::new(|v| {
+                    // not reset_state, since that would remove the marks
+                    v.app_state.get_mut().mode = Mode::SimpleMode(SimpleMode::Normal);
+                    apply_marks(v)
+                }),
+            )
+            .with_action(
+                String::from("normal_mode"),
+                // the marks are kept when cancelling too, so that they can be changed and applied again
+                Box::new(|v| {
+                    v.app_state.get_mut().mode = Mode::SimpleMode(SimpleMode::Normal);
+                    ActionResult::Valid
+                }),
+            ),
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::OpenLargeFileConfirm { file, line, .. },
+                ..
+            } => {
+                let file = file.to_owned();
+                let line = *line;
+                ActionMapper::new_dynamic(
+                    String::from("select"),
+                    Box::new(move |v| {
+                        v.app_state.get_mut().reset_state();
+                        open_file(v, &file, line)
+                    }),
+                )
+            }
+            Mode::OverlayMode {
+                overlay_mode:
+                    OverlayMode::ExtractOverwriteConfirm {
+                        file,
+                        format,
+                        destination,
+                    },
+                ..
+            } => {
+                let file = file.to_owned();
+                let format = *format;
+                let destination = destination.to_owned();
+                ActionMapper::new_dynamic(
+                    String::from("select"),
+                    Box::new(move |v| {
+                        v.app_state.get_mut().reset_state();
+                        start_extracting(v, &file, format, destination.clone())
+                    }),
+                )
+            }
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::ReloadConfigConfirm,
+                ..
+            } => ActionMapper::new_dynamic(
+                String::from("select"),
+                Box::new(|v| {
+                    // the config is owned by the main loop, so ask it to do the reloading
+                    v.app_state.get_mut().config_reload_requested = true;
+
+                    // reset the mode
+                    v.app_state.get_mut().reset_state();
+
+                    ActionResult::Valid
+                }),
+            ),
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::GoToPath,
+                ..
+            } => ActionMapper::new_dynamic(
+                String::from("select"),
+                Box::new(|v| {
+                    let path = resolve_path(
+                        v.app_state.current_dir.get_path_buf(),
+                        &v.app_state.entered_text,
+                    );
+
+                    // reset the mode
+                    v.app_state.get_mut().reset_state();
+
+                    if path.is_dir() {
+                        v.app_state.get_mut().change_dir(FileTreeNode::new(path));
+                    } else {
+                        v.app_state.get_mut().error_popup(
+                            String::from("Could not go to the path"),
+                            format!("'{}' is not a directory", path.to_string_lossy()),
+                        );
+                    }
+
+                    ActionResult::Valid
+                }),
+            ),
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::CopyRelativePath { file },
+                ..
+            } => {
+                let file = file.clone();
                 ActionMapper::new_dynamic(
                     String::from("select"),
                     Box::new(move |v| {
-                        let result = delete_file_tree_node(&file);
+                        let base = resolve_path(
+                            v.app_state.current_dir.get_path_buf(),
+                            &v.app_state.entered_text,
+                        );
 
                         // reset the mode
                         v.app_state.get_mut().reset_state();
 
-                        match result {
-                            Ok(_) => ActionResult::Valid,
-                            Err(err) => {
-                                ActionResult::Invalid(format!("Error while deleting: {}", err))
+                        let relative_path = match file.get_path_buf().strip_prefix(&base) {
+                            // the base itself
+                            Ok(relative_path) if relative_path.as_os_str().is_empty() => {
+                                String::from(".")
+                            }
+                            Ok(relative_path) => relative_path.to_string_lossy().into_owned(),
+                            Err(_) => {
+                                v.app_state.get_mut().error_popup(
+                                    String::from("Could not copy the relative path"),
+                                    format!(
+                                        "'{}' is not inside '{}'",
+                                        file.get_path_buf().to_string_lossy(),
+                                        base.to_string_lossy()
+                                    ),
+                                );
+                                return ActionResult::Valid;
                             }
+                        };
+
+                        match v
+                            .app_state
+                            .get_mut()
+                            .set_clipboard_text(relative_path.clone())
+                        {
+                            Ok(()) => {
+                                v.app_state
+                                    .get_mut()
+                                    .toast(format!("Copied '{}' to the clipboard", relative_path));
+                                ActionResult::Valid
+                            }
+                            Err(err) => ActionResult::Invalid(format!(
+                                "Could not copy to the clipboard: {}",
+                                err
+                            )),
                         }
                     }),
                 )
             }
             Mode::OverlayMode {
-                overlay_mode: OverlayMode::CreateFile,
+                overlay_mode: OverlayMode::DiskUsage,
+                ..
+            } => ActionMapper::new_dynamic(
+                String::from("select"),
+                Box::new(|v| {
+                    let typed_text = v.app_state.entered_text.trim();
+                    // the number that is shown next to the entry
+                    let item = typed_text
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|number| number.checked_sub(1))
+                        .and_then(|index| {
+                            let disk_usage = v.app_state.disk_usage.as_ref()?;
+                            disk_usage
+                                .get_sorted_entries()
+                                .get(index)
+                                .map(|(item, _)| item.clone())
+                        });
+                    let item = match item {
+                        Some(item) => item,
+                        None => {
+                            return ActionResult::Invalid(format!(
+                                "There is no entry '{}'",
+                                typed_text
+                            ))
+                        }
+                    };
+
+                    v.app_state.get_mut().reset_state();
+                    if item.is_dir() {
+                        v.app_state.get_mut().change_dir(item);
+                    } else {
+                        // files can not be entered, so just put the cursor on them
+                        v.app_state.get_mut().selected_file = Some(item);
+                    }
+                    ActionResult::Valid
+                }),
+            ),
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::CopyToBookmark { paths },
                 ..
             } => {
+                let paths = paths.clone();
                 ActionMapper::new_dynamic(
                     String::from("select"),
                     Box::new(move |v| {
-                        let mut file_path = v.app_state.current_dir.get_path_buf().clone();
+                        let number_or_name = v.app_state.entered_text.trim().to_owned();
+                        copy_to_bookmark(v, &paths, &number_or_name)
+                    }),
+                )
+            }
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::Registers,
+                ..
+            } => ActionMapper::new_dynamic(
+                String::from("select"),
+                Box::new(|v| {
+                    // ENTER without a key picks the unnamed register
+                    let register = v
+                        .app_state
+                        .entered_text
+                        .chars()
+                        .next()
+                        .unwrap_or(UNNAMED_REGISTER);
+                    // the marks stay, since they are what the register will be used on
+                    let app_state = v.app_state.get_mut();
+                    app_state.entered_text = String::new();
+                    app_state.cursor_pos = 0;
+                    app_state.mode = Mode::SimpleMode(SimpleMode::Normal);
+                    app_state.pending_register = Some(register);
+                    app_state.toast(format!("Using the register '{}'", register));
+                    ActionResult::Valid
+                }),
+            ),
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::JumpToDir,
+                ..
+            } => ActionMapper::new_dynamic(
+                String::from("select"),
+                Box::new(|v| {
+                    let query = v.app_state.entered_text.trim().to_owned();
+                    let best_match = v.app_state.visited_dirs.get_matches(&query, 1).pop();
+                    let dir = match best_match {
+                        Some(dir) => dir,
+                        None => {
+                            return ActionResult::Invalid(format!(
+                                "No visited directory matches '{}'",
+                                query
+                            ))
+                        }
+                    };
+                    v.app_state.get_mut().reset_state();
+                    v.app_state.get_mut().change_dir(FileTreeNode::new(dir));
+                    ActionResult::Valid
+                }),
+            ),
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::CommandMenu,
+                ..
+            } => ActionMapper::new_dynamic(
+                String::from("select"),
+                Box::new(|v| {
+                    let typed_text = v.app_state.entered_text.trim();
+                    // commands can be picked either by their number or by their name
+                    let custom_command = match typed_text.parse::<usize>() {
+                        Ok(number) => number
+                            .checked_sub(1)
+                            .and_then(|index| v.config.commands.values().nth(index)),
+                        Err(_) => v.config.commands.get(typed_text),
+                    };
+                    let custom_command = match custom_command {
+                        Some(custom_command) => custom_command,
+                        None => {
+                            return ActionResult::Invalid(format!(
+                                "There is no command '{}'",
+                                typed_text
+                            ))
+                        }
+                    };
+                    if custom_command.command.is_empty() {
+                        return ActionResult::Invalid(String::from("The command is empty"));
+                    }
+
+                    let uses_file = custom_command
+                        .command
+                        .iter()
+                        .any(|option| option.contains("<FILE>"));
+                    let file_name = match &v.app_state.selected_file {
+                        Some(selected_file) => selected_file.get_simple_name().to_owned(),
+                        None if uses_file => {
+                            return ActionResult::Invalid(String::from("No file selected"))
+                        }
+                        None => String::new(),
+                    };
+                    let dir_path = v
+                        .app_state
+                        .current_dir
+                        .get_path_buf()
+                        .to_string_lossy()
+                        .to_string();
+                    let options = custom_command.command.iter().map(|option| {
+                        option
+                            .replace("<FILE>", &file_name)
+                            .replace("<DIR>", &dir_path)
+                    });
+
+                    let result = run_command_in_foreground(
+                        options,
+                        v.terminal,
+                        v.app_state.current_dir.get_path_buf(),
+                        &v.app_state.interrupt_signal_receiver,
+                        v.config.command_status_refresh_secs,
+                        custom_command.pause,
+                    );
+
+                    v.app_state.get_mut().reset_state();
+                    // the command might have changed the files
+                    v.app_state.get_mut().invalidate_listing();
+                    if let Err(err) = result {
+                        v.app_state
+                            .get_mut()
+                            .error_popup(String::from("The command failed"), err);
+                    }
 
-                        file_path.push(v.app_state.entered_text.clone());
+                    ActionResult::Valid
+                }),
+            ),
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::CreateFile { from_clipboard },
+                ..
+            } => {
+                let from_clipboard = *from_clipboard;
+                ActionMapper::new_dynamic(
+                    String::from("select"),
+                    Box::new(move |v| {
+                        let file_path =
+                            v.app_state.get_target_dir().join(&v.app_state.entered_text);
+                        // the prompt stays open, so that another name can be typed
+                        if let Some(existing) =
+                            find_existing(&file_path, v.app_state.case_insensitive_names)
+                        {
+                            return ActionResult::Invalid(format!(
+                                "'{}' already exists",
+                                existing.display()
+                            ));
+                        }
+                        let template = file_path.extension().and_then(|extension| {
+                            v.config
+                                .templates
+                                .get(&extension.to_string_lossy().to_lowercase())
+                        });
+                        // if the clipboard is empty or can not be read, the file is just left empty
+                        let clipboard_text = if from_clipboard {
+                            Some(
+                                v.app_state
+                                    .get_mut()
+                                    .get_clipboard_text()
+                                    .unwrap_or_default(),
+                            )
+                        } else {
+                            None
+                        };
+                        let filesystem = &mut *v.filesystem;
                         let result = (|| {
                             // create the directory first
                             let mut dir_path = file_path.clone();
                             dir_path.pop();
 
-                            fs::create_dir_all(dir_path)?;
-
-                            File::create(file_path)?;
+                            filesystem.create_dir_all(&dir_path)?;
 
-                            std::io::Result::Ok(())
+                            let contents = if let Some(clipboard_text) = clipboard_text {
+                                clipboard_text
+                            } else if let Some(template) = template {
+                                template.get_contents()?
+                            } else {
+                                String::new()
+                            };
+                            filesystem.write_file(&file_path, contents.as_bytes())
                         })();
 
                         // reset the mode
                         v.app_state.get_mut().reset_state();
+                        v.app_state.get_mut().invalidate_listing();
 
                         match result {
                             Ok(_) => ActionResult::Valid,
-                            Err(err) => {
-                                ActionResult::Invalid(format!("Error while deleting: {}", err))
-                            }
+                            Err(err) => ActionResult::Invalid(format!(
+                                "Error while creating the file: {}",
+                                err
+                            )),
                         }
                     }),
                 )
@@ -193,14 +738,13 @@ impl Mode {
                 ActionMapper::new_dynamic(
                     String::from("select"),
                     Box::new(move |v| {
-                        let mut dir_path = v.app_state.current_dir.get_path_buf().clone();
-
-                        dir_path.push(v.app_state.entered_text.clone());
+                        let dir_path = v.app_state.get_target_dir().join(&v.app_state.entered_text);
 
-                        let result = fs::create_dir_all(dir_path);
+                        let result = v.filesystem.create_dir_all(&dir_path);
 
                         // reset the mode
                         v.app_state.get_mut().reset_state();
+                        v.app_state.get_mut().invalidate_listing();
 
                         match result {
                             Ok(_) => ActionResult::Valid,
@@ -211,66 +755,501 @@ impl Mode {
                     }),
                 )
             }
+            Mode::OverlayMode {
+                overlay_mode: OverlayMode::Archive { paths },
+                ..
+            } => {
+                let paths = paths.clone();
+                ActionMapper::new_dynamic(
+                    String::from("select"),
+                    Box::new(move |v| {
+                        let mut archive_name = v.app_state.entered_text.trim().to_owned();
+                        if archive_name.is_empty() {
+                            return ActionResult::Invalid(String::from("The archive needs a name"));
+                        }
+                        if v.app_state.archive_job.is_some() {
+                            return ActionResult::Invalid(String::from(
+                                "Another archive is still being worked on",
+                            ));
+                        }
+                        // the extension that is typed wins over the configured format
+                        let format = match ArchiveFormat::from_file_name(&archive_name) {
+                            Some(format) => format,
+                            None => {
+                                archive_name.push_str(v.config.archive_format.get_extension());
+                                v.config.archive_format
+                            }
+                        };
+                        let destination = v.app_state.get_target_dir().join(&archive_name);
+                        let paths = paths.clone();
+
+                        // reset the mode
+                        v.app_state.get_mut().reset_state();
+
+                        let description = format!("archiving '{}'", archive_name);
+                        if !v.filesystem.start_job(&format!(
+                            "archive {} item(s) into '{}'",
+                            paths.len(),
+                            destination.display()
+                        )) {
+                            v.app_state
+                                .get_mut()
+                                .toast(format!("Dry run: skipped {}", description));
+                            return ActionResult::Valid;
+                        }
+                        v.app_state.get_mut().archive_job =
+                            Some(ArchiveJob::new(description, move |report_progress| {
+                                create_archive(&paths, &destination, format, report_progress)
+                            }));
+                        ActionResult::Valid
+                    }),
+                )
+            }
         }
     }
 }
 
 impl OverlayMode {
-    pub fn get_popup_text(&self, typed_text: String, config: &AppSettings) -> Paragraph {
+    pub fn get_popup_text(&self, app_state: &AppState, config: &AppSettings) -> Paragraph<'_> {
+        let typed_text = app_state.entered_text.clone();
+        let cursor_pos = app_state.cursor_pos;
         let (title, spans) = match self {
-            OverlayMode::Rename { old_file } => (
+            OverlayMode::Rename {
+                old_file,
+                keep_extension,
+            } => {
+                // the extension that will be added back is shown, but can not be edited
+                let kept_extension = match old_file.get_extension() {
+                    Some(extension) if *keep_extension => format!(".{}", extension),
+                    _ => String::new(),
+                };
+                (
+                    format!("Renaming '{}'", old_file.get_simple_name()),
+                    vec![Spans::from(
+                        once(Span::raw("New name: '"))
+                            .chain(get_typed_text_spans(typed_text, cursor_pos))
+                            .chain(once(Span::styled(
+                                kept_extension,
+                                Style::default().fg(tui::style::Color::DarkGray),
+                            )))
+                            .chain(once(Span::raw("'")))
+                            .collect::<Vec<_>>(),
+                    )],
+                )
+            }
+            OverlayMode::RenameOverwriteConfirm { old_file, new_path } => (
                 format!("Renaming '{}'", old_file.get_simple_name()),
-                vec![Spans::from(vec![
-                    Span::raw("New name: '"),
-                    Span::styled(typed_text, Style::default().fg(tui::style::Color::Blue)),
-                    Span::raw("'"),
-                ])],
+                vec![Spans::from(vec![Span::raw(format!(
+                    "'{}' already exists and would be replaced. If you want to cancel, {}. Otherwise, {} to replace it",
+                    new_path.file_name().unwrap_or_default().to_string_lossy(),
+                    confirm_hint(config, "normal_mode"),
+                    confirm_hint(config, "select")
+                ))])],
+            ),
+            OverlayMode::TrashConfirm { files } => (
+                match files.as_slice() {
+                    [file] => format!("Trashing '{}'", file.get_simple_name()),
+                    _ => format!("Trashing {} items", files.len()),
+                },
+                vec![Spans::from(vec![Span::raw(format!(
+                    "If you want to cancel, {}. Otherwise, {} to move to the trash",
+                    confirm_hint(config, "normal_mode"),
+                    confirm_hint(config, "select")
+                ))])],
             ),
+            OverlayMode::ApplyMarksConfirm => {
+                let count_marks = |mark_type| {
+                    app_state
+                        .marked_files
+                        .values()
+                        .filter(|marked_type| **marked_type == mark_type)
+                        .count()
+                };
+                (
+                    String::from("Applying the marks"),
+                    vec![Spans::from(vec![Span::raw(format!(
+                        "{} item(s) would be deleted, {} moved and {} copied. If you want to cancel, {}. Otherwise, {} to confirm",
+                        count_marks(MarkType::Delete),
+                        count_marks(MarkType::Cut),
+                        count_marks(MarkType::Copy),
+                        confirm_hint(config, "normal_mode"),
+                        confirm_hint(config, "select")
+                    ))])],
+                )
+            }
             OverlayMode::DeleteInstantlyConfirm { file } => (
                 format!("Deleting '{}'", file.get_simple_name()),
                 vec![Spans::from(vec![Span::raw(format!(
-                    "If you want to cancel, press '{}'. Otherwise, press '{}' to confirm",
-                    config
-                        .global_key_bindings
-                        .find_key_by_action_name("normal_mode")
-                        .expect("No 'normal_mode' action key selected for global_key_bindings"),
-                    config
-                        .text_input_mode_key_bindings
-                        .find_key_by_action_name("select")
-                        .expect("No 'select' action key selected for text_input_key_bindings")
+                    "If you want to cancel, {}. Otherwise, {} to confirm",
+                    confirm_hint(config, "normal_mode"),
+                    confirm_hint(config, "select")
                 ))])],
             ),
+            OverlayMode::OpenLargeFileConfirm { file, size, .. } => (
+                format!("Opening '{}'", file.get_simple_name()),
+                vec![Spans::from(vec![Span::raw(format!(
+                    "The file is {} large, so opening it might take a while. If you want to cancel, {}. Otherwise, {} to open it",
+                    format_size(*size),
+                    confirm_hint(config, "normal_mode"),
+                    confirm_hint(config, "select")
+                ))])],
+            ),
+            OverlayMode::ExtractOverwriteConfirm {
+                file, destination, ..
+            } => (
+                format!("Extracting '{}'", file.get_simple_name()),
+                vec![Spans::from(vec![Span::raw(format!(
+                    "'{}' already exists, so the files in it with the same names as in the archive would be overwritten. If you want to cancel, {}. Otherwise, {} to extract anyway",
+                    destination.to_string_lossy(),
+                    confirm_hint(config, "normal_mode"),
+                    confirm_hint(config, "select")
+                ))])],
+            ),
+            OverlayMode::ReloadConfigConfirm => (
+                String::from("Reloading the config file"),
+                vec![Spans::from(vec![Span::raw(format!(
+                    "If you want to keep the current settings, {}. Otherwise, {} to reload the config file",
+                    confirm_hint(config, "normal_mode"),
+                    confirm_hint(config, "select")
+                ))])],
+            ),
+            OverlayMode::GoToPath => (
+                String::from("Going to a directory"),
+                vec![Spans::from(
+                    once(Span::raw("Path: '"))
+                        .chain(get_typed_text_spans(typed_text, cursor_pos))
+                        .chain(once(Span::raw("'")))
+                        .collect::<Vec<_>>(),
+                )],
+            ),
+            OverlayMode::CopyRelativePath { file } => (
+                format!("Copying the path of {}", file.get_simple_name()),
+                vec![Spans::from(
+                    once(Span::raw("Relative to: '"))
+                        .chain(get_typed_text_spans(typed_text, cursor_pos))
+                        .chain(once(Span::raw("'")))
+                        .collect::<Vec<_>>(),
+                )],
+            ),
+            OverlayMode::CommandMenu => (
+                String::from("Running a command"),
+                config
+                    .commands
+                    .keys()
+                    .enumerate()
+                    .map(|(i, name)| Spans::from(format!("{}. {}", i + 1, name)))
+                    .chain(once(Spans::from("")))
+                    .chain(once(Spans::from(
+                        once(Span::raw("Number or name: '"))
+                            .chain(get_typed_text_spans(typed_text, cursor_pos))
+                            .chain(once(Span::raw("'")))
+                            .collect::<Vec<_>>(),
+                    )))
+                    .collect(),
+            ),
+            OverlayMode::JumpToDir => {
+                let matches = app_state
+                    .visited_dirs
+                    .get_matches(typed_text.trim(), JUMP_SHOWN_DIRS);
+                (
+                    String::from("Jumping to a visited directory"),
+                    once(Spans::from(
+                        once(Span::raw("Directory: '"))
+                            .chain(get_typed_text_spans(typed_text, cursor_pos))
+                            .chain(once(Span::raw("'")))
+                            .collect::<Vec<_>>(),
+                    ))
+                    .chain(once(Spans::from("")))
+                    // the first one is where selecting goes
+                    .chain(matches.iter().enumerate().map(|(i, dir)| {
+                        let style = if i == 0 {
+                            Style::default().add_modifier(Modifier::BOLD)
+                        } else {
+                            Style::default()
+                        };
+                        Spans::from(Span::styled(
+                            display_path(dir, app_state.path_display, config.path_display_root.as_deref()),
+                            style,
+                        ))
+                    }))
+                    .collect(),
+                )
+            }
+            OverlayMode::CopyToBookmark { paths } => (
+                format!("Copying {} item(s) to a bookmark", paths.len()),
+                config
+                    .bookmarks
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (name, path))| Spans::from(format!("{}. {} ({})", i + 1, name, path)))
+                    .chain(once(Spans::from("")))
+                    .chain(once(Spans::from(
+                        once(Span::raw("Number or name: '"))
+                            .chain(get_typed_text_spans(typed_text, cursor_pos))
+                            .chain(once(Span::raw("'")))
+                            .collect::<Vec<_>>(),
+                    )))
+                    .collect(),
+            ),
+            OverlayMode::DiskUsage => (
+                String::from("Disk usage"),
+                get_disk_usage_lines(app_state.disk_usage.as_ref())
+                    .into_iter()
+                    .chain(once(Spans::from("")))
+                    .chain(once(Spans::from(
+                        once(Span::raw("Number to go to: '"))
+                            .chain(get_typed_text_spans(typed_text, cursor_pos))
+                            .chain(once(Span::raw("'")))
+                            .collect::<Vec<_>>(),
+                    )))
+                    .collect(),
+            ),
+            OverlayMode::Registers => (
+                String::from("Registers"),
+                get_register_lines(&app_state.registers)
+                    .into_iter()
+                    .chain(once(Spans::from("")))
+                    .chain(once(Spans::from(format!(
+                        "Press a key to use that register for the next yank, cut or paste, or {} for the unnamed one",
+                        confirm_hint(config, "select")
+                    ))))
+                    .collect(),
+            ),
+            OverlayMode::SubstituteRename { paths, is_regex } => (
+                format!("Renaming {} item(s)", paths.len()),
+                get_substitute_rename_lines(paths, *is_regex, typed_text, cursor_pos, app_state, config),
+            ),
+            OverlayMode::Archive { paths } => (
+                format!("Archiving {} item(s)", paths.len()),
+                vec![
+                    Spans::from(format!(
+                        "The archive is a {} file unless the name ends with another archive extension",
+                        config.archive_format.get_extension()
+                    )),
+                    Spans::from(
+                        once(Span::raw("Name: '"))
+                            .chain(get_typed_text_spans(typed_text, cursor_pos))
+                            .chain(once(Span::raw("'")))
+                            .collect::<Vec<_>>(),
+                    ),
+                ],
+            ),
             OverlayMode::CreateDirectory => (
                 String::from("Creating a new directory"),
-                vec![Spans::from(vec![
-                    Span::raw("Name: '"),
-                    Span::styled(typed_text, Style::default().fg(tui::style::Color::Blue)),
-                    Span::raw("'"),
-                ])],
+                vec![Spans::from(
+                    once(Span::raw("Name: '"))
+                        .chain(get_typed_text_spans(typed_text, cursor_pos))
+                        .chain(once(Span::raw("'")))
+                        .collect::<Vec<_>>(),
+                )],
             ),
-            OverlayMode::CreateFile => (
-                String::from("Creating a new file"),
-                vec![Spans::from(vec![
-                    Span::raw("Name: '"),
-                    Span::styled(typed_text, Style::default().fg(tui::style::Color::Blue)),
-                    Span::raw("'"),
-                ])],
+            OverlayMode::CreateFile { from_clipboard } => (
+                String::from(if *from_clipboard {
+                    "Creating a new file from the clipboard"
+                } else {
+                    "Creating a new file"
+                }),
+                vec![Spans::from(
+                    once(Span::raw("Name: '"))
+                        .chain(get_typed_text_spans(typed_text, cursor_pos))
+                        .chain(once(Span::raw("'")))
+                        .collect::<Vec<_>>(),
+                )],
             ),
         };
-        let block = Block::default().title(title).borders(Borders::ALL);
+        let block = config.get_block().title(title);
+        // the bars only line up if they are on the left
+        let alignment = match self {
+            OverlayMode::DiskUsage
+            | OverlayMode::SubstituteRename { .. }
+            | OverlayMode::JumpToDir => tui::layout::Alignment::Left,
+            _ => tui::layout::Alignment::Center,
+        };
 
         Paragraph::new(spans)
             .block(block)
-            .alignment(tui::layout::Alignment::Center)
+            .alignment(alignment)
             .wrap(Wrap { trim: false })
     }
 }
 
+/// Renames the file and reports an error if that did not work
+fn rename_file(v: ActionData, old_file: &FileTreeNode, new_path: &Path) -> ActionResult {
+    v.app_state.get_mut().invalidate_listing();
+    match rename_path(
+        v.filesystem,
+        old_file.get_path_buf(),
+        new_path,
+        v.app_state.case_insensitive_names,
+    ) {
+        Ok(_) => ActionResult::Valid,
+        Err(err) => ActionResult::Invalid(format!("Error while renaming: {}", err)),
+    }
+}
+
+/// A line for each register with the names of its files
+fn get_register_lines(registers: &BTreeMap<char, Register>) -> Vec<Spans<'static>> {
+    if registers.is_empty() {
+        return vec![Spans::from("All the registers are empty")];
+    }
+    registers
+        .iter()
+        .map(|(register, contents)| {
+            let names: Vec<_> = contents
+                .files
+                .iter()
+                .map(|file| file.get_simple_name().as_str())
+                .collect();
+            Spans::from(format!(
+                "'{}' ({}): {}",
+                register,
+                if contents.is_cut { "cut" } else { "yanked" },
+                names.join(", ")
+            ))
+        })
+        .collect()
+}
+
+/// How to run `action` from a prompt, e.g. "press 'Enter'", or the name of the action if the config has no key for it
+fn confirm_hint(config: &AppSettings, action: &str) -> String {
+    match config
+        .text_input_mode_key_bindings
+        .find_key_by_action_name(action)
+        .or_else(|| config.global_key_bindings.find_key_by_action_name(action))
+    {
+        Some(key) => format!("press '{}'", key),
+        None => format!("use {}", action),
+    }
+}
+
+/// The prompt for the pattern, followed by the names that the files would get
+fn get_substitute_rename_lines(
+    paths: &[PathBuf],
+    is_regex: bool,
+    typed_text: String,
+    cursor_pos: usize,
+    app_state: &AppState,
+    config: &AppSettings,
+) -> Vec<Spans<'static>> {
+    let toggle_hint = match config
+        .text_input_mode_key_bindings
+        .find_key_by_action_name("toggle_regex")
+    {
+        Some(key) => format!(" ('{}' toggles it)", key),
+        None => String::new(),
+    };
+    let mut lines = vec![
+        Spans::from(format!(
+            "Type find/replace. Regex: {}{}",
+            if is_regex { "on" } else { "off" },
+            toggle_hint
+        )),
+        Spans::from(
+            once(Span::raw("Pattern: '"))
+                .chain(get_typed_text_spans(typed_text.clone(), cursor_pos))
+                .chain(once(Span::raw("'")))
+                .collect::<Vec<_>>(),
+        ),
+        Spans::from(""),
+    ];
+
+    let error_style = Style::default().fg(tui::style::Color::Red);
+    let renames = match get_substituted_names(paths, &typed_text, is_regex) {
+        Ok(renames) => renames,
+        Err(err) => {
+            lines.push(Spans::from(Span::styled(err, error_style)));
+            return lines;
+        }
+    };
+    if let Some(conflict) = find_conflict(&renames, app_state.case_insensitive_names) {
+        lines.push(Spans::from(Span::styled(conflict, error_style)));
+    }
+    let unchanged_style = Style::default().fg(tui::style::Color::DarkGray);
+    lines.extend(renames.iter().map(|(old_path, new_path)| {
+        let old_name = old_path.file_name().unwrap_or_default().to_string_lossy();
+        if old_path == new_path {
+            Spans::from(Span::styled(old_name.into_owned(), unchanged_style))
+        } else {
+            let new_name = new_path.file_name().unwrap_or_default().to_string_lossy();
+            Spans::from(format!("{} -> {}", old_name, new_name))
+        }
+    }));
+    lines
+}
+
+/// Lists the largest entries, numbered, with bars proportional to their sizes
+fn get_disk_usage_lines(disk_usage: Option<&DiskUsage>) -> Vec<Spans<'static>> {
+    const BAR_WIDTH: usize = 20;
+
+    let disk_usage = match disk_usage {
+        Some(disk_usage) => disk_usage,
+        None => return vec![],
+    };
+    let entries = disk_usage.get_sorted_entries();
+    let largest_size = entries.first().map_or(0, |(_, size)| *size);
+
+    let mut lines: Vec<_> = entries
+        .iter()
+        .take(DISK_USAGE_SHOWN_ENTRIES)
+        .enumerate()
+        .map(|(i, (item, size))| {
+            let bar_length = if largest_size == 0 {
+                0
+            } else {
+                (*size as f64 / largest_size as f64 * BAR_WIDTH as f64).round() as usize
+            };
+            Spans::from(vec![
+                Span::raw(format!("{:>2}. ", i + 1)),
+                Span::styled(
+                    format!("{:<width$}", "█".repeat(bar_length), width = BAR_WIDTH),
+                    Style::default().fg(tui::style::Color::Blue),
+                ),
+                Span::raw(format!(
+                    " {:>7} {}",
+                    format_size(*size),
+                    item.get_simple_name()
+                )),
+            ])
+        })
+        .collect();
+
+    if !disk_usage.is_finished() {
+        lines.push(Spans::from(format!(
+            "{} Calculating...",
+            disk_usage.get_spinner_frame()
+        )));
+    } else if disk_usage.is_limit_reached() {
+        lines.push(Spans::from(format!(
+            "Stopped after {} files, so the sizes are incomplete",
+            MAX_DISK_USAGE_ENTRIES
+        )));
+    }
+    lines
+}
+
+/// Shows the typed text with the character under the cursor highlighted
+fn get_typed_text_spans(typed_text: String, cursor_pos: usize) -> Vec<Span<'static>> {
+    let text_style = Style::default().fg(tui::style::Color::Blue);
+    let (before_cursor, after_cursor) = typed_text.split_at(cursor_pos);
+    let mut after_cursor_chars = after_cursor.chars();
+    // at the end of the text, highlight an empty space instead
+    let under_cursor = after_cursor_chars
+        .next()
+        .map_or(String::from(" "), String::from);
+
+    vec![
+        Span::styled(before_cursor.to_string(), text_style),
+        Span::styled(under_cursor, text_style.add_modifier(Modifier::REVERSED)),
+        Span::styled(after_cursor_chars.as_str().to_string(), text_style),
+    ]
+}
+
 impl TextInput {
     pub fn represent_text_line(&self, text_line: &str) -> String {
         match &self {
             TextInput::Search => format!("/{}", text_line),
             TextInput::RunCommand => format!(":{}", text_line),
+            TextInput::PreviewSearch => format!("preview/{}", text_line),
         }
     }
 }
@@ -298,13 +1277,217 @@ pub fn cmp_by_dir_and_path(a: &FileTreeNode, b: &FileTreeNode) -> Ordering {
         a.get_path_buf().cmp(b.get_path_buf())
     }
 }
-pub fn get_file_text_preview(f: &FileTreeNode) -> Option<String> {
+/// Sorts the items by the sort key, with the directories grouped before or after the files unless they are mixed.
+/// Sizes and modification times are sorted from the largest / newest, unless reversed
+pub fn sort_dir_items(
+    dir_items: &mut [FileTreeNode],
+    sort_key: &SortKey,
+    is_reversed: bool,
+    group_directories: GroupDirectories,
+) {
+    match sort_key {
+        SortKey::Name => dir_items.sort_by(|a, b| a.get_path_buf().cmp(b.get_path_buf())),
+        // cache the keys so that every file is only queried once
+        SortKey::Size => dir_items.sort_by_cached_key(|item| {
+            (
+                Reverse(item.get_size().unwrap_or(0)),
+                item.get_path_buf().clone(),
+            )
+        }),
+        SortKey::Modified => dir_items.sort_by_cached_key(|item| {
+            (
+                Reverse(item.get_modified().ok()),
+                item.get_path_buf().clone(),
+            )
+        }),
+        SortKey::Extension => dir_items.sort_by_cached_key(|item| {
+            (
+                item.get_path_buf()
+                    .extension()
+                    .map(|extension| extension.to_string_lossy().to_lowercase()),
+                item.get_path_buf().clone(),
+            )
+        }),
+    }
+
+    if is_reversed {
+        dir_items.reverse();
+    }
+    // the sort is stable, so this groups the directories without changing the order within each group
+    match group_directories {
+        GroupDirectories::First => dir_items.sort_by_cached_key(|item| !item.is_dir()),
+        GroupDirectories::Last => dir_items.sort_by_cached_key(|item| item.is_dir()),
+        GroupDirectories::Mixed => {}
+    }
+}
+/// Keeps the items that match the query, the best matches first.
+/// The sort is stable, so the items with the same score stay in the order that they were in
+pub fn sort_by_score(
+    dir_items: Vec<FileTreeNode>,
+    query: &str,
+    search_match: SearchMatch,
+    base_dir: &Path,
+) -> Vec<FileTreeNode> {
+    let mut scored_items: Vec<_> = dir_items
+        .into_iter()
+        .filter_map(|item| Some((item.compute_score(query, search_match, base_dir)?, item)))
+        .collect();
+    scored_items.sort_by_key(|(score, _)| Reverse(*score));
+    scored_items.into_iter().map(|(_, item)| item).collect()
+}
+
+pub struct TextPreview {
+    pub text: String,
+    // whether the file is longer than what was read
+    pub is_truncated: bool,
+}
+
+/// Reads up to `fetch_length` bytes from the start of the file
+pub fn get_file_text_preview(f: &FileTreeNode, fetch_length: usize) -> Option<TextPreview> {
     // let extension = f.get_path_buf().extension().unwrap_or(OsStr::new(""));
 
-    let mut buffer = [0; PREVIEW_TEXT_FETCH_LENGTH];
+    let mut buffer = Vec::with_capacity(fetch_length);
     let opened_file = File::open(f.get_path_buf()).ok();
 
     opened_file
-        .and_then(|mut opened_file| opened_file.read(&mut buffer).ok())
-        .map(|n| String::from_utf8_lossy(&buffer[..n]).into_owned())
+        .and_then(|opened_file| {
+            // a single read can return less than asked for, even before the end of the file
+            opened_file
+                .take(fetch_length as u64)
+                .read_to_end(&mut buffer)
+                .ok()
+        })
+        .map(|n| TextPreview {
+            text: String::from_utf8_lossy(&buffer[..n]).into_owned(),
+            is_truncated: f.get_size().is_ok_and(|size| size > n as u64),
+        })
+}
+
+/// A search in the text of the previewed file. `n` and `N` move between the matches
+pub struct PreviewSearch {
+    pub path: PathBuf,
+    pub query: String,
+    pub matches: Vec<PreviewMatch>,
+    pub current_match: usize,
+    // the text that was searched, which is shown instead of reading the file again, since the matches are only
+    // about this text
+    pub text_preview: TextPreview,
+}
+
+/// Where a match is, as byte offsets into its line
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PreviewMatch {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Finds all the occurrences of the query in the text, line by line.
+/// Like the directory search, it ignores the case unless the query has capital letters in it
+pub fn find_in_text(text: &str, query: &str) -> Vec<PreviewMatch> {
+    if query.is_empty() {
+        return vec![];
+    }
+    let is_case_sensitive = query.chars().any(char::is_uppercase);
+    let regex = match RegexBuilder::new(&regex::escape(query))
+        .case_insensitive(!is_case_sensitive)
+        .build()
+    {
+        Ok(regex) => regex,
+        Err(_) => return vec![],
+    };
+    text.lines()
+        .enumerate()
+        .flat_map(|(line, line_text)| {
+            regex.find_iter(line_text).map(move |found| PreviewMatch {
+                line,
+                start: found.start(),
+                end: found.end(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{
+        compile_time_settings::DEFAULT_CONFIG,
+        directory_tree::FileTreeNode,
+        helper_types::{AppSettings, SearchMatch},
+    };
+
+    use super::{confirm_hint, sort_by_score};
+
+    fn get_paths(dir_items: &[FileTreeNode]) -> Vec<&Path> {
+        dir_items
+            .iter()
+            .map(|item| item.get_path_buf().as_path())
+            .collect()
+    }
+
+    #[test]
+    fn best_match_comes_first() {
+        let base_dir = Path::new("/fphile-test-root");
+        let dir_items = vec![
+            FileTreeNode::new(PathBuf::from("/fphile-test-root/r_e_a_d_m_e")),
+            FileTreeNode::new(PathBuf::from("/fphile-test-root/other")),
+            FileTreeNode::new(PathBuf::from("/fphile-test-root/readme")),
+        ];
+
+        let sorted_items = sort_by_score(dir_items, "readme", SearchMatch::Name, base_dir);
+
+        assert_eq!(
+            get_paths(&sorted_items),
+            vec![
+                Path::new("/fphile-test-root/readme"),
+                Path::new("/fphile-test-root/r_e_a_d_m_e")
+            ]
+        );
+    }
+
+    #[test]
+    fn tied_scores_keep_their_order() {
+        let base_dir = Path::new("/fphile-test-root");
+        // the same names in different directories match exactly as well
+        let paths = [
+            "/fphile-test-root/b/name",
+            "/fphile-test-root/c/name",
+            "/fphile-test-root/a/name",
+        ];
+        let dir_items = paths
+            .iter()
+            .map(|path| FileTreeNode::new(PathBuf::from(path)))
+            .collect();
+
+        let sorted_items = sort_by_score(dir_items, "name", SearchMatch::Name, base_dir);
+
+        assert_eq!(
+            get_paths(&sorted_items),
+            paths.iter().map(Path::new).collect::<Vec<_>>()
+        );
+
+        let dir_items = paths
+            .iter()
+            .map(|path| FileTreeNode::new(PathBuf::from(path)))
+            .collect();
+        let sorted_items = sort_by_score(dir_items, "", SearchMatch::Name, base_dir);
+        assert_eq!(
+            get_paths(&sorted_items),
+            paths.iter().map(Path::new).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn confirm_hint_names_the_action_without_a_key() {
+        let mut config: AppSettings =
+            toml::from_str(DEFAULT_CONFIG).expect("The default config should be valid");
+        config
+            .global_key_bindings
+            .retain(|_, action_name| action_name != "normal_mode");
+
+        assert_eq!(confirm_hint(&config, "normal_mode"), "use normal_mode");
+        assert!(confirm_hint(&config, "select").starts_with("press '"));
+    }
 }
@@ -1,6 +1,8 @@
 use std::fs;
 
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::time::SystemTime;
 
 use crate::directory_tree::FileTreeNode;
 
@@ -11,3 +13,93 @@ pub fn delete_file_tree_node(file_tree_node: &FileTreeNode) -> Result<()> {
         fs::remove_file(file_tree_node.get_path_buf())
     }
 }
+
+/// Moves the file or directory to the trash, which, unlike deleting, can be undone
+pub fn trash_file_tree_node(
+    file_tree_node: &FileTreeNode,
+) -> std::result::Result<(), trash::Error> {
+    trash::delete(file_tree_node.get_path_buf())
+}
+
+/// Sets the modification time to now, creating an empty file if there is nothing at the path, like `touch`
+pub fn touch_path(path: &Path) -> Result<()> {
+    // directories can not be opened for writing, but their times can still be set through a read-only handle
+    let file = if path.is_dir() {
+        fs::File::open(path)?
+    } else {
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?
+    };
+    file.set_modified(SystemTime::now())
+}
+
+/// Copies a file or a whole directory. Symlinks are copied as links rather than followed
+pub fn copy_path(from: &Path, to: &Path) -> Result<()> {
+    check_destination(from, to)?;
+    copy_path_unchecked(from, to)
+}
+
+/// Moves a file or a directory, copying it if it is on a different filesystem
+pub fn move_path(from: &Path, to: &Path) -> Result<()> {
+    check_destination(from, to)?;
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            copy_path_unchecked(from, to)?;
+            if fs::symlink_metadata(from)?.is_dir() {
+                fs::remove_dir_all(from)
+            } else {
+                fs::remove_file(from)
+            }
+        }
+        result => result,
+    }
+}
+
+fn check_destination(from: &Path, to: &Path) -> Result<()> {
+    // never overwrite anything
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        ));
+    }
+    if to.starts_with(from) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("can not put {} inside itself", from.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn copy_path_unchecked(from: &Path, to: &Path) -> Result<()> {
+    let file_type = fs::symlink_metadata(from)?.file_type();
+    if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_path_unchecked(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else if file_type.is_symlink() {
+        copy_symlink(&fs::read_link(from)?, to)
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(target: &Path, to: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, to)
+}
+
+#[cfg(windows)]
+fn copy_symlink(target: &Path, to: &Path) -> Result<()> {
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
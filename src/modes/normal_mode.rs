@@ -1,46 +1,77 @@
-use tui::widgets::List;
+use tui::{style::Style, widgets::List};
 
 use crate::{
+    actions::get_visual_range,
     directory_tree::{get_file_cursor_index, FileTreeNode},
-    helper_types::{AppState, StyleSet, TrackedModifiable},
+    helper_types::{AppSettings, AppState, LineNumbers, StyleSet, TrackedModifiable},
 };
 
 pub fn get_default_left_ui<'a>(
     app_state: &mut TrackedModifiable<AppState>,
-    dir_items: &'a Vec<FileTreeNode>,
-    min_distance_from_cursor_to_bottom: usize,
+    dir_items: &'a [FileTreeNode],
+    config: &AppSettings,
     cursor_styles: StyleSet,
     default_styles: StyleSet,
     height_of_list_available: usize,
+    width_of_list_available: usize,
 ) -> List<'a> {
     let file_cursor_highlight_index = get_file_cursor_index(&app_state.selected_file, dir_items);
 
     // reset the selected file to the first element if it was not found
     let file_cursor_highlight_index = file_cursor_highlight_index.unwrap_or_else(|| {
-        app_state.get_mut().selected_file = dir_items.get(0).map(|e| e.to_owned());
+        // in an empty directory, it is already None. Writing it anyway would force a redraw on every frame
+        if app_state.selected_file.as_ref() != dir_items.first() {
+            app_state.get_mut().selected_file = dir_items.first().map(|e| e.to_owned());
+        }
         0
     });
 
-    // how many list elements to skip to give the appearance of scrolling
-    let num_to_skip =
-            // Do not do anything if it all fits in on one screen
-            if dir_items.len() <= height_of_list_available {
-                0
-
-            // Do not do anything if it can all be seen on one screen
-            } else if min_distance_from_cursor_to_bottom + file_cursor_highlight_index < height_of_list_available {
-                0
-            // if the viewport is full and the cursor is close to the bottom, but there are still concealed items later on in the list
-            } else if dir_items.len() > file_cursor_highlight_index + min_distance_from_cursor_to_bottom {
-                file_cursor_highlight_index + min_distance_from_cursor_to_bottom - height_of_list_available
-            // show the last page
-            } else {
-                dir_items.len() - height_of_list_available
-            };
+    // there is no room for any rows (e.g. in a tiny terminal), and the scrolling logic below assumes at least one
+    if height_of_list_available == 0 {
+        return List::new(vec![]);
+    }
+
+    // if the list is shorter than the distance (e.g. after the terminal was resized), the cursor would be scrolled off the screen
+    let min_distance_from_cursor_to_bottom = config
+        .min_distance_from_cursor_to_bottom
+        .clamp(1, height_of_list_available);
+
+    let num_to_skip = get_scroll_offset(
+        dir_items.len(),
+        file_cursor_highlight_index,
+        height_of_list_available,
+        min_distance_from_cursor_to_bottom,
+    );
+
+    // let the actions know what is on the screen. Only write it if it changed, since writing forces a redraw
+    if app_state.scroll_offset != num_to_skip || app_state.visible_rows != height_of_list_available
+    {
+        app_state.get_mut().scroll_offset = num_to_skip;
+        app_state.get_mut().visible_rows = height_of_list_available;
+    }
+
+    if config.show_children_num {
+        let visible_items_end = dir_items.len().min(num_to_skip + height_of_list_available);
+        AppState::count_children(app_state, &dir_items[num_to_skip..visible_items_end]);
+    }
+
+    // the visual selection is shown with a different background until it is marked
+    let visual_range = get_visual_range(app_state, dir_items);
+    let visual_background = Style::default().bg(tui::style::Color::DarkGray);
+    let visual_styles = StyleSet {
+        file: default_styles.file.patch(visual_background),
+        dir: default_styles.dir.patch(visual_background),
+        executable: default_styles.executable.patch(visual_background),
+    };
+
+    // the distances from the cursor are never longer than the positions, so this fits both
+    let line_number_width = dir_items.len().to_string().len();
 
     let dir_items: Vec<_> = dir_items
         .iter()
         .enumerate()
+        // only the rows that fit on the screen, since the detailed view reads the metadata of every row it shows
+        .take(num_to_skip + height_of_list_available)
         .filter_map(|el| {
             let el_index = el.0;
             let el = el.1;
@@ -49,15 +80,124 @@ pub fn get_default_left_ui<'a>(
                 return None;
             }
 
-            Some(el.get_tui_representation(
-                &cursor_styles,
-                &default_styles,
-                el_index == file_cursor_highlight_index,
-                &app_state.marked_files,
-                &app_state.mark_type,
-            ))
+            let is_in_visual_range = visual_range
+                .as_ref()
+                .is_some_and(|visual_range| visual_range.contains(&el_index));
+            Some(
+                el.get_tui_representation(
+                    config,
+                    if el_index == file_cursor_highlight_index {
+                        &cursor_styles
+                    } else if is_in_visual_range {
+                        &visual_styles
+                    } else {
+                        &default_styles
+                    },
+                    app_state,
+                    width_of_list_available,
+                    get_line_number(
+                        app_state.line_numbers,
+                        el_index,
+                        file_cursor_highlight_index,
+                    )
+                    .map(|line_number| {
+                        format!("{:>width$} ", line_number, width = line_number_width)
+                    }),
+                ),
+            )
         })
         .collect();
 
     List::new(dir_items)
 }
+
+/// The number shown before the element at `index`. The positions count from 1 like the count of `go_to_or_go_to_bottom`
+fn get_line_number(
+    line_numbers: LineNumbers,
+    index: usize,
+    file_cursor_highlight_index: usize,
+) -> Option<usize> {
+    match line_numbers {
+        LineNumbers::Off => None,
+        LineNumbers::Absolute => Some(index + 1),
+        LineNumbers::Hybrid if index == file_cursor_highlight_index => Some(index + 1),
+        LineNumbers::Relative | LineNumbers::Hybrid => {
+            Some(index.abs_diff(file_cursor_highlight_index))
+        }
+    }
+}
+
+/// How many list elements to skip to give the appearance of scrolling.
+/// `height_of_list_available` and `min_distance_from_cursor_to_bottom` must be at least 1
+pub(crate) fn get_scroll_offset(
+    items_num: usize,
+    file_cursor_highlight_index: usize,
+    height_of_list_available: usize,
+    min_distance_from_cursor_to_bottom: usize,
+) -> usize {
+    // Do not do anything if it all fits in on one screen or if it can all be seen on one screen
+    if items_num <= height_of_list_available
+        || min_distance_from_cursor_to_bottom + file_cursor_highlight_index
+            < height_of_list_available
+    {
+        0
+    // if the viewport is full and the cursor is close to the bottom, but there are still concealed items later on in the list
+    } else if items_num > file_cursor_highlight_index + min_distance_from_cursor_to_bottom {
+        file_cursor_highlight_index + min_distance_from_cursor_to_bottom - height_of_list_available
+    // show the last page
+    } else {
+        items_num - height_of_list_available
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tui::style::Style;
+
+    use crate::{directory_tree::FileTreeNode, helper_types::StyleSet, test_util::TestApp};
+
+    use super::{get_default_left_ui, get_scroll_offset};
+
+    #[test]
+    fn scroll_offset_keeps_the_cursor_away_from_the_bottom() {
+        // everything fits
+        assert_eq!(get_scroll_offset(5, 4, 5, 2), 0);
+        // the cursor is far enough from the bottom
+        assert_eq!(get_scroll_offset(20, 2, 5, 2), 0);
+        assert_eq!(get_scroll_offset(20, 10, 5, 2), 7);
+        // the last page does not scroll past the end
+        assert_eq!(get_scroll_offset(20, 19, 5, 2), 15);
+    }
+
+    #[test]
+    fn drawing_stores_the_scroll_offset() {
+        let root = Path::new("/fphile-test-root");
+        let mut app = TestApp::new(root);
+        app.config.min_distance_from_cursor_to_bottom = 2;
+        let dir_items: Vec<_> = (0..20)
+            .map(|i| FileTreeNode::new(root.join(format!("file{:02}", i))))
+            .collect();
+        app.select(&root.join("file15"));
+        let styles = StyleSet {
+            file: Style::default(),
+            dir: Style::default(),
+            executable: Style::default(),
+        };
+
+        get_default_left_ui(
+            &mut app.app_state,
+            &dir_items,
+            &app.config,
+            styles.clone(),
+            styles,
+            5,
+            40,
+        );
+
+        // the rows 12 to 16 are visible, which keeps the cursor 2 rows away from the bottom
+        assert_eq!(app.app_state.scroll_offset, 12);
+        assert_eq!(app.app_state.visible_rows, 5);
+    }
+}
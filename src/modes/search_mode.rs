@@ -2,28 +2,56 @@ use tui::widgets::List;
 
 use crate::{
     directory_tree::FileTreeNode,
-    helper_types::{AppState, StyleSet, TrackedModifiable},
+    helper_types::{AppSettings, AppState, StyleSet, TrackedModifiable},
+    modes::normal_mode::get_scroll_offset,
 };
 
 pub fn get_search_mode_left_ui<'a>(
     app_state: &mut TrackedModifiable<AppState>,
-    dir_items: &'a Vec<FileTreeNode>,
+    dir_items: &'a [FileTreeNode],
+    config: &AppSettings,
     cursor_styles: &StyleSet,
     default_styles: &StyleSet,
+    height_of_list_available: usize,
+    width_of_list_available: usize,
 ) -> List<'a> {
+    if height_of_list_available == 0 {
+        return List::new(vec![]);
+    }
+    let search_cursor = app_state.get_search_cursor(dir_items.len());
+    // like in the normal mode, so that the last match can be scrolled to
+    let num_to_skip = get_scroll_offset(
+        dir_items.len(),
+        search_cursor,
+        height_of_list_available,
+        config
+            .min_distance_from_cursor_to_bottom
+            .clamp(1, height_of_list_available),
+    );
+    let dir_items = &dir_items[num_to_skip..];
+    let dir_items = &dir_items[..dir_items.len().min(height_of_list_available)];
+
+    if config.show_children_num {
+        AppState::count_children(app_state, dir_items);
+    }
+
     let dir_items: Vec<_> = dir_items
         .iter()
         .enumerate()
-        .filter_map(|el| {
-            let el_index = el.0;
+        .map(|el| {
+            let el_index = num_to_skip + el.0;
             let el = el.1;
-            Some(el.get_tui_representation(
-                &cursor_styles,
-                &default_styles,
-                el_index == 0,
-                &app_state.marked_files,
-                &app_state.mark_type,
-            ))
+            el.get_tui_representation(
+                config,
+                if el_index == search_cursor {
+                    cursor_styles
+                } else {
+                    default_styles
+                },
+                app_state,
+                width_of_list_available,
+                None,
+            )
         })
         .collect();
 
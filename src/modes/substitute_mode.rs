@@ -0,0 +1,358 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use regex::Regex;
+
+use crate::filesystem::Filesystem;
+
+/// A rename from the old path to the new one. Both are in the same directory
+pub type Rename = (PathBuf, PathBuf);
+
+/// Applies a `find/replace` pattern to the names of the paths, replacing every match.
+/// With `is_regex`, `find` is a regular expression and `replace` can refer to its groups, like `$1`
+pub fn get_substituted_names(
+    paths: &[PathBuf],
+    pattern: &str,
+    is_regex: bool,
+) -> Result<Vec<Rename>, String> {
+    let (find, replace) = pattern
+        .split_once('/')
+        .ok_or_else(|| String::from("The pattern should look like find/replace"))?;
+    if find.is_empty() {
+        return Err(String::from("There is nothing to find"));
+    }
+    let regex = if is_regex {
+        // the parse errors point at the problem over several lines, and only the last one says what it is
+        Some(Regex::new(find).map_err(|err| {
+            let err = err.to_string();
+            let reason = err.lines().last().unwrap_or_default();
+            format!("Invalid regex: {}", reason.trim_start_matches("error: "))
+        })?)
+    } else {
+        None
+    };
+
+    paths
+        .iter()
+        .map(|path| {
+            // names that are not valid UTF-8 are left alone rather than mangled
+            let old_name = match path.file_name().and_then(|file_name| file_name.to_str()) {
+                Some(old_name) => old_name,
+                None => return Ok((path.clone(), path.clone())),
+            };
+            let new_name = match &regex {
+                Some(regex) => regex.replace_all(old_name, replace).into_owned(),
+                None => old_name.replace(find, replace),
+            };
+            check_new_name(old_name, &new_name)?;
+            Ok((path.clone(), path.with_file_name(new_name)))
+        })
+        .collect()
+}
+
+/// Pairs the paths with the names on the lines of `edited_text`, which started as one name per line
+pub fn get_edited_names(paths: &[PathBuf], edited_text: &str) -> Result<Vec<Rename>, String> {
+    let mut lines: Vec<_> = edited_text.lines().collect();
+    // some editors add an empty line at the end
+    while lines.len() > paths.len() && lines.last() == Some(&"") {
+        lines.pop();
+    }
+    if lines.len() != paths.len() {
+        return Err(format!(
+            "There were {} name(s), but {} line(s) after editing. Lines can not be added or removed",
+            paths.len(),
+            lines.len()
+        ));
+    }
+
+    paths
+        .iter()
+        .zip(lines)
+        .map(|(path, new_name)| {
+            let old_name = get_name(path);
+            // the names that are not valid UTF-8 were written with replacement characters, so they are left alone
+            if new_name == old_name {
+                return Ok((path.clone(), path.clone()));
+            }
+            check_new_name(&old_name, new_name)?;
+            Ok((path.clone(), path.with_file_name(new_name)))
+        })
+        .collect()
+}
+
+fn check_new_name(old_name: &str, new_name: &str) -> Result<(), String> {
+    if new_name.is_empty() || new_name == "." || new_name == ".." {
+        return Err(format!("'{}' would be renamed to '{}'", old_name, new_name));
+    }
+    if new_name.contains(std::path::is_separator) {
+        return Err(format!(
+            "'{}' would be renamed to '{}', which is not a valid name",
+            old_name, new_name
+        ));
+    }
+    Ok(())
+}
+
+/// Describes the first rename that would overwrite another file, if there is one.
+/// With `case_insensitive_names`, names that only differ in case count as the same
+pub fn find_conflict(renames: &[Rename], case_insensitive_names: bool) -> Option<String> {
+    let new_paths: Vec<_> = renames
+        .iter()
+        .map(|(_, new_path)| new_path.clone())
+        .collect();
+    if let Some((first, second)) = find_duplicate(&new_paths, case_insensitive_names) {
+        return Some(format!(
+            "'{}' and '{}' would both be renamed to '{}'",
+            get_name(&renames[first].0),
+            get_name(&renames[second].0),
+            get_name(&renames[second].1)
+        ));
+    }
+    // this also refuses renaming in a chain, like a -> b and b -> c, which would depend on the order
+    renames
+        .iter()
+        .find(|(old_path, new_path)| is_taken(old_path, new_path))
+        .map(|(old_path, new_path)| {
+            format!(
+                "'{}' would be renamed to '{}', which already exists",
+                get_name(old_path),
+                get_name(new_path)
+            )
+        })
+}
+
+/// The indices of the first two paths that point to the same file.
+/// With `case_insensitive_names`, names that only differ in case count as the same
+pub fn find_duplicate(paths: &[PathBuf], case_insensitive_names: bool) -> Option<(usize, usize)> {
+    let mut seen_paths: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    for (i, path) in paths.iter().enumerate() {
+        let key = if case_insensitive_names {
+            PathBuf::from(path.to_string_lossy().to_lowercase())
+        } else {
+            path.clone()
+        };
+        if let Some(first) = seen_paths.insert(key, i) {
+            return Some((first, i));
+        }
+    }
+    None
+}
+
+/// The item that is already at `path`, if there is one. With `case_insensitive_names`, this can be an item whose
+/// name only differs in case, which is the same file there
+pub fn find_existing(path: &Path, case_insensitive_names: bool) -> Option<PathBuf> {
+    if case_insensitive_names {
+        let name = get_name(path).to_lowercase();
+        let same_name = path.parent().and_then(|parent| {
+            fs::read_dir(parent)
+                .ok()?
+                .flatten()
+                .map(|entry| entry.path())
+                .find(|other_path| get_name(other_path).to_lowercase() == name)
+        });
+        if same_name.is_some() {
+            return same_name;
+        }
+    }
+    path.symlink_metadata().ok().map(|_| path.to_owned())
+}
+
+/// Whether renaming `old_path` to `new_path` would replace another file. On case-insensitive filesystems,
+/// a new name that only differs in case is found, but it is the file that is being renamed
+pub fn is_taken(old_path: &Path, new_path: &Path) -> bool {
+    old_path != new_path && new_path.symlink_metadata().is_ok() && !is_same_file(old_path, new_path)
+}
+
+#[cfg(unix)]
+fn is_same_file(first_path: &Path, second_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (
+        first_path.symlink_metadata(),
+        second_path.symlink_metadata(),
+    ) {
+        (Ok(first), Ok(second)) => first.dev() == second.dev() && first.ino() == second.ino(),
+        _ => false,
+    }
+}
+
+/// The canonical paths have the names as they are stored, whatever their case was in the given paths
+#[cfg(not(unix))]
+fn is_same_file(first_path: &Path, second_path: &Path) -> bool {
+    match (first_path.canonicalize(), second_path.canonicalize()) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => false,
+    }
+}
+
+/// Renames a file. With `case_insensitive_names`, a rename that only changes the case, e.g. "File" to "file",
+/// goes through a temporary name, since the filesystem might otherwise take it as renaming the file to itself
+pub fn rename_path(
+    filesystem: &mut dyn Filesystem,
+    from: &Path,
+    to: &Path,
+    case_insensitive_names: bool,
+) -> io::Result<()> {
+    let is_case_only_change = from != to
+        && from.parent() == to.parent()
+        && get_name(from).to_lowercase() == get_name(to).to_lowercase();
+    if !case_insensitive_names || !is_case_only_change {
+        return filesystem.rename(from, to);
+    }
+
+    let temp_path = from.with_file_name(format!(
+        ".{}.fphile-rename-{}",
+        get_name(from),
+        process::id()
+    ));
+    filesystem.rename(from, &temp_path)?;
+    if let Err(err) = filesystem.rename(&temp_path, to) {
+        // the file should not be left under the temporary name
+        let _ = filesystem.rename(&temp_path, from);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Renames the files, stopping at the first error. Returns how many were renamed.
+/// The deepest paths are renamed first, so that the paths inside a renamed directory (e.g. in an expanded one) are
+/// still where they were when they are renamed
+pub fn rename_all(
+    renames: &[Rename],
+    filesystem: &mut dyn Filesystem,
+    case_insensitive_names: bool,
+) -> Result<usize, String> {
+    let mut renames: Vec<_> = renames.iter().filter(|(old, new)| old != new).collect();
+    renames.sort_by_key(|(old_path, _)| Reverse(old_path.components().count()));
+    let mut num_renamed = 0;
+    for (old_path, new_path) in renames {
+        rename_path(filesystem, old_path, new_path, case_insensitive_names).map_err(|err| {
+            format!(
+                "Error while renaming '{}' after renaming {} item(s): {}",
+                get_name(old_path),
+                num_renamed,
+                err
+            )
+        })?;
+        num_renamed += 1;
+    }
+    Ok(num_renamed)
+}
+
+fn get_name(path: &Path) -> String {
+    path.file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{test_util::MemoryFilesystem, util::PrivateTempDir};
+
+    use super::*;
+
+    const ROOT: &str = "/fphile-test-root";
+
+    #[test]
+    fn case_only_rename_goes_through_a_temporary_name() {
+        let root = Path::new(ROOT);
+        let mut filesystem = MemoryFilesystem::new(root);
+        filesystem.add_file(&root.join("File"), b"contents");
+
+        rename_path(
+            &mut filesystem,
+            &root.join("File"),
+            &root.join("file"),
+            true,
+        )
+        .unwrap();
+
+        assert!(!filesystem.exists(&root.join("File")));
+        assert_eq!(
+            filesystem.get_contents(&root.join("file")),
+            Some(&b"contents"[..])
+        );
+        assert!(!filesystem.exists(&root.join(format!(".File.fphile-rename-{}", process::id()))));
+    }
+
+    #[test]
+    fn failed_case_only_rename_restores_the_old_name() {
+        let root = Path::new(ROOT);
+        let mut filesystem = MemoryFilesystem::new(root);
+        filesystem.add_file(&root.join("File"), b"contents");
+        filesystem.fail_renames_to(&root.join("file"));
+
+        assert!(rename_path(
+            &mut filesystem,
+            &root.join("File"),
+            &root.join("file"),
+            true
+        )
+        .is_err());
+
+        assert_eq!(
+            filesystem.get_contents(&root.join("File")),
+            Some(&b"contents"[..])
+        );
+        assert!(!filesystem.exists(&root.join(format!(".File.fphile-rename-{}", process::id()))));
+    }
+
+    #[test]
+    fn names_that_only_differ_in_case_conflict_when_case_insensitive() {
+        let root = Path::new(ROOT);
+        let renames = vec![
+            (root.join("a"), root.join("Name")),
+            (root.join("b"), root.join("name")),
+        ];
+
+        assert_eq!(
+            find_conflict(&renames, true),
+            Some(String::from("'a' and 'b' would both be renamed to 'name'"))
+        );
+        assert_eq!(find_conflict(&renames, false), None);
+    }
+
+    #[test]
+    fn existing_name_is_found_whatever_its_case() {
+        let temp_dir = PrivateTempDir::new("fphile-test-find-existing").unwrap();
+        let dir = temp_dir.get_path();
+        temp_dir.create_file("File", b"").unwrap();
+
+        let found_insensitive = find_existing(&dir.join("file"), true);
+        let found_sensitive = find_existing(&dir.join("file"), false);
+        let found_exact = find_existing(&dir.join("File"), false);
+
+        assert_eq!(found_insensitive, Some(dir.join("File")));
+        assert_eq!(found_exact, Some(dir.join("File")));
+        // only a case-insensitive filesystem finds it anyway
+        if found_sensitive.is_some() {
+            assert_eq!(found_sensitive, Some(dir.join("file")));
+        }
+    }
+
+    #[test]
+    fn paths_inside_renamed_directories_are_renamed_first() {
+        let root = Path::new(ROOT);
+        let mut filesystem = MemoryFilesystem::new(root);
+        filesystem.create_dir_all(&root.join("dir")).unwrap();
+        filesystem.add_file(&root.join("dir/file"), b"contents");
+        let renames = vec![
+            (root.join("dir"), root.join("renamed_dir")),
+            (root.join("dir/file"), root.join("dir/renamed_file")),
+        ];
+
+        assert_eq!(rename_all(&renames, &mut filesystem, false), Ok(2));
+
+        assert!(!filesystem.exists(&root.join("dir")));
+        assert_eq!(
+            filesystem.get_contents(&root.join("renamed_dir/renamed_file")),
+            Some(&b"contents"[..])
+        );
+    }
+}
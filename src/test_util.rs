@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crossbeam_channel::bounded;
+use tui::{backend::CrosstermBackend, layout::Rect, Terminal, TerminalOptions, Viewport};
+
+use crate::{
+    actions::{ActionData, ActionMapper, ActionResult, GLOBAL_ACTION_MAP},
+    compile_time_settings::DEFAULT_CONFIG,
+    directory_tree::FileTreeNode,
+    filesystem::Filesystem,
+    helper_types::{AppSettings, AppState, TrackedModifiable},
+    CustomTerminal,
+};
+
+/// Runs the actions like the key presses would, but on files in memory, so nothing on the disk is touched
+pub struct TestApp {
+    pub config: AppSettings,
+    pub terminal: CustomTerminal,
+    pub app_state: TrackedModifiable<AppState>,
+    pub filesystem: MemoryFilesystem,
+}
+
+impl TestApp {
+    /// An app in `root`, which should not exist on the disk, so that the checks that look at the real files find nothing
+    pub fn new(root: &Path) -> Self {
+        let config: AppSettings =
+            toml::from_str(DEFAULT_CONFIG).expect("The default config should be valid");
+        // the terminal is never drawn to, and a fixed size keeps it from asking stdout for its size
+        let terminal = Terminal::with_options(
+            CrosstermBackend::new(io::stdout()),
+            TerminalOptions {
+                viewport: Viewport::fixed(Rect::new(0, 0, 80, 24)),
+            },
+        )
+        .expect("The terminal should be created");
+        let (_, receiver) = bounded(1);
+        let app_state = AppState::with_interrupt_receiver(
+            FileTreeNode::new(root.to_owned()),
+            &config,
+            receiver,
+        );
+        TestApp {
+            config,
+            terminal,
+            app_state: TrackedModifiable::new(app_state),
+            filesystem: MemoryFilesystem::new(root),
+        }
+    }
+
+    pub fn select(&mut self, path: &Path) {
+        self.app_state.get_mut().selected_file = Some(FileTreeNode::new(path.to_owned()));
+    }
+
+    /// Runs the action the way the current mode would, falling back to the global ones
+    pub fn run_action(&mut self, name: &str, dir_items: &[PathBuf]) -> ActionResult {
+        let dir_items: Vec<_> = dir_items
+            .iter()
+            .map(|path| FileTreeNode::new(path.to_owned()))
+            .collect();
+        let name = name.to_owned();
+        let mode_actions = self.app_state.mode.get_action_map();
+        let global_actions = ActionMapper::StaticActionMap(&GLOBAL_ACTION_MAP);
+        let action = mode_actions
+            .find_action(&name)
+            .or_else(|| global_actions.find_action(&name))
+            .unwrap_or_else(|| panic!("There is no action called {}", name));
+        action(ActionData::new(
+            &self.config,
+            &mut self.terminal,
+            &mut self.app_state,
+            None,
+            &dir_items,
+            &mut self.filesystem,
+        ))
+    }
+
+    /// Types the text into the prompt and confirms it
+    pub fn submit(&mut self, text: &str) -> ActionResult {
+        self.app_state.get_mut().set_entered_text(text.to_owned());
+        self.run_action("select", &[])
+    }
+}
+
+/// Keeps the files in memory, so that the actions can be tested without touching the disk.
+/// Paths are taken as they are, without resolving symlinks or `..`
+#[derive(Default)]
+pub struct MemoryFilesystem {
+    // directories have no contents
+    entries: BTreeMap<PathBuf, Option<Vec<u8>>>,
+    skipped_jobs: Vec<String>,
+    // renaming to these fails, to test what happens after an error
+    failing_rename_paths: Vec<PathBuf>,
+}
+
+impl MemoryFilesystem {
+    /// A filesystem with only the (empty) directory `root`
+    pub fn new(root: &Path) -> Self {
+        let mut memory_filesystem = MemoryFilesystem::default();
+        memory_filesystem.entries.insert(root.to_owned(), None);
+        memory_filesystem
+    }
+
+    pub fn add_file(&mut self, path: &Path, contents: &[u8]) {
+        self.entries
+            .insert(path.to_owned(), Some(contents.to_owned()));
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    pub fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.entries.get(path), Some(None))
+    }
+
+    /// Makes renaming anything to `path` fail, like it would without the permissions
+    pub fn fail_renames_to(&mut self, path: &Path) {
+        self.failing_rename_paths.push(path.to_owned());
+    }
+
+    /// The contents of the file, or None if it is not there or is a directory
+    pub fn get_contents(&self, path: &Path) -> Option<&[u8]> {
+        self.entries.get(path)?.as_deref()
+    }
+
+    fn check_exists(&self, path: &Path) -> io::Result<()> {
+        if self.exists(path) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist", path.display()),
+            ))
+        }
+    }
+
+    fn check_parent_exists(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !self.is_dir(parent) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a directory", parent.display()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Like the real copying and moving, never overwrites anything
+    fn check_destination(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_exists(from)?;
+        if self.exists(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+        if to.starts_with(from) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can not put {} inside itself", from.display()),
+            ));
+        }
+        self.check_parent_exists(to)
+    }
+
+    /// The entry at `path` and everything inside it, with the paths relative to `path`
+    fn take_tree(&mut self, path: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+        let tree_paths: Vec<_> = self
+            .entries
+            .keys()
+            .filter(|entry_path| entry_path.starts_with(path))
+            .cloned()
+            .collect();
+        tree_paths
+            .into_iter()
+            .filter_map(|entry_path| {
+                let contents = self.entries.remove(&entry_path)?;
+                let relative_path = entry_path.strip_prefix(path).ok()?.to_owned();
+                Some((relative_path, contents))
+            })
+            .collect()
+    }
+
+    fn put_tree(&mut self, path: &Path, tree: Vec<(PathBuf, Option<Vec<u8>>)>) {
+        for (relative_path, contents) in tree {
+            self.entries.insert(path.join(relative_path), contents);
+        }
+    }
+}
+
+impl Filesystem for MemoryFilesystem {
+    fn delete(&mut self, path: &Path) -> io::Result<()> {
+        self.check_exists(path)?;
+        self.take_tree(path);
+        Ok(())
+    }
+
+    fn trash(&mut self, path: &Path) -> io::Result<()> {
+        self.delete(path)
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_exists(from)?;
+        self.check_parent_exists(to)?;
+        if self.failing_rename_paths.iter().any(|path| path == to) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("can not rename to {}", to.display()),
+            ));
+        }
+        if from == to {
+            return Ok(());
+        }
+        let tree = self.take_tree(from);
+        // like `fs::rename`, this replaces what was there
+        self.take_tree(to);
+        self.put_tree(to, tree);
+        Ok(())
+    }
+
+    fn copy(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_destination(from, to)?;
+        let tree = self.take_tree(from);
+        self.put_tree(from, tree.clone());
+        self.put_tree(to, tree);
+        Ok(())
+    }
+
+    fn move_path(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_destination(from, to)?;
+        let tree = self.take_tree(from);
+        self.put_tree(to, tree);
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        for ancestor in path.ancestors() {
+            match self.entries.get(ancestor) {
+                Some(None) => break,
+                Some(Some(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} is a file", ancestor.display()),
+                    ))
+                }
+                None => {}
+            }
+        }
+        for ancestor in path.ancestors() {
+            if ancestor.as_os_str().is_empty() || self.is_dir(ancestor) {
+                break;
+            }
+            self.entries.insert(ancestor.to_owned(), None);
+        }
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.check_parent_exists(path)?;
+        if self.is_dir(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a directory", path.display()),
+            ));
+        }
+        self.add_file(path, contents);
+        Ok(())
+    }
+
+    fn touch(&mut self, path: &Path) -> io::Result<()> {
+        // the modification times are not kept, so only a missing file makes a difference
+        if !self.exists(path) {
+            self.write_file(path, &[])?;
+        }
+        Ok(())
+    }
+
+    fn start_job(&mut self, description: &str) -> bool {
+        // archives can not be read from or written to memory
+        self.skipped_jobs.push(description.to_owned());
+        false
+    }
+
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+
+    fn get_log(&self) -> &[String] {
+        &self.skipped_jobs
+    }
+}
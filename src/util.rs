@@ -0,0 +1,338 @@
+use std::{
+    borrow::Cow,
+    env,
+    fmt::Write,
+    fs::{self, DirBuilder, Metadata, OpenOptions},
+    io::{self, ErrorKind, Write as _},
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Duration, Local,
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::helper_types::{NameTruncation, TimeFormat};
+
+const ABSOLUTE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+const FULL_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const ELLIPSIS: &str = "…";
+
+/// The bytes of the path as they are on unix, so that a name that is not valid UTF-8 still points to the same file
+/// when it is read back. Elsewhere, the invalid parts are replaced
+pub(crate) fn get_path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(path.as_os_str().as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        match path.to_string_lossy() {
+            Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+            Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+        }
+    }
+}
+
+/// A new directory in the temporary directory that only the current user can access, for the scratch files that are
+/// opened in the editor. The temporary directory is shared, so fixed names there could be taken over by other users.
+/// It is removed with everything in it when dropped
+pub(crate) struct PrivateTempDir {
+    path: PathBuf,
+}
+
+impl PrivateTempDir {
+    pub(crate) fn new(prefix: &str) -> io::Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos())
+            .unwrap_or_default();
+        let mut dir_builder = DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            dir_builder.mode(0o700);
+        }
+        // creating a directory fails if anything is there, so the one that is created can not be someone else's
+        for attempt in 0..100u32 {
+            let path = env::temp_dir().join(format!(
+                "{}-{}-{}",
+                prefix,
+                process::id(),
+                nanos.wrapping_add(attempt)
+            ));
+            match dir_builder.create(&path) {
+                Ok(()) => return Ok(PrivateTempDir { path }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            "Could not find a free name for a temporary directory",
+        ))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates a new file in the directory with the contents
+    pub(crate) fn create_file(&self, name: &str, contents: &[u8]) -> io::Result<PathBuf> {
+        let path = self.path.join(name);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?
+            .write_all(contents)?;
+        Ok(path)
+    }
+}
+
+impl Drop for PrivateTempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Formats a modification time, e.g. "2h ago" or "2022-04-01 12:30".
+/// With the relative format, times older than a week are still shown as dates
+pub(crate) fn format_mtime(mtime: SystemTime, time_format: TimeFormat) -> String {
+    let mtime: DateTime<Local> = mtime.into();
+    if let TimeFormat::Absolute = time_format {
+        return mtime.format(ABSOLUTE_TIME_FORMAT).to_string();
+    }
+
+    let now = Local::now();
+    let age = now.signed_duration_since(mtime);
+    // files from the future (e.g. because of clock skew) are treated as new
+    if age < Duration::minutes(1) {
+        String::from("just now")
+    } else if age < Duration::hours(1) {
+        format!("{} min ago", age.num_minutes())
+    } else if age < Duration::days(1) {
+        format!("{}h ago", age.num_hours())
+    } else if now.date_naive().pred_opt() == Some(mtime.date_naive()) {
+        String::from("yesterday")
+    } else if age < Duration::weeks(1) {
+        // count calendar days, so that e.g. 30 hours ago the day before yesterday is "2 days ago"
+        let days = (now.date_naive() - mtime.date_naive()).num_days();
+        format!("{} days ago", days)
+    } else {
+        mtime.format(ABSOLUTE_TIME_FORMAT).to_string()
+    }
+}
+
+/// Formats a time with the seconds, e.g. "2022-04-01 12:30:05"
+pub(crate) fn format_full_time(time: SystemTime) -> String {
+    let time: DateTime<Local> = time.into();
+    time.format(FULL_TIME_FORMAT).to_string()
+}
+
+/// The name of a backup copy of `file_name`, e.g. "notes.2022-04-01.txt" for "<STEM>.%Y-%m-%d<EXT>".
+/// <NAME> is the whole name, <STEM> is the name without the extension and <EXT> is the extension with its dot.
+/// The rest is filled in with the time like in `strftime`
+pub(crate) fn get_backup_name(
+    file_name: &str,
+    name_format: &str,
+    time: DateTime<Local>,
+) -> Result<String, String> {
+    // formatting a time with an invalid pattern panics
+    if StrftimeItems::new(name_format).any(|item| item == Item::Error) {
+        return Err(format!("'{}' is not a valid time format", name_format));
+    }
+    let mut formatted = String::new();
+    write!(formatted, "{}", time.format(name_format))
+        .map_err(|_| format!("'{}' can not be used with this time", name_format))?;
+
+    let path = Path::new(file_name);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    // the placeholders are replaced last, so that a "%" in the name is kept as it is
+    Ok(formatted
+        .replace("<NAME>", file_name)
+        .replace("<STEM>", &stem)
+        .replace("<EXT>", &extension))
+}
+
+/// Formats the permissions like `ls -l`, e.g. "drwxr-xr-x"
+#[cfg(unix)]
+pub(crate) fn format_permissions(metadata: &Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata.permissions().mode();
+    let file_type = if metadata.is_dir() {
+        'd'
+    } else if metadata.file_type().is_symlink() {
+        'l'
+    } else {
+        '-'
+    };
+    let permission_chars = ['r', 'w', 'x'];
+    let permissions: String = (0..9)
+        .map(|i| {
+            // from the owner's read bit down to the others' execute bit
+            if mode & (1 << (8 - i)) != 0 {
+                permission_chars[i % 3]
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("{}{}", file_type, permissions)
+}
+
+/// Windows only knows whether a file is read-only
+#[cfg(not(unix))]
+pub(crate) fn format_permissions(metadata: &Metadata) -> String {
+    let file_type = if metadata.is_dir() { 'd' } else { '-' };
+    let write_permission = if metadata.permissions().readonly() {
+        '-'
+    } else {
+        'w'
+    };
+    format!("{}r{}", file_type, write_permission)
+}
+
+/// Shortens a name to fit into `max_width` columns by replacing a part of it with an ellipsis.
+/// `kept_suffix` (e.g. the extension) stays visible unless it leaves no room for the rest of the name
+pub(crate) fn truncate_name(
+    name: &str,
+    kept_suffix: &str,
+    max_width: usize,
+    truncation: NameTruncation,
+) -> String {
+    if name.width() <= max_width {
+        return name.to_owned();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+    // at least one character of the name should be left next to the ellipsis and the suffix
+    let kept_suffix = match name.strip_suffix(kept_suffix) {
+        Some(_) if kept_suffix.width() + 2 <= max_width => kept_suffix,
+        _ => "",
+    };
+    let stem = &name[..name.len() - kept_suffix.len()];
+    let budget = max_width - ELLIPSIS.width() - kept_suffix.width();
+
+    match truncation {
+        NameTruncation::Start => {
+            format!("{}{}{}", ELLIPSIS, take_end(stem, budget), kept_suffix)
+        }
+        NameTruncation::Middle => {
+            let start_width = budget - budget / 2;
+            format!(
+                "{}{}{}{}",
+                take_start(stem, start_width),
+                ELLIPSIS,
+                take_end(stem, budget - start_width),
+                kept_suffix
+            )
+        }
+        NameTruncation::End => format!("{}{}{}", take_start(stem, budget), ELLIPSIS, kept_suffix),
+    }
+}
+
+/// The longest start of the text that fits into `max_width` columns
+fn take_start(text: &str, max_width: usize) -> &str {
+    let mut width = 0;
+    for (i, c) in text.char_indices() {
+        width += c.width().unwrap_or(0);
+        if width > max_width {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+/// The longest end of the text that fits into `max_width` columns
+fn take_end(text: &str, max_width: usize) -> &str {
+    let mut width = 0;
+    for (i, c) in text.char_indices().rev() {
+        width += c.width().unwrap_or(0);
+        if width > max_width {
+            return &text[i + c.len_utf8()..];
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use unicode_width::UnicodeWidthStr;
+
+    use crate::helper_types::NameTruncation;
+
+    use super::{get_path_bytes, truncate_name};
+
+    const TRUNCATIONS: [NameTruncation; 3] = [
+        NameTruncation::Start,
+        NameTruncation::Middle,
+        NameTruncation::End,
+    ];
+
+    #[test]
+    fn wide_names_fit_into_every_width() {
+        for name in ["日本語のファイル名.txt", "🎉🎉🎉 party.txt", "a日b🎉c"] {
+            for max_width in 0..=name.width() + 1 {
+                for truncation in TRUNCATIONS {
+                    let truncated_name = truncate_name(name, ".txt", max_width, truncation);
+                    assert!(
+                        truncated_name.width() <= max_width,
+                        "'{}' is wider than {} columns",
+                        truncated_name,
+                        max_width
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wide_characters_are_not_split() {
+        // an odd width leaves a column that a wide character does not fit into
+        assert_eq!(
+            truncate_name("日本語ファイル", "", 7, NameTruncation::End),
+            "日本語…"
+        );
+        assert_eq!(
+            truncate_name("🎉🎉🎉🎉", "", 6, NameTruncation::Start),
+            "…🎉🎉"
+        );
+        // the start gets the odd column, which only leaves room for one character on either side
+        assert_eq!(
+            truncate_name("日本語ファイル.txt", ".txt", 10, NameTruncation::Middle),
+            "日…ル.txt"
+        );
+    }
+
+    #[test]
+    fn short_names_are_kept() {
+        assert_eq!(
+            truncate_name("日本語", "", 6, NameTruncation::Middle),
+            "日本語"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn path_bytes_are_kept_as_they_are() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+        let bytes = b"/tmp/not \xff utf-8";
+        let path = Path::new(OsStr::from_bytes(bytes));
+        assert_eq!(get_path_bytes(path).as_ref(), &bytes[..]);
+    }
+}